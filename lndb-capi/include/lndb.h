@@ -0,0 +1,40 @@
+#ifndef LNDB_H
+#define LNDB_H
+
+/* C API for Lndb, built with `cargo build -p lndb-capi`. See src/lib.rs
+ * for ownership rules: buffers and strings returned by the library must be
+ * released with lndb_free and lndb_free_string. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct lndb_t lndb_t;
+typedef struct lndb_iter_t lndb_iter_t;
+
+lndb_t *lndb_open(const char *path, char **err);
+void lndb_close(lndb_t *db);
+
+int lndb_set(lndb_t *db, const uint8_t *key, size_t key_len,
+             const uint8_t *value, size_t value_len, char **err);
+int lndb_get(lndb_t *db, const uint8_t *key, size_t key_len,
+             uint8_t **value, size_t *value_len, char **err);
+int lndb_delete(lndb_t *db, const uint8_t *key, size_t key_len, char **err);
+
+lndb_iter_t *lndb_scan(lndb_t *db, const uint8_t *start, size_t start_len,
+                       const uint8_t *end, size_t end_len, char **err);
+int lndb_iter_next(lndb_iter_t *iter, uint8_t **key, size_t *key_len,
+                   uint8_t **value, size_t *value_len, char **err);
+void lndb_iter_free(lndb_iter_t *iter);
+
+void lndb_free(uint8_t *ptr, size_t len);
+void lndb_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
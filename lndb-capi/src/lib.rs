@@ -0,0 +1,330 @@
+// C bindings for embedding the engine, built as the liblndb_capi shared
+// library. See include/lndb.h for the C declarations.
+//
+// Ownership: handles returned by lndb_open and lndb_scan are owned by the
+// caller and released with lndb_close and lndb_iter_free. Keys, values and
+// error messages returned through out-parameters are allocated by the library
+// and must be released with lndb_free and lndb_free_string respectively. Input
+// buffers are only borrowed for the duration of the call.
+//
+// Functions returning int use 0 for success, 1 for "not found" or "end of
+// iteration" where applicable, and -1 for an error, in which case *err (if
+// non-null) is set to a message that must be freed with lndb_free_string. A
+// panic is caught at the boundary and reported as an error, rather than
+// unwinding into C.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ops::Bound;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+
+use lndb::error::{Error, Result};
+use lndb::storage::bitcask::BitCask;
+use lndb::storage::Engine;
+
+// Opaque to C as lndb_t.
+pub struct Database {
+    engine: BitCask,
+}
+
+// Opaque to C as lndb_iter_t. Scans are materialized when created, so the
+// iterator holds no borrow of the database and may outlive further writes.
+pub struct Iter {
+    items: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+}
+
+unsafe fn set_error(err: *mut *mut c_char, error: Error) {
+    if !err.is_null() {
+        let message = CString::new(error.to_string().replace('\0', " ")).unwrap_or_default();
+        *err = message.into_raw();
+    }
+}
+
+// Runs f, returning failed and setting *err if it fails or panics.
+unsafe fn call<T>(err: *mut *mut c_char, failed: T, f: impl FnOnce() -> Result<T>) -> T {
+    let result = catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(Error::Internal(format!("panic: {}", message)))
+    });
+    match result {
+        Ok(value) => value,
+        Err(error) => {
+            set_error(err, error);
+            failed
+        }
+    }
+}
+
+// Borrows len bytes at ptr. Null is only accepted for an empty buffer.
+unsafe fn slice<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8]> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(invalid("null pointer with nonzero length")),
+        (false, _) => Ok(std::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+unsafe fn out_buffer(data: Vec<u8>, ptr: *mut *mut u8, len: *mut usize) {
+    *len = data.len();
+    *ptr = Box::into_raw(data.into_boxed_slice()) as *mut u8;
+}
+
+unsafe fn bound(ptr: *const u8, len: usize) -> Result<Bound<Vec<u8>>> {
+    if ptr.is_null() {
+        Ok(Bound::Unbounded)
+    } else {
+        Ok(Bound::Included(slice(ptr, len)?.to_vec()))
+    }
+}
+
+fn invalid(what: &str) -> Error {
+    Error::Value(format!("invalid argument: {}", what))
+}
+
+/// Opens or creates a database at the NUL-terminated path. Returns null on error.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string; `err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_open(path: *const c_char, err: *mut *mut c_char) -> *mut Database {
+    call(err, std::ptr::null_mut(), || {
+        if path.is_null() {
+            return Err(invalid("path is null"));
+        }
+        let path = CStr::from_ptr(path).to_str().map_err(|_| invalid("path is not UTF-8"))?;
+        Ok(Box::into_raw(Box::new(Database { engine: BitCask::new(PathBuf::from(path))? })))
+    })
+}
+
+/// Closes a database. Null is ignored.
+///
+/// # Safety
+/// `db` must be null or a handle from lndb_open that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_close(db: *mut Database) {
+    if !db.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(db))));
+    }
+}
+
+/// # Safety
+/// `db` must be an open handle; key and value must point to at least the given
+/// number of bytes; `err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_set(
+    db: *mut Database,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+    err: *mut *mut c_char,
+) -> c_int {
+    call(err, -1, || {
+        let db = db.as_mut().ok_or_else(|| invalid("db is null"))?;
+        db.engine.set(slice(key, key_len)?, slice(value, value_len)?.to_vec())?;
+        Ok(0)
+    })
+}
+
+/// Looks up a key. Returns 0 and sets *value/*value_len if found, 1 if not.
+///
+/// # Safety
+/// `db` must be an open handle; `key` must point to key_len bytes; `value` and
+/// `value_len` must be valid for writes; `err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_get(
+    db: *mut Database,
+    key: *const u8,
+    key_len: usize,
+    value: *mut *mut u8,
+    value_len: *mut usize,
+    err: *mut *mut c_char,
+) -> c_int {
+    call(err, -1, || {
+        let db = db.as_mut().ok_or_else(|| invalid("db is null"))?;
+        if value.is_null() || value_len.is_null() {
+            return Err(invalid("value output is null"));
+        }
+        match db.engine.get(slice(key, key_len)?)? {
+            Some(data) => {
+                out_buffer(data, value, value_len);
+                Ok(0)
+            }
+            None => Ok(1),
+        }
+    })
+}
+
+/// # Safety
+/// `db` must be an open handle; `key` must point to key_len bytes; `err` must
+/// be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_delete(
+    db: *mut Database,
+    key: *const u8,
+    key_len: usize,
+    err: *mut *mut c_char,
+) -> c_int {
+    call(err, -1, || {
+        let db = db.as_mut().ok_or_else(|| invalid("db is null"))?;
+        db.engine.delete(slice(key, key_len)?)?;
+        Ok(0)
+    })
+}
+
+/// Scans keys in [start, end). A null start or end leaves that side unbounded.
+/// Returns null on error.
+///
+/// # Safety
+/// `db` must be an open handle; non-null start/end must point to the given
+/// number of bytes; `err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_scan(
+    db: *mut Database,
+    start: *const u8,
+    start_len: usize,
+    end: *const u8,
+    end_len: usize,
+    err: *mut *mut c_char,
+) -> *mut Iter {
+    call(err, std::ptr::null_mut(), || {
+        let db = db.as_mut().ok_or_else(|| invalid("db is null"))?;
+        let end = match bound(end, end_len)? {
+            Bound::Included(end) => Bound::Excluded(end),
+            end => end,
+        };
+        let items = db.engine.scan((bound(start, start_len)?, end)).collect::<Result<Vec<_>>>()?;
+        Ok(Box::into_raw(Box::new(Iter { items: items.into_iter() })))
+    })
+}
+
+/// Advances the iterator. Returns 0 and sets the key and value outputs, or 1
+/// when the iteration is done.
+///
+/// # Safety
+/// `iter` must be a live handle from lndb_scan; all outputs must be valid for
+/// writes; `err` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_iter_next(
+    iter: *mut Iter,
+    key: *mut *mut u8,
+    key_len: *mut usize,
+    value: *mut *mut u8,
+    value_len: *mut usize,
+    err: *mut *mut c_char,
+) -> c_int {
+    call(err, -1, || {
+        let iter = iter.as_mut().ok_or_else(|| invalid("iter is null"))?;
+        if key.is_null() || key_len.is_null() || value.is_null() || value_len.is_null() {
+            return Err(invalid("output is null"));
+        }
+        match iter.items.next() {
+            Some((k, v)) => {
+                out_buffer(k, key, key_len);
+                out_buffer(v, value, value_len);
+                Ok(0)
+            }
+            None => Ok(1),
+        }
+    })
+}
+
+/// # Safety
+/// `iter` must be null or a handle from lndb_scan that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_iter_free(iter: *mut Iter) {
+    if !iter.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(iter))));
+    }
+}
+
+/// Frees a key or value buffer returned by the library.
+///
+/// # Safety
+/// `ptr` and `len` must come from the same library-returned buffer, freed once.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_free(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)))));
+    }
+}
+
+/// Frees an error message returned by the library.
+///
+/// # Safety
+/// `s` must be null or an error string returned by the library, freed once.
+#[no_mangle]
+pub unsafe extern "C" fn lndb_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(CString::from_raw(s))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    unsafe fn take(ptr: *mut u8, len: usize) -> Vec<u8> {
+        let data = slice(ptr, len).expect("buffer").to_vec();
+        lndb_free(ptr, len);
+        data
+    }
+
+    unsafe fn take_error(err: &mut *mut c_char) -> String {
+        assert!(!err.is_null());
+        let message = CStr::from_ptr(*err).to_str().expect("utf-8").to_string();
+        lndb_free_string(*err);
+        *err = std::ptr::null_mut();
+        message
+    }
+
+    #[test]
+    fn test_capi() {
+        let dir = TempDir::new("capi").expect("Failed to create temporary directory");
+        let path = CString::new(dir.path().join("db").to_string_lossy().as_bytes()).expect("path");
+        let mut err: *mut c_char = std::ptr::null_mut();
+        unsafe {
+            let db = lndb_open(path.as_ptr(), &mut err);
+            assert!(!db.is_null());
+
+            assert_eq!(0, lndb_set(db, b"a".as_ptr(), 1, b"1".as_ptr(), 1, &mut err));
+            assert_eq!(0, lndb_set(db, b"b".as_ptr(), 1, b"22".as_ptr(), 2, &mut err));
+            assert_eq!(0, lndb_set(db, b"c".as_ptr(), 1, std::ptr::null(), 0, &mut err));
+            assert_eq!(0, lndb_delete(db, b"a".as_ptr(), 1, &mut err));
+
+            let (mut value, mut value_len) = (std::ptr::null_mut(), 0);
+            assert_eq!(1, lndb_get(db, b"a".as_ptr(), 1, &mut value, &mut value_len, &mut err));
+            assert_eq!(0, lndb_get(db, b"b".as_ptr(), 1, &mut value, &mut value_len, &mut err));
+            assert_eq!(b"22".to_vec(), take(value, value_len));
+
+            let iter = lndb_scan(db, std::ptr::null(), 0, b"c".as_ptr(), 1, &mut err);
+            let (mut key, mut key_len) = (std::ptr::null_mut(), 0);
+            assert_eq!(0, lndb_iter_next(iter, &mut key, &mut key_len, &mut value, &mut value_len, &mut err));
+            assert_eq!((b"b".to_vec(), b"22".to_vec()), (take(key, key_len), take(value, value_len)));
+            assert_eq!(1, lndb_iter_next(iter, &mut key, &mut key_len, &mut value, &mut value_len, &mut err));
+            lndb_iter_free(iter);
+
+            assert_eq!(-1, lndb_set(std::ptr::null_mut(), b"a".as_ptr(), 1, b"1".as_ptr(), 1, &mut err));
+            assert_eq!("invalid argument: db is null", take_error(&mut err));
+
+            // A null buffer is only an empty one if its length is zero.
+            assert_eq!(-1, lndb_set(db, std::ptr::null(), 1, b"1".as_ptr(), 1, &mut err));
+            assert_eq!("invalid argument: null pointer with nonzero length", take_error(&mut err));
+            assert_eq!(-1, lndb_get(db, std::ptr::null(), 3, &mut value, &mut value_len, &mut err));
+            assert_eq!("invalid argument: null pointer with nonzero length", take_error(&mut err));
+            assert_eq!(1, lndb_get(db, std::ptr::null(), 0, &mut value, &mut value_len, &mut err));
+            assert!(err.is_null());
+
+            // Panics are reported as errors instead of unwinding into C.
+            assert_eq!(-1, call(&mut err, -1, || -> Result<c_int> { panic!("boom") }));
+            assert_eq!("panic: boom", take_error(&mut err));
+
+            lndb_close(db);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use alloc::vec::Vec;
+
+// A log entry is laid out as:
+//
+//   key_len: u32 (big-endian)
+//   value_len: i32 (big-endian), -1 for a tombstone
+//   key: [u8; key_len]
+//   value: [u8; value_len]
+pub const HEADER_SIZE: u64 = 8;
+
+// Since format version 2, segment files start with MAGIC followed by the
+// version as a big-endian u32, and every entry is prefixed with the CRC32 of
+// the rest of the entry as a big-endian u32. Version 1 files have no file
+// header. One can't be mistaken for MAGIC, which would be the length of a key
+// over 1 GiB.
+pub const MAGIC: [u8; 4] = *b"LNDB";
+pub const VERSION: u32 = 3;
+pub const FILE_HEADER_SIZE: u64 = 8;
+pub const CHECKSUM_SIZE: u64 = 4;
+
+// Version 3 adds record alignment: the file header is followed by the
+// alignment as a big-endian u32 and zero padding up to the alignment, and
+// every record (an entry, or a batch marker with its entries) starts at a
+// multiple of the alignment, zero-padded up to the next one. Segments without
+// alignment are still written as version 2.
+pub const ALIGNED_FILE_HEADER_SIZE: u64 = FILE_HEADER_SIZE + 4;
+
+// In version 2, the entries of a write batch are preceded by a batch marker:
+// an entry whose value length is BATCH_MARKER and whose 8-byte key holds the
+// total size of the batch's entries as a big-endian u64, so that a batch that
+// was only partly written can be dropped as a whole.
+pub const BATCH_MARKER: i32 = -2;
+pub const BATCH_MARKER_SIZE: u64 = CHECKSUM_SIZE + HEADER_SIZE + 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub key_len: u32,
+    // None for a tombstone.
+    pub value_len: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    KeyTooLarge(usize),
+    ValueTooLarge(usize),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::KeyTooLarge(len) => write!(f, "key too large ({} bytes)", len),
+            Error::ValueTooLarge(len) => write!(f, "value too large ({} bytes)", len),
+        }
+    }
+}
+
+impl Header {
+    pub fn new(key: &[u8], value: Option<&[u8]>) -> Result<Self, Error> {
+        let key_len = u32::try_from(key.len()).map_err(|_| Error::KeyTooLarge(key.len()))?;
+        let value_len = match value {
+            Some(v) if v.len() > i32::MAX as usize => return Err(Error::ValueTooLarge(v.len())),
+            Some(v) => Some(v.len() as u32),
+            None => None,
+        };
+        Ok(Self { key_len, value_len })
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_SIZE as usize] {
+        let value_len = self.value_len.map_or(-1, |len| len as i32);
+        let mut buf = [0; HEADER_SIZE as usize];
+        buf[..4].copy_from_slice(&self.key_len.to_be_bytes());
+        buf[4..].copy_from_slice(&value_len.to_be_bytes());
+        buf
+    }
+
+    pub fn decode(buf: [u8; HEADER_SIZE as usize]) -> Self {
+        let [k0, k1, k2, k3, v0, v1, v2, v3] = buf;
+        let key_len = u32::from_be_bytes([k0, k1, k2, k3]);
+        let value_len = match i32::from_be_bytes([v0, v1, v2, v3]) {
+            len if len >= 0 => Some(len as u32),
+            _ => None,
+        };
+        Self { key_len, value_len }
+    }
+
+    // Total size of the entry including the header.
+    pub fn entry_len(&self) -> u64 {
+        HEADER_SIZE + self.key_len as u64 + self.value_len.unwrap_or(0) as u64
+    }
+
+    // Offset of the value relative to the start of the entry.
+    pub fn value_offset(&self) -> u64 {
+        HEADER_SIZE + self.key_len as u64
+    }
+
+    pub fn is_batch_marker(buf: [u8; HEADER_SIZE as usize]) -> bool {
+        let [_, _, _, _, v0, v1, v2, v3] = buf;
+        i32::from_be_bytes([v0, v1, v2, v3]) == BATCH_MARKER
+    }
+}
+
+// Serializes a full entry, value None writing a tombstone.
+pub fn encode(key: &[u8], value: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+    let header = Header::new(key, value)?;
+    let mut buf = Vec::with_capacity(header.entry_len() as usize);
+    buf.extend_from_slice(&header.encode());
+    buf.extend_from_slice(key);
+    if let Some(value) = value {
+        buf.extend_from_slice(value);
+    }
+    Ok(buf)
+}
+
+// Like encode, prefixed with the entry's checksum.
+pub fn encode_checked(key: &[u8], value: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+    Ok(checksummed(&encode(key, value)?))
+}
+
+// Encodes the batch marker for batch entries of batch_len bytes in total.
+pub fn encode_batch_marker(batch_len: u64) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(BATCH_MARKER_SIZE as usize);
+    entry.extend_from_slice(&8u32.to_be_bytes());
+    entry.extend_from_slice(&BATCH_MARKER.to_be_bytes());
+    entry.extend_from_slice(&batch_len.to_be_bytes());
+    checksummed(&entry)
+}
+
+fn checksummed(entry: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(CHECKSUM_SIZE as usize + entry.len());
+    buf.extend_from_slice(&crc32(entry).to_be_bytes());
+    buf.extend_from_slice(entry);
+    buf
+}
+
+// Checks the checksum of an entry encoded by encode_checked.
+pub fn verify_checksum(buf: &[u8]) -> bool {
+    match buf.split_first_chunk::<4>() {
+        Some((checksum, entry)) => u32::from_be_bytes(*checksum) == crc32(entry),
+        None => false,
+    }
+}
+
+// Encodes the file header of a new segment, as version 3 with its padding if
+// alignment is over 1, and as version 2 otherwise.
+pub fn encode_file_header(alignment: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(FILE_HEADER_SIZE as usize);
+    buf.extend_from_slice(&MAGIC);
+    if alignment <= 1 {
+        buf.extend_from_slice(&2u32.to_be_bytes());
+        return buf;
+    }
+    buf.extend_from_slice(&3u32.to_be_bytes());
+    buf.extend_from_slice(&alignment.to_be_bytes());
+    buf.resize(align(ALIGNED_FILE_HEADER_SIZE, alignment as u64) as usize, 0);
+    buf
+}
+
+// Rounds pos up to a multiple of alignment, which must be a power of two or 0.
+pub fn align(pos: u64, alignment: u64) -> u64 {
+    match alignment {
+        0 | 1 => pos,
+        _ => (pos + alignment - 1) & !(alignment - 1),
+    }
+}
+
+// Returns the format version, or None if buf isn't a file header.
+pub fn decode_file_header(buf: [u8; FILE_HEADER_SIZE as usize]) -> Option<u32> {
+    let [m0, m1, m2, m3, v0, v1, v2, v3] = buf;
+    ([m0, m1, m2, m3] == MAGIC).then(|| u32::from_be_bytes([v0, v1, v2, v3]))
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// CRC-32 (IEEE), as used by zlib and Ethernet.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc: u32, b| CRC_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() -> Result<(), Error> {
+        let buf = encode(b"key", Some(b"value"))?;
+        assert_eq!(b"\x00\x00\x00\x03\x00\x00\x00\x05keyvalue".as_slice(), buf.as_slice());
+        let header = Header::decode(buf[..8].try_into().map_err(|_| Error::KeyTooLarge(0))?);
+        assert_eq!(Header { key_len: 3, value_len: Some(5) }, header);
+        assert_eq!((16, 11), (header.entry_len(), header.value_offset()));
+
+        let buf = encode(b"key", None)?;
+        assert_eq!(b"\x00\x00\x00\x03\xff\xff\xff\xffkey".as_slice(), buf.as_slice());
+        let header = Header::decode([0, 0, 0, 3, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!((None, 11), (header.value_len, header.entry_len()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum() -> Result<(), Error> {
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+
+        let mut buf = encode_checked(b"key", Some(b"value"))?;
+        assert_eq!(&encode(b"key", Some(b"value"))?, &buf[4..]);
+        assert!(verify_checksum(&buf));
+        buf[12] ^= 0x01;
+        assert!(!verify_checksum(&buf));
+
+        let marker = encode_batch_marker(300);
+        assert_eq!(BATCH_MARKER_SIZE as usize, marker.len());
+        assert!(verify_checksum(&marker));
+        assert!(Header::is_batch_marker(marker[4..12].try_into().map_err(|_| Error::KeyTooLarge(0))?));
+        assert_eq!(&300u64.to_be_bytes(), &marker[12..]);
+
+        assert_eq!(&b"LNDB\0\0\0\x02"[..], &encode_file_header(0)[..]);
+        let header = encode_file_header(16);
+        assert_eq!(16, header.len());
+        assert_eq!(Some(3), decode_file_header(header[..8].try_into().map_err(|_| Error::KeyTooLarge(0))?));
+        assert_eq!(&[0, 0, 0, 16, 0, 0, 0, 0], &header[8..]);
+        assert_eq!((0, 16, 16, 32), (align(0, 16), align(1, 16), align(16, 16), align(17, 16)));
+        assert_eq!(17, align(17, 1));
+        assert_eq!(None, decode_file_header([0, 0, 0, 3, 0, 0, 0, 5]));
+        Ok(())
+    }
+}
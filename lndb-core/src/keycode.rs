@@ -0,0 +1,264 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// An order-preserving key encoding: encoded keys compare bytewise the same way
+// as the values they encode, so typed keys like (table, column value, row ID)
+// can be stored in and range-scanned from the byte-oriented engines.
+//
+// - Booleans are 0x00 or 0x01.
+// - Unsigned integers are big-endian. Signed integers are big-endian with the
+//   sign bit flipped, so negative numbers sort first.
+// - Strings and byte slices have 0x00 escaped as 0x00 0xff and are terminated
+//   by 0x00 0x00, so a value sorts before its extensions.
+// - Tuples concatenate the encodings of their elements. Every encoding above
+//   is self-delimiting, so tuples compare element by element.
+//
+// Keys carry no type information and must be decoded as the type they were
+// encoded from.
+pub trait KeyEncode {
+    fn encode_into(&self, out: &mut Vec<u8>);
+}
+
+pub trait KeyDecode: Sized {
+    // Decodes a value from the start of input and advances past it.
+    fn decode_from(input: &mut &[u8]) -> Result<Self, Error>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // The key ends before a value of the given size.
+    Truncated(usize),
+    // The key has bytes left after the decoded value.
+    TrailingBytes(usize),
+    // The key holds a value that isn't valid for the type.
+    Invalid(String),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Truncated(len) => write!(f, "key ends before a {}-byte value", len),
+            Error::TrailingBytes(len) => write!(f, "key has {} unexpected trailing bytes", len),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+pub fn encode<K: KeyEncode + ?Sized>(key: &K) -> Vec<u8> {
+    let mut out = Vec::new();
+    key.encode_into(&mut out);
+    out
+}
+
+pub fn decode<K: KeyDecode>(mut input: &[u8]) -> Result<K, Error> {
+    let key = K::decode_from(&mut input)?;
+    if !input.is_empty() {
+        return Err(Error::TrailingBytes(input.len()));
+    }
+    Ok(key)
+}
+
+fn take<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], Error> {
+    let Some((bytes, rest)) = input.split_first_chunk::<N>() else {
+        return Err(Error::Truncated(N));
+    };
+    *input = rest;
+    Ok(*bytes)
+}
+
+impl KeyEncode for bool {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl KeyDecode for bool {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, Error> {
+        match take::<1>(input)? {
+            [0x00] => Ok(false),
+            [0x01] => Ok(true),
+            [b] => Err(Error::Invalid(format!("invalid boolean {:#04x} in key", b))),
+        }
+    }
+}
+
+macro_rules! unsigned {
+    ($($t:ty),*) => {$(
+        impl KeyEncode for $t {
+            fn encode_into(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+        }
+
+        impl KeyDecode for $t {
+            fn decode_from(input: &mut &[u8]) -> Result<Self, Error> {
+                Ok(Self::from_be_bytes(take(input)?))
+            }
+        }
+    )*};
+}
+
+macro_rules! signed {
+    ($($t:ty => $u:ty),*) => {$(
+        impl KeyEncode for $t {
+            fn encode_into(&self, out: &mut Vec<u8>) {
+                (*self as $u ^ 1 << (<$u>::BITS - 1)).encode_into(out);
+            }
+        }
+
+        impl KeyDecode for $t {
+            fn decode_from(input: &mut &[u8]) -> Result<Self, Error> {
+                Ok((<$u>::decode_from(input)? ^ 1 << (<$u>::BITS - 1)) as $t)
+            }
+        }
+    )*};
+}
+
+unsigned!(u8, u16, u32, u64);
+signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64);
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for b in bytes {
+        match b {
+            0x00 => out.extend_from_slice(&[0x00, 0xff]),
+            b => out.push(*b),
+        }
+    }
+    out.extend_from_slice(&[0x00, 0x00]);
+}
+
+fn decode_bytes(input: &mut &[u8]) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    loop {
+        match take::<1>(input)? {
+            [0x00] => match take::<1>(input)? {
+                [0x00] => return Ok(bytes),
+                [0xff] => bytes.push(0x00),
+                [b] => return Err(Error::Invalid(format!("invalid escape 0x00 {:#04x} in key", b))),
+            },
+            [b] => bytes.push(b),
+        }
+    }
+}
+
+impl KeyEncode for [u8] {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out)
+    }
+}
+
+impl KeyEncode for Vec<u8> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out)
+    }
+}
+
+impl KeyDecode for Vec<u8> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, Error> {
+        decode_bytes(input)
+    }
+}
+
+impl KeyEncode for str {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out)
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out)
+    }
+}
+
+impl KeyDecode for String {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, Error> {
+        String::from_utf8(decode_bytes(input)?).map_err(|err| Error::Invalid(format!("invalid string in key: {}", err)))
+    }
+}
+
+impl<K: KeyEncode + ?Sized> KeyEncode for &K {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        (**self).encode_into(out)
+    }
+}
+
+macro_rules! tuple {
+    ($($name:ident),+) => {
+        impl<$($name: KeyEncode),+> KeyEncode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_into(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_into(out);)+
+            }
+        }
+
+        impl<$($name: KeyDecode),+> KeyDecode for ($($name,)+) {
+            fn decode_from(input: &mut &[u8]) -> Result<Self, Error> {
+                Ok(($($name::decode_from(input)?,)+))
+            }
+        }
+    };
+}
+
+tuple!(A);
+tuple!(A, B);
+tuple!(A, B, C);
+tuple!(A, B, C, D);
+tuple!(A, B, C, D, E);
+tuple!(A, B, C, D, E, F);
+tuple!(A, B, C, D, E, F, G);
+tuple!(A, B, C, D, E, F, G, H);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_encode() -> Result<(), Error> {
+        assert_eq!(vec![0x01], encode(&true));
+        assert_eq!(vec![0x00, 0x00, 0x01, 0x02], encode(&0x0102u32));
+        assert_eq!(vec![0x7f, 0xff], encode(&-1i16));
+        assert_eq!(vec![0x80], encode(&0i8));
+        assert_eq!(vec![b'a', 0x00, 0xff, b'b', 0x00, 0x00], encode(&b"a\0b"[..]));
+        assert_eq!(
+            vec![0x01, b'x', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07],
+            encode(&(true, "x", 7u64))
+        );
+
+        let key = (-5i64, "orders".to_string(), vec![0x00, 0xff], false);
+        assert_eq!(key, decode::<(i64, String, Vec<u8>, bool)>(&encode(&key))?);
+        assert_eq!(Err(Error::Truncated(4)), decode::<u32>(&[0x00, 0x01]));
+        assert_eq!(Err(Error::TrailingBytes(1)), decode::<u8>(&[0x00, 0x01]));
+        assert!(decode::<bool>(&[0x02]).is_err());
+        assert!(decode::<Vec<u8>>(&[0x00, 0x01]).is_err());
+        assert!(decode::<String>(&[0xff, 0x00, 0x00]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_order() -> Result<(), Error> {
+        let mut keys = vec![
+            (i32::MIN, "".to_string(), vec![]),
+            (-1, "".to_string(), vec![]),
+            (0, "".to_string(), vec![]),
+            (0, "".to_string(), vec![0x00]),
+            (0, "".to_string(), vec![0x00, 0x00]),
+            (0, "".to_string(), vec![0x01]),
+            (0, "a".to_string(), vec![]),
+            (0, "a\0".to_string(), vec![]),
+            (0, "ab".to_string(), vec![]),
+            (0, "b".to_string(), vec![]),
+            (1, "".to_string(), vec![]),
+            (i32::MAX, "".to_string(), vec![0xff]),
+        ];
+        let mut encoded: Vec<_> = keys.iter().map(encode).collect();
+        keys.sort();
+        encoded.sort();
+        assert_eq!(keys, encoded.iter().map(|key| decode(key)).collect::<Result<Vec<_>, _>>()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,9 @@
+// On-disk format definitions and the key encoding shared by the engine and by
+// tooling that parses Lndb files. Only depends on core and alloc so it can be used without std.
+#![no_std]
+#![deny(clippy::unwrap_used)]
+
+extern crate alloc;
+
+pub mod entry;
+pub mod keycode;
@@ -0,0 +1,272 @@
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use lndb::client::Client;
+use lndb::error::{Error, Result};
+use lndb::storage::bitcask::BitCask;
+use lndb::storage::usage::{self, Grouping, PrefixUsage};
+use lndb::storage::Engine;
+
+const USAGE: &str = "usage: lndb-cli <path> | lndb-cli --connect <host:port> | lndb-cli convert <path>";
+
+const HELP: &str = "\
+get <key>           print the value of key
+set <key> <value>   set key to value
+del <key>           delete key
+scan [prefix]       print the keys and values starting with prefix
+status              print the store's status
+stats --by-prefix [--delimiter <c> | --depth <n>]
+                    print the keys and bytes per top-level prefix, largest
+                    first, grouped up to the delimiter (default /) or by the
+                    first n bytes (local stores only)
+compact             compact the store (local stores only)
+help                print this help
+quit                exit";
+
+// A store opened locally, or a remote server.
+enum Target {
+    Local(Box<BitCask>),
+    Remote(Client),
+}
+
+impl Target {
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self {
+            Target::Local(db) => db.get(key),
+            Target::Remote(client) => client.get(key),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        match self {
+            Target::Local(db) => db.set(key, value),
+            Target::Remote(client) => client.set(key, value),
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        match self {
+            Target::Local(db) => db.delete(key),
+            Target::Remote(client) => client.delete(key),
+        }
+    }
+
+    fn scan_prefix(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        match self {
+            Target::Local(db) => db.scan_prefix(prefix).collect(),
+            Target::Remote(client) => client.scan_prefix(prefix),
+        }
+    }
+
+    fn status(&mut self) -> Result<lndb::storage::Status> {
+        match self {
+            Target::Local(db) => db.status(),
+            Target::Remote(client) => client.status(),
+        }
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        match self {
+            Target::Local(db) => db.compact(),
+            Target::Remote(_) => Err(Error::Value("compact is only available on a local store".to_string())),
+        }
+    }
+
+    fn prefix_usage(&mut self, grouping: Grouping) -> Result<Vec<PrefixUsage>> {
+        match self {
+            Target::Local(db) => usage::prefix_usage(db.as_mut(), grouping),
+            Target::Remote(_) => Err(Error::Value("stats is only available on a local store".to_string())),
+        }
+    }
+}
+
+// Keys and values are read as UTF-8 and printed with non-printable bytes
+// escaped.
+fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+// Executes one command line. Returns false if the REPL should exit.
+fn execute(target: &mut Target, line: &str, out: &mut impl Write) -> Result<bool> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(true);
+    };
+    let args: Vec<&str> = words.collect();
+    match (command, args.as_slice()) {
+        ("get", [key]) => match target.get(key.as_bytes())? {
+            Some(value) => writeln!(out, "{}", escape(&value))?,
+            None => writeln!(out, "(not found)")?,
+        },
+        ("set", [key, value]) => target.set(key.as_bytes(), value.as_bytes().to_vec())?,
+        ("del", [key]) => target.delete(key.as_bytes())?,
+        ("scan", [] | [_]) => {
+            let prefix = args.first().map_or(&[][..], |prefix| prefix.as_bytes());
+            for (key, value) in target.scan_prefix(prefix)? {
+                writeln!(out, "{} = {}", escape(&key), escape(&value))?;
+            }
+        }
+        ("status", []) => {
+            let status = target.status()?;
+            let fields = [
+                ("name", status.name.clone()),
+                ("keys", status.keys.to_string()),
+                ("size", status.size.to_string()),
+                ("total disk size", status.total_disk_size.to_string()),
+                ("live disk size", status.live_disk_size.to_string()),
+                ("garbage disk size", status.garbage_disk_size.to_string()),
+                ("write amplification", format!("{:.2}", status.write_amplification())),
+            ];
+            for (name, value) in fields {
+                writeln!(out, "{:<21}{}", format!("{}:", name), value)?;
+            }
+        }
+        ("stats", ["--by-prefix", grouping @ ..]) => {
+            let grouping = match grouping {
+                [] => Grouping::Delimiter(b'/'),
+                ["--delimiter", delimiter] if delimiter.len() == 1 => Grouping::Delimiter(delimiter.as_bytes()[0]),
+                ["--depth", depth] => match depth.parse() {
+                    Ok(depth) => Grouping::Depth(depth),
+                    Err(_) => return Err(Error::Value(format!("invalid depth {}", depth))),
+                },
+                _ => {
+                    writeln!(out, "invalid command, try help")?;
+                    return Ok(true);
+                }
+            };
+            for usage in target.prefix_usage(grouping)? {
+                writeln!(
+                    out,
+                    "{}: {} keys, {} bytes ({} in keys, {} in values)",
+                    escape(&usage.prefix),
+                    usage.keys,
+                    usage.bytes(),
+                    usage.key_bytes,
+                    usage.value_bytes
+                )?;
+            }
+        }
+        ("compact", []) => target.compact()?,
+        ("help", []) => writeln!(out, "{}", HELP)?,
+        ("quit" | "exit", []) => return Ok(false),
+        _ => writeln!(out, "invalid command, try help")?,
+    }
+    Ok(true)
+}
+
+fn open(args: &[String]) -> Result<Target> {
+    match args {
+        [flag, addr] if flag == "--connect" => Ok(Target::Remote(Client::connect(addr.as_str())?)),
+        [path] if !path.starts_with('-') => Ok(Target::Local(Box::new(BitCask::new(PathBuf::from(path))?))),
+        _ => Err(Error::Value(USAGE.to_string())),
+    }
+}
+
+// Rewrites the store at path in the current format version, offline.
+fn convert(path: &str, out: &mut impl Write) -> Result<()> {
+    let mut db = BitCask::new(PathBuf::from(path))?;
+    match db.convert()? {
+        Some(version) => writeln!(out, "converted {} from format version {}", path, version)?,
+        None => writeln!(out, "{} is already in the current format", path)?,
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [command, path] = args.as_slice() {
+        if command == "convert" {
+            if let Err(err) = convert(path, &mut std::io::stdout()) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+    let mut target = match open(&args) {
+        Ok(target) => target,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    loop {
+        print!("lndb> ");
+        let _ = stdout.flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        }
+        match execute(&mut target, &line, &mut stdout) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => eprintln!("error: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_repl() -> Result<()> {
+        let dir = TempDir::new("cli").expect("Failed to create temporary directory");
+        let mut target = open(&[dir.path().join("log").display().to_string()])?;
+        let mut run = |line: &str| -> Result<String> {
+            let mut out = Vec::new();
+            execute(&mut target, line, &mut out)?;
+            Ok(String::from_utf8_lossy(&out).into_owned())
+        };
+
+        run("set user:1 alice")?;
+        run("set user:2 bob")?;
+        run("set other x")?;
+        assert_eq!("alice\n", run("get user:1")?);
+        run("del user:1")?;
+        assert_eq!("(not found)\n", run("get user:1")?);
+        assert_eq!("user:2 = bob\n", run("scan user:")?);
+        assert_eq!(2, run("scan")?.lines().count());
+        run("set user:3 carol")?;
+        assert_eq!(
+            "user:: 2 keys, 20 bytes (12 in keys, 8 in values)\nother: 1 keys, 6 bytes (5 in keys, 1 in values)\n",
+            run("stats --by-prefix --delimiter :")?
+        );
+        assert_eq!(
+            "us: 2 keys, 20 bytes (12 in keys, 8 in values)\not: 1 keys, 6 bytes (5 in keys, 1 in values)\n",
+            run("stats --by-prefix --depth 2")?
+        );
+        assert_eq!(3, run("stats --by-prefix")?.lines().count());
+        assert!(run("stats --by-prefix --depth x").is_err());
+        assert_eq!("invalid command, try help\n", run("stats --by-prefix --delimiter ::")?);
+        run("del user:3")?;
+        run("compact")?;
+        assert!(run("status")?.contains("keys:                2\n"));
+        assert_eq!("invalid command, try help\n", run("set onlykey")?);
+        assert_eq!("", run("   ")?);
+
+        let mut out = Vec::new();
+        assert!(!execute(&mut target, "quit", &mut out)?);
+        assert!(open(&["--connect".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert() -> Result<()> {
+        let dir = TempDir::new("cli").expect("Failed to create temporary directory");
+        let path = dir.path().join("log").display().to_string();
+        let mut out = Vec::new();
+        convert(&path, &mut out)?;
+        assert_eq!(format!("{} is already in the current format\n", path), String::from_utf8_lossy(&out));
+        Ok(())
+    }
+}
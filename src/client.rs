@@ -0,0 +1,166 @@
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::{Bound, RangeBounds};
+
+use crate::error::{Error, Result};
+use crate::server::{read_frame, write_frame, Request, Response};
+use crate::storage::Status;
+
+// A client for a remote Server, see server::Request for the semantics of the
+// calls. Errors returned by the server come back as the same Error.
+pub struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self { stream })
+    }
+
+    fn call(&mut self, request: Request) -> Result<Response> {
+        write_frame(&mut self.stream, &request)?;
+        match read_frame::<Result<Response>>(&mut self.stream)? {
+            Some(response) => response,
+            None => Err(Error::Internal("server closed the connection".to_string())),
+        }
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.call(Request::Get(key.to_vec()))? {
+            Response::Get(value) => Ok(value),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        match self.call(Request::Set(key.to_vec(), value))? {
+            Response::Set => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        match self.call(Request::Delete(key.to_vec()))? {
+            Response::Delete => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    // Fetches the range a page at a time, see Request::Scan.
+    pub fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let (mut start, end) = (range.start_bound().cloned(), range.end_bound().cloned());
+        let mut items = Vec::new();
+        loop {
+            match self.call(Request::Scan(start, end.clone()))? {
+                Response::Scan(page, next) => {
+                    items.extend(page);
+                    match next {
+                        Some(key) => start = Bound::Excluded(key),
+                        None => return Ok(items),
+                    }
+                }
+                response => return Err(unexpected(response)),
+            }
+        }
+    }
+
+    pub fn scan_prefix(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let range: (Bound<Vec<u8>>, Bound<Vec<u8>>) = crate::storage::prefix_range(prefix);
+        self.scan(range)
+    }
+
+    pub fn status(&mut self) -> Result<Status> {
+        match self.call(Request::Status)? {
+            Response::Status(status) => Ok(status),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn begin(&mut self) -> Result<()> {
+        match self.call(Request::Begin)? {
+            Response::Begin => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        match self.call(Request::Commit)? {
+            Response::Commit => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn rollback(&mut self) -> Result<()> {
+        match self.call(Request::Rollback)? {
+            Response::Rollback => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+fn unexpected(response: Response) -> Error {
+    Error::Internal(format!("unexpected response {:?}", response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::storage::memory::Memory;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_client() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = Server::new(Arc::new(Mutex::new(Memory::new())));
+        std::thread::spawn(move || server.serve(listener));
+
+        let mut c = Client::connect(addr)?;
+        c.set(b"a", vec![0x01])?;
+        c.set(b"b", vec![0x02])?;
+        c.delete(b"a")?;
+        assert_eq!(None, c.get(b"a")?);
+        assert_eq!(Some(vec![0x02]), c.get(b"b")?);
+
+        // Writes in a transaction are visible to its reads, and to other
+        // connections only once committed.
+        let mut other = Client::connect(addr)?;
+        c.begin()?;
+        assert!(c.begin().is_err());
+        c.set(b"c", vec![0x03])?;
+        c.delete(b"b")?;
+        assert_eq!(Some(vec![0x03]), c.get(b"c")?);
+        assert_eq!(vec![(b"c".to_vec(), vec![0x03])], c.scan(..)?);
+        assert_eq!(vec![(b"b".to_vec(), vec![0x02])], other.scan(..)?);
+        c.commit()?;
+        assert_eq!(vec![(b"c".to_vec(), vec![0x03])], other.scan(..)?);
+
+        c.begin()?;
+        c.set(b"d", vec![0x04])?;
+        c.rollback()?;
+        assert_eq!(None, c.get(b"d")?);
+        assert_eq!(Err(Error::Value("no transaction is open".to_string())), c.commit());
+
+        c.set(b"cd", vec![0x05])?;
+        assert_eq!(2, c.scan_prefix(b"c")?.len());
+        assert_eq!(2, other.status()?.keys);
+
+        // Large scans are fetched in pages, with buffered writes applied to
+        // the page holding them.
+        let value = vec![0xff; 1 << 20];
+        for key in [b"x1", b"x2", b"x3", b"x4", b"x5", b"x6"] {
+            c.set(key, value.clone())?;
+        }
+        c.begin()?;
+        c.delete(b"x2")?;
+        c.set(b"x55", vec![0x06])?;
+        c.delete(b"x6")?;
+        let keys: Vec<_> = c.scan_prefix(b"x")?.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(vec![b"x1".to_vec(), b"x3".to_vec(), b"x4".to_vec(), b"x5".to_vec(), b"x55".to_vec()], keys);
+        c.rollback()?;
+        Ok(())
+    }
+}
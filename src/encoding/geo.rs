@@ -0,0 +1,323 @@
+use std::ops::Bound;
+
+use crate::error::{Error, Result};
+use crate::storage::{prefix_range, Engine};
+
+// Geospatial keys on an ordered keyspace. A point is quantized to 32 bits of
+// longitude and 32 bits of latitude, and the bits are interleaved into a
+// 64-bit Z-order value, longitude first as in geohashes, so nearby points
+// mostly share a key prefix and any bounding box is covered by a few Z-order
+// ranges. Stored big-endian after a caller-chosen key prefix, points can be
+// range-scanned with scan_geo.
+//
+// Bounding boxes don't wrap around the antimeridian; split those in two.
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl BoundingBox {
+    pub fn new(min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Result<Self> {
+        let valid = (-90.0..=90.0).contains(&min_lat)
+            && (-90.0..=90.0).contains(&max_lat)
+            && (-180.0..=180.0).contains(&min_lon)
+            && (-180.0..=180.0).contains(&max_lon)
+            && min_lat <= max_lat
+            && min_lon <= max_lon;
+        if !valid {
+            return Err(Error::Value(format!(
+                "invalid bounding box ({}, {}) to ({}, {})",
+                min_lat, min_lon, max_lat, max_lon
+            )));
+        }
+        Ok(Self { min_lat, min_lon, max_lat, max_lon })
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat) && (self.min_lon..=self.max_lon).contains(&lon)
+    }
+
+    // The quantized longitude and latitude ranges, inclusive.
+    fn quantize(&self) -> ((u32, u32), (u32, u32)) {
+        let (x0, y0) = quantize(self.min_lat, self.min_lon);
+        let (x1, y1) = quantize(self.max_lat, self.max_lon);
+        ((x0, x1), (y0, y1))
+    }
+}
+
+// Maps a point to its quantized longitude and latitude, clamping it to the
+// valid coordinates.
+fn quantize(lat: f64, lon: f64) -> (u32, u32) {
+    let scale = |value: f64, min: f64, max: f64| {
+        let unit = ((value - min) / (max - min)).clamp(0.0, 1.0);
+        (unit * 4294967296.0).min(u32::MAX as f64) as u32
+    };
+    (scale(lon, -180.0, 180.0), scale(lat, -90.0, 90.0))
+}
+
+// Spreads the bits of x to the even bits of the result.
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | x << 16) & 0x0000_ffff_0000_ffff;
+    x = (x | x << 8) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x << 2) & 0x3333_3333_3333_3333;
+    (x | x << 1) & 0x5555_5555_5555_5555
+}
+
+// Gathers the even bits of z.
+fn compact(z: u64) -> u32 {
+    let mut z = z & 0x5555_5555_5555_5555;
+    z = (z | z >> 1) & 0x3333_3333_3333_3333;
+    z = (z | z >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    z = (z | z >> 4) & 0x00ff_00ff_00ff_00ff;
+    z = (z | z >> 8) & 0x0000_ffff_0000_ffff;
+    (z | z >> 16) as u32
+}
+
+// Interleaves x and y, with x in the odd bits, so the top bit is x's.
+pub fn zorder(x: u32, y: u32) -> u64 {
+    spread(x) << 1 | spread(y)
+}
+
+pub fn unzorder(z: u64) -> (u32, u32) {
+    (compact(z >> 1), compact(z))
+}
+
+// Returns the Z-order value of a point.
+pub fn encode(lat: f64, lon: f64) -> u64 {
+    let (x, y) = quantize(lat, lon);
+    zorder(x, y)
+}
+
+// Returns the south-west corner of the quantization cell of a Z-order value.
+pub fn decode(z: u64) -> (f64, f64) {
+    let (x, y) = unzorder(z);
+    (y as f64 / 4294967296.0 * 180.0 - 90.0, x as f64 / 4294967296.0 * 360.0 - 180.0)
+}
+
+// Returns the geohash of a point with up to 12 characters.
+pub fn geohash(lat: f64, lon: f64, precision: usize) -> Result<String> {
+    if !(1..=12).contains(&precision) {
+        return Err(Error::Value(format!("geohash precision {} is not between 1 and 12", precision)));
+    }
+    let z = encode(lat, lon);
+    Ok((0..precision).map(|i| BASE32[(z >> (59 - 5 * i) & 0x1f) as usize] as char).collect())
+}
+
+// Returns the bounding box of a geohash's cell.
+pub fn decode_geohash(hash: &str) -> Result<BoundingBox> {
+    if hash.is_empty() || hash.len() > 12 {
+        return Err(Error::Value(format!("invalid geohash {:?}", hash)));
+    }
+    let mut z = 0;
+    for (i, c) in hash.bytes().enumerate() {
+        let Some(bits) = BASE32.iter().position(|b| *b == c.to_ascii_lowercase()) else {
+            return Err(Error::Value(format!("invalid geohash {:?}", hash)));
+        };
+        z |= (bits as u64) << (59 - 5 * i);
+    }
+    // Longitude takes the odd bits, so the extra one if there's an odd number.
+    let bits = 5 * hash.len() as i32;
+    let (min_lat, min_lon) = decode(z);
+    let max_lat = min_lat + 180.0 / 2f64.powi(bits / 2);
+    let max_lon = min_lon + 360.0 / 2f64.powi(bits - bits / 2);
+    Ok(BoundingBox { min_lat, min_lon, max_lat, max_lon })
+}
+
+// A quadtree cell: the Z-order values sharing the top 2 * level bits of z.
+#[derive(Clone, Copy)]
+struct Cell {
+    z: u64,
+    level: u32,
+}
+
+enum Overlap {
+    Inside,
+    Partial,
+    Outside,
+}
+
+impl Cell {
+    fn range(&self) -> (u64, u64) {
+        (self.z, self.z | u64::MAX.checked_shr(2 * self.level).unwrap_or(0))
+    }
+
+    fn children(&self) -> impl Iterator<Item = Cell> + '_ {
+        let shift = 62 - 2 * self.level;
+        (0..4).map(move |i| Cell { z: self.z | i << shift, level: self.level + 1 })
+    }
+
+    fn overlap(&self, (xs, ys): ((u32, u32), (u32, u32))) -> Overlap {
+        let (start, end) = self.range();
+        let ((x0, y0), (x1, y1)) = (unzorder(start), unzorder(end));
+        if x1 < xs.0 || x0 > xs.1 || y1 < ys.0 || y0 > ys.1 {
+            Overlap::Outside
+        } else if x0 >= xs.0 && x1 <= xs.1 && y0 >= ys.0 && y1 <= ys.1 {
+            Overlap::Inside
+        } else {
+            Overlap::Partial
+        }
+    }
+}
+
+// Sorts inclusive ranges and merges the adjacent or overlapping ones.
+fn merge(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+// Returns the fewest inclusive Z-order ranges covering the bounding box at
+// the finest quadtree level that needs at most max_ranges of them. Cells
+// straddling the box's edges are split level by level until a level would
+// need more, so the ranges are exact if max_ranges suffices, and otherwise
+// also cover some points outside the box.
+pub fn ranges(bbox: &BoundingBox, max_ranges: usize) -> Vec<(u64, u64)> {
+    let bounds = bbox.quantize();
+    let mut inside = Vec::new();
+    let mut partial = vec![Cell { z: 0, level: 0 }];
+    if let Overlap::Inside = partial[0].overlap(bounds) {
+        return vec![(0, u64::MAX)];
+    }
+    while !partial.is_empty() {
+        let mut next_inside = inside.clone();
+        let mut next_partial = Vec::new();
+        for child in partial.iter().flat_map(Cell::children) {
+            match child.overlap(bounds) {
+                Overlap::Inside => next_inside.push(child.range()),
+                Overlap::Partial => next_partial.push(child),
+                Overlap::Outside => {}
+            }
+        }
+        let all = next_inside.iter().copied().chain(next_partial.iter().map(Cell::range)).collect();
+        if merge(all).len() > max_ranges.max(1) {
+            break;
+        }
+        inside = next_inside;
+        partial = next_partial;
+    }
+    merge(inside.into_iter().chain(partial.iter().map(Cell::range)).collect())
+}
+
+// Returns the key of a point: the prefix, then its Z-order value big-endian,
+// then the suffix, e.g. an ID to tell points at the same location apart.
+pub fn key(prefix: &[u8], lat: f64, lon: f64, suffix: &[u8]) -> Vec<u8> {
+    [prefix, &encode(lat, lon).to_be_bytes(), suffix].concat()
+}
+
+// Returns the entries with keys from geo::key under prefix whose points lie
+// in the bounding box, ordered by key. Scans at most MAX_SCAN_RANGES ranges
+// and filters out the points they cover outside the box.
+pub fn scan_geo<E: Engine>(engine: &mut E, prefix: &[u8], bbox: &BoundingBox) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let ((x0, x1), (y0, y1)) = bbox.quantize();
+    let mut entries = Vec::new();
+    for (start, end) in ranges(bbox, MAX_SCAN_RANGES) {
+        let from = Bound::Included([prefix, &start.to_be_bytes()].concat());
+        let to = match end.checked_add(1) {
+            Some(end) => Bound::Excluded([prefix, &end.to_be_bytes()].concat()),
+            None => prefix_range(prefix).1,
+        };
+        for item in engine.scan((from, to)) {
+            let (key, value) = item?;
+            let Some(z) = key.get(prefix.len()..).and_then(|rest| rest.first_chunk::<8>()) else {
+                return Err(Error::Value(format!("key {:?} is too short for a point", key)));
+            };
+            let (x, y) = unzorder(u64::from_be_bytes(*z));
+            if (x0..=x1).contains(&x) && (y0..=y1).contains(&y) {
+                entries.push((key, value));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+const MAX_SCAN_RANGES: usize = 16;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::Memory;
+
+    #[test]
+    fn test_encode() -> Result<()> {
+        assert_eq!(0b1001, zorder(0b10, 0b01));
+        assert_eq!((0xdead_beef, 0x0123_4567), unzorder(zorder(0xdead_beef, 0x0123_4567)));
+        assert_eq!(0, encode(-90.0, -180.0));
+        assert_eq!(u64::MAX, encode(90.0, 180.0));
+
+        let (lat, lon) = decode(encode(57.64911, 10.40744));
+        assert!((lat - 57.64911).abs() < 1e-7 && (lon - 10.40744).abs() < 1e-7);
+        assert_eq!("u4pruydqqvj", geohash(57.64911, 10.40744, 11)?);
+        assert_eq!("u4pru", geohash(57.64911, 10.40744, 5)?);
+        assert!(geohash(0.0, 0.0, 13).is_err());
+
+        let cell = decode_geohash("u4pruydqqvj")?;
+        assert!(cell.contains(57.64911, 10.40744));
+        let cell = decode_geohash("u4pru")?;
+        assert!(cell.contains(57.64911, 10.40744));
+        assert!((cell.max_lat - cell.min_lat - 180.0 / 4096.0).abs() < 1e-9);
+        assert!((cell.max_lon - cell.min_lon - 360.0 / 8192.0).abs() < 1e-9);
+        assert!(decode_geohash("u4pra").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() -> Result<()> {
+        // A box aligned with a quadtree cell is a single range.
+        let ne = BoundingBox::new(0.0, 0.0, 90.0, 180.0)?;
+        assert_eq!(vec![(0xc000_0000_0000_0000, u64::MAX)], ranges(&ne, 1));
+        assert_eq!(vec![(0, u64::MAX)], ranges(&BoundingBox::new(-90.0, -180.0, 90.0, 180.0)?, 4));
+
+        // Other boxes are covered exactly given enough ranges, and otherwise
+        // over-approximated with fewer.
+        let bbox = BoundingBox::new(10.0, 10.0, 20.0, 30.0)?;
+        for max_ranges in [1, 4, 16, 64] {
+            let ranges = ranges(&bbox, max_ranges);
+            assert!(ranges.len() <= max_ranges);
+            for (lat, lon) in [(10.0, 10.0), (15.0, 20.0), (20.0, 30.0)] {
+                let z = encode(lat, lon);
+                assert!(ranges.iter().any(|(start, end)| (*start..=*end).contains(&z)));
+            }
+        }
+        assert!(BoundingBox::new(10.0, 30.0, 20.0, 10.0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_geo() -> Result<()> {
+        let mut engine = Memory::new();
+        let cities = [
+            ("aarhus", 56.1629, 10.2039),
+            ("berlin", 52.5200, 13.4050),
+            ("copenhagen", 55.6761, 12.5683),
+            ("oslo", 59.9139, 10.7522),
+            ("sydney", -33.8688, 151.2093),
+        ];
+        for (name, lat, lon) in cities {
+            engine.set(&key(b"city/", lat, lon, name.as_bytes()), name.as_bytes().to_vec())?;
+        }
+        engine.set(b"other", vec![])?;
+
+        let denmark = BoundingBox::new(54.5, 8.0, 57.8, 15.2)?;
+        let mut found: Vec<_> = scan_geo(&mut engine, b"city/", &denmark)?.into_iter().map(|(_, v)| v).collect();
+        found.sort();
+        assert_eq!(vec![b"aarhus".to_vec(), b"copenhagen".to_vec()], found);
+
+        let world = BoundingBox::new(-90.0, -180.0, 90.0, 180.0)?;
+        assert_eq!(5, scan_geo(&mut engine, b"city/", &world)?.len());
+        Ok(())
+    }
+}
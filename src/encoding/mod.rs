@@ -0,0 +1,3 @@
+pub mod geo;
+pub use lndb_core::keycode;
+pub mod subspace;
@@ -0,0 +1,96 @@
+use std::ops::Bound;
+
+use super::keycode::{self, KeyDecode, KeyEncode};
+use crate::error::{Error, Result};
+use crate::storage::prefix_range;
+
+// A namespace of keys sharing a prefix, in the style of FoundationDB's
+// subspaces. Keys are packed from keycode tuples appended to the prefix, e.g.
+// orders.pack(&(user_id, "orders", order_id)), so they sort by their typed
+// elements, and all keys of a subspace and of its nested subspaces fall in
+// its range.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Subspace {
+    prefix: Vec<u8>,
+}
+
+impl Subspace {
+    // A subspace whose prefix is the encoded tuple, e.g. ("users",).
+    pub fn new<K: KeyEncode + ?Sized>(prefix: &K) -> Self {
+        Self { prefix: keycode::encode(prefix) }
+    }
+
+    // A subspace with a raw prefix, e.g. one not encoded with keycode.
+    pub fn from_bytes(prefix: Vec<u8>) -> Self {
+        Self { prefix }
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    // A subspace nested in this one, whose prefix is the packed tuple.
+    pub fn subspace<K: KeyEncode + ?Sized>(&self, key: &K) -> Self {
+        Self { prefix: self.pack(key) }
+    }
+
+    pub fn pack<K: KeyEncode + ?Sized>(&self, key: &K) -> Vec<u8> {
+        let mut out = self.prefix.clone();
+        key.encode_into(&mut out);
+        out
+    }
+
+    // Decodes a key packed in this subspace, failing if it has another prefix
+    // or doesn't decode as K.
+    pub fn unpack<K: KeyDecode>(&self, key: &[u8]) -> Result<K> {
+        let rest = key
+            .strip_prefix(self.prefix.as_slice())
+            .ok_or_else(|| Error::Value("key is outside the subspace".to_string()))?;
+        Ok(keycode::decode(rest)?)
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key.starts_with(&self.prefix)
+    }
+
+    // The range of all keys in the subspace, for Engine::scan.
+    pub fn range(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        prefix_range(&self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::Memory;
+    use crate::storage::Engine;
+
+    #[test]
+    fn test_subspace() -> Result<()> {
+        let users = Subspace::new(&("users",));
+        let orders = users.subspace(&(7u64, "orders"));
+        assert_eq!(users.pack(&(7u64, "orders", 3u32)), orders.pack(&(3u32,)));
+        assert_eq!((7u64, "orders".to_string(), 3u32), users.unpack(&orders.pack(&(3u32,)))?);
+        assert_eq!((3u32,), orders.unpack(&orders.pack(&(3u32,)))?);
+        assert!(matches!(orders.unpack::<(u32,)>(&users.pack(&(8u64,))), Err(Error::Value(_))));
+        assert!(users.contains(&orders.pack(&(3u32,))));
+        assert!(!orders.contains(users.prefix()));
+
+        // A subspace's range holds its keys in element order, and none of
+        // another subspace's whose prefix extends it as bytes.
+        let mut s = Memory::new();
+        for id in [10u32, 2, 300] {
+            s.set(&orders.pack(&(id,)), vec![])?;
+        }
+        s.set(&users.pack(&(7u64, "orders2")), vec![])?;
+        s.set(&Subspace::new(&("users2",)).pack(&(1u8,)), vec![])?;
+        let ids = s
+            .scan(orders.range())
+            .map(|item| orders.unpack::<(u32,)>(&item?.0).map(|(id,)| id))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(vec![2, 10, 300], ids);
+        assert_eq!(4, s.scan(users.range()).count());
+        assert_eq!(Subspace::from_bytes(vec![]).range(), prefix_range(&[]));
+        Ok(())
+    }
+}
@@ -1,12 +1,24 @@
 use core::fmt;
 
+use serde::{Deserialize, Serialize};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Error {
     Abort,
+    // Data on disk that fails a checksum or doesn't match the keydir.
+    Corruption(String),
+    // A write refused because repeated write failures or a stuck I/O
+    // operation made the store read-only.
+    Degraded(String),
+    // The store is locked by another process or handle.
+    InUse(String),
     Internal(String),
+    // A write refused by a WriteValidator.
+    Rejected(String),
+    Transient(String),
     Value(String),
 }
 
@@ -16,13 +28,40 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> fmt::Result {
        match self {
            Error::Abort => write!(f, "Operation aborted"),
+           Error::Corruption(message) => write!(f, "Data corruption: {}", message),
+           Error::Degraded(message) => write!(f, "Store degraded: {}", message),
+           Error::InUse(message) => write!(f, "Store in use: {}", message),
            Error::Value(message) | Error::Internal(message) => write!(f, "{}", message),
+           Error::Rejected(message) => write!(f, "Write rejected: {}", message),
+           Error::Transient(message) => write!(f, "Transient error: {}", message),
        } 
     }
 }
 
 impl From<std::io::Error> for Error {
     fn from(value: std::io::Error) -> Self {
-        Error::Internal(value.to_string())
+        // A Vfs may wrap one of our errors to pass it through as is.
+        if let Some(err) = value.get_ref().and_then(|err| err.downcast_ref::<Error>()) {
+            return err.clone();
+        }
+        match value.kind() {
+            std::io::ErrorKind::Interrupted
+            | std::io::ErrorKind::WouldBlock
+            | std::io::ErrorKind::TimedOut
+            | std::io::ErrorKind::StaleNetworkFileHandle => Error::Transient(value.to_string()),
+            _ => Error::Internal(value.to_string()),
+        }
+    }
+}
+
+impl From<lndb_core::entry::Error> for Error {
+    fn from(value: lndb_core::entry::Error) -> Self {
+        Error::Value(value.to_string())
     }
-}
\ No newline at end of file
+}
+
+impl From<lndb_core::keycode::Error> for Error {
+    fn from(value: lndb_core::keycode::Error) -> Self {
+        Error::Value(value.to_string())
+    }
+}
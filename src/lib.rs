@@ -1,4 +1,18 @@
-#![feature(seek_seek_relative)]
+#![cfg_attr(not(test), deny(clippy::unwrap_used))]
 
-mod storage;
-mod error;
\ No newline at end of file
+pub mod client;
+pub mod clock;
+pub mod encoding;
+pub mod error;
+pub mod metrics;
+#[cfg(feature = "python")]
+mod python;
+pub mod raft;
+pub mod retry;
+#[cfg(feature = "search")]
+pub mod search;
+pub mod server;
+pub mod sql;
+pub mod storage;
+#[cfg(feature = "vector")]
+pub mod vector;
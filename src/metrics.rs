@@ -0,0 +1,132 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // Appends the counter as a sample in the Prometheus text format. labels
+    // are comma-separated name="value" pairs without braces, and may be empty.
+    pub fn write_prometheus(&self, out: &mut String, name: &str, labels: &str) {
+        let _ = writeln!(out, "{}{} {}", name, braces(labels), self.get());
+    }
+}
+
+fn braces(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+// Latency bucket upper bounds in seconds, from 10µs to 10s.
+const LATENCY_BOUNDS: [f64; 13] = [
+    0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.1, 1.0, 10.0,
+];
+
+// A fixed-bucket histogram of durations. Buckets are not cumulative; an
+// observation lands in the first bucket whose bound it does not exceed, or in
+// the trailing overflow bucket.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(LATENCY_BOUNDS.to_vec())
+    }
+}
+
+impl Histogram {
+    pub fn new(bounds: Vec<f64>) -> Self {
+        let buckets = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+        Self { bounds, buckets, count: AtomicU64::new(0), sum_nanos: AtomicU64::new(0) }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let index = self.bounds.iter().position(|bound| secs <= *bound).unwrap_or(self.bounds.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> Duration {
+        Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn bounds(&self) -> &[f64] {
+        &self.bounds
+    }
+
+    // Per-bucket counts, with the overflow bucket last.
+    pub fn buckets(&self) -> Vec<u64> {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect()
+    }
+
+    // Appends the histogram's _bucket, _sum and _count samples in the
+    // Prometheus text format, see Counter::write_prometheus. Prometheus
+    // buckets are cumulative, and _count is taken from the same bucket reads
+    // so the two agree under concurrent observations.
+    pub fn write_prometheus(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let bounds = self.bounds.iter().map(|bound| bound.to_string()).chain(["+Inf".to_string()]);
+        let mut cumulative = 0;
+        for (bound, count) in bounds.zip(self.buckets()) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), self.sum().as_secs_f64());
+        let _ = writeln!(out, "{}_count{} {}", name, braces(labels), cumulative);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let h = Histogram::new(vec![0.001, 0.01]);
+        h.observe(Duration::from_micros(500));
+        h.observe(Duration::from_millis(1));
+        h.observe(Duration::from_millis(5));
+        h.observe(Duration::from_secs(1));
+        assert_eq!(vec![2, 1, 1], h.buckets());
+        assert_eq!(4, h.count());
+        assert_eq!(Duration::from_micros(1_006_500), h.sum());
+
+        let mut out = String::new();
+        h.write_prometheus(&mut out, "op_seconds", "op=\"get\"");
+        assert_eq!(
+            "op_seconds_bucket{op=\"get\",le=\"0.001\"} 2\n\
+             op_seconds_bucket{op=\"get\",le=\"0.01\"} 3\n\
+             op_seconds_bucket{op=\"get\",le=\"+Inf\"} 4\n\
+             op_seconds_sum{op=\"get\"} 1.0065\n\
+             op_seconds_count{op=\"get\"} 4\n",
+            out
+        );
+    }
+}
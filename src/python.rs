@@ -0,0 +1,286 @@
+// Python bindings, enabled with the `python` feature and built as an extension
+// module with maturin (see pyproject.toml):
+//
+//   import lndb
+//   db = lndb.open("/tmp/db")
+//   db[b"key"] = b"value"
+//   for key, value in db.scan(b"a", b"z"): ...
+//   with db.transaction() as txn:
+//       txn[b"a"] = b"1"
+//       del txn[b"b"]
+
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use crate::error::{Error, Result};
+use crate::storage::bitcask::BitCask;
+use crate::storage::Engine;
+
+create_exception!(lndb, LndbError, PyException);
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        LndbError::new_err(err.to_string())
+    }
+}
+
+#[pyclass(name = "Database", module = "lndb")]
+pub struct Database {
+    engine: Mutex<Option<BitCask>>,
+}
+
+impl Database {
+    // Runs f on the engine with the GIL released, so other Python threads
+    // keep running while it does I/O.
+    fn with<T: Send>(&self, py: Python<'_>, f: impl FnOnce(&mut BitCask) -> Result<T> + Send) -> PyResult<T> {
+        Ok(py.allow_threads(|| {
+            let mut engine = self.lock();
+            let engine = engine.as_mut().ok_or_else(|| Error::Value("database is closed".to_string()))?;
+            f(engine)
+        })?)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<BitCask>> {
+        self.engine.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[pymethods]
+impl Database {
+    fn get(&self, py: Python<'_>, key: &[u8]) -> PyResult<Option<Py<PyBytes>>> {
+        let value = self.with(py, |e| e.get(key))?;
+        Ok(value.map(|v| PyBytes::new(py, &v).unbind()))
+    }
+
+    fn set(&self, py: Python<'_>, key: &[u8], value: &[u8]) -> PyResult<()> {
+        self.with(py, |e| e.set(key, value.to_vec()))
+    }
+
+    fn delete(&self, py: Python<'_>, key: &[u8]) -> PyResult<()> {
+        self.with(py, |e| e.delete(key))
+    }
+
+    fn __getitem__(&self, py: Python<'_>, key: &[u8]) -> PyResult<Py<PyBytes>> {
+        self.get(py, key)?.ok_or_else(|| PyKeyError::new_err(PyBytes::new(py, key).unbind()))
+    }
+
+    fn __setitem__(&self, py: Python<'_>, key: &[u8], value: &[u8]) -> PyResult<()> {
+        self.set(py, key, value)
+    }
+
+    fn __delitem__(&self, py: Python<'_>, key: &[u8]) -> PyResult<()> {
+        self.delete(py, key)
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &[u8]) -> PyResult<bool> {
+        Ok(self.with(py, |e| e.get(key))?.is_some())
+    }
+
+    // Iterates over key/value pairs in [start, end). The results are read
+    // eagerly, so writes during iteration are not observed.
+    #[pyo3(signature = (start=None, end=None))]
+    fn scan(&self, py: Python<'_>, start: Option<&[u8]>, end: Option<&[u8]>) -> PyResult<ScanIterator> {
+        let start = start.map_or(std::ops::Bound::Unbounded, |s| std::ops::Bound::Included(s.to_vec()));
+        let end = end.map_or(std::ops::Bound::Unbounded, |e| std::ops::Bound::Excluded(e.to_vec()));
+        let items = self.with(py, |e| e.scan((start, end)).collect::<Result<Vec<_>>>())?;
+        Ok(ScanIterator { items: items.into_iter() })
+    }
+
+    fn status<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let status = self.with(py, |e| e.status())?;
+        let dict = PyDict::new(py);
+        dict.set_item("name", status.name)?;
+        dict.set_item("keys", status.keys)?;
+        dict.set_item("size", status.size)?;
+        dict.set_item("total_disk_size", status.total_disk_size)?;
+        dict.set_item("live_disk_size", status.live_disk_size)?;
+        dict.set_item("garbage_disk_size", status.garbage_disk_size)?;
+        Ok(dict)
+    }
+
+    fn compact(&self, py: Python<'_>) -> PyResult<()> {
+        self.with(py, |e| e.compact())
+    }
+
+    fn transaction(slf: Py<Self>) -> Transaction {
+        Transaction { db: slf, writes: Vec::new() }
+    }
+
+    // Dropping the engine flushes it, so the GIL is released for that too.
+    fn close(&self, py: Python<'_>) {
+        py.allow_threads(|| drop(self.lock().take()));
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type=None, _exc_value=None, _traceback=None))]
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        _exc_type: Option<&Bound<'_, PyAny>>,
+        _exc_value: Option<&Bound<'_, PyAny>>,
+        _traceback: Option<&Bound<'_, PyAny>>,
+    ) -> bool {
+        self.close(py);
+        false
+    }
+}
+
+#[pyclass(module = "lndb")]
+pub struct ScanIterator {
+    items: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+}
+
+#[pymethods]
+impl ScanIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> Option<(Py<PyBytes>, Py<PyBytes>)> {
+        self.items
+            .next()
+            .map(|(k, v)| (PyBytes::new(py, &k).unbind(), PyBytes::new(py, &v).unbind()))
+    }
+}
+
+// Buffers writes and applies them when the with-block exits without an
+// exception; an exception discards them. Reads see the transaction's own
+// writes. The writes are applied as one atomic batch.
+#[pyclass(module = "lndb")]
+pub struct Transaction {
+    db: Py<Database>,
+    writes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+#[pymethods]
+impl Transaction {
+    fn __getitem__(&self, py: Python<'_>, key: &[u8]) -> PyResult<Py<PyBytes>> {
+        match self.writes.iter().rev().find(|(k, _)| k == key) {
+            Some((_, Some(value))) => Ok(PyBytes::new(py, value).unbind()),
+            Some((_, None)) => Err(PyKeyError::new_err(PyBytes::new(py, key).unbind())),
+            None => self.db.borrow(py).__getitem__(py, key),
+        }
+    }
+
+    fn __setitem__(&mut self, key: &[u8], value: &[u8]) {
+        self.writes.push((key.to_vec(), Some(value.to_vec())));
+    }
+
+    fn __delitem__(&mut self, key: &[u8]) {
+        self.writes.push((key.to_vec(), None));
+    }
+
+    fn commit(&mut self, py: Python<'_>) -> PyResult<()> {
+        let writes = std::mem::take(&mut self.writes);
+        self.db.borrow(py).with(py, |e| e.write_batch(writes))
+    }
+
+    fn rollback(&mut self) {
+        self.writes.clear();
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (exc_type=None, _exc_value=None, _traceback=None))]
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        exc_type: Option<&Bound<'_, PyAny>>,
+        _exc_value: Option<&Bound<'_, PyAny>>,
+        _traceback: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<bool> {
+        match exc_type {
+            Some(_) => self.rollback(),
+            None => self.commit(py)?,
+        }
+        Ok(false)
+    }
+}
+
+#[pyfunction]
+fn open(py: Python<'_>, path: PathBuf) -> PyResult<Database> {
+    let engine = py.allow_threads(|| BitCask::new(path))?;
+    Ok(Database { engine: Mutex::new(Some(engine)) })
+}
+
+#[pymodule]
+#[pyo3(name = "lndb")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(open, m)?)?;
+    m.add_class::<Database>()?;
+    m.add_class::<ScanIterator>()?;
+    m.add_class::<Transaction>()?;
+    m.add("LndbError", m.py().get_type::<LndbError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_python() -> PyResult<()> {
+        let dir = TempDir::new("python").expect("Failed to create temporary directory");
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "lndb")?;
+            python_module(&module)?;
+            let locals = PyDict::new(py);
+            locals.set_item("lndb", module)?;
+            locals.set_item("path", dir.path().join("db"))?;
+            py.run(
+                c"
+db = lndb.open(path)
+db[b'a'] = b'1'
+db[b'b'] = b'2'
+assert db[b'a'] == b'1'
+assert b'b' in db and b'c' not in db and db.get(b'c') is None
+try:
+    db[b'c']
+    assert False
+except KeyError:
+    pass
+del db[b'b']
+assert list(db.scan()) == [(b'a', b'1')]
+
+with db.transaction() as txn:
+    txn[b'c'] = b'3'
+    del txn[b'a']
+    assert txn[b'c'] == b'3'
+    assert b'a' in db
+assert list(db.scan(b'a', b'z')) == [(b'c', b'3')]
+try:
+    with db.transaction() as txn:
+        txn[b'd'] = b'4'
+        raise ValueError
+except ValueError:
+    pass
+assert db.get(b'd') is None
+
+with db:
+    pass
+try:
+    db.get(b'c')
+    assert False
+except lndb.LndbError as err:
+    assert str(err) == 'database is closed'
+db = lndb.open(path)
+assert list(db.scan()) == [(b'c', b'3')]
+",
+                None,
+                Some(&locals),
+            )
+        })
+    }
+}
@@ -0,0 +1,233 @@
+use super::{Index, NodeId, Term};
+use crate::encoding::keycode;
+use crate::error::{Error, Result};
+use crate::storage::Engine;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub index: Index,
+    pub term: Term,
+    // None for the empty entry a new leader appends to commit earlier terms.
+    pub command: Option<Vec<u8>>,
+}
+
+// The Raft log and the node's durable term and vote, stored in an engine.
+// Entries are stored under ("entry", index), so they are contiguous and in
+// index order, with (term, command) as the value. The current term and vote
+// are under "term" and the commit index under "commit". The term, vote and
+// entries are synced before the calls writing them return, since a node must
+// not forget a vote or an entry it acknowledged; the commit index isn't, since
+// it can be learned again from the leader.
+pub struct Log<E: Engine> {
+    engine: E,
+    last_index: Index,
+    last_term: Term,
+    commit_index: Index,
+    commit_term: Term,
+}
+
+fn entry_key(index: Index) -> Vec<u8> {
+    keycode::encode(&("entry", index))
+}
+
+fn encode_entry(entry: &Entry) -> Vec<u8> {
+    let command = entry.command.as_deref().unwrap_or_default();
+    keycode::encode(&(entry.term, entry.command.is_some(), command))
+}
+
+fn decode_entry(key: &[u8], value: &[u8]) -> Result<Entry> {
+    let (_, index) = keycode::decode::<(String, Index)>(key)?;
+    let (term, has_command, command) = keycode::decode::<(Term, bool, Vec<u8>)>(value)?;
+    Ok(Entry { index, term, command: has_command.then_some(command) })
+}
+
+impl<E: Engine> Log<E> {
+    pub fn new(mut engine: E) -> Result<Self> {
+        let (last_index, last_term) = match engine.scan_prefix(&keycode::encode("entry")).next_back() {
+            Some(item) => {
+                let (key, value) = item?;
+                let entry = decode_entry(&key, &value)?;
+                (entry.index, entry.term)
+            }
+            None => (0, 0),
+        };
+        let (commit_index, commit_term) = match engine.get(&keycode::encode("commit"))? {
+            Some(value) => keycode::decode(&value)?,
+            None => (0, 0),
+        };
+        Ok(Self { engine, last_index, last_term, commit_index, commit_term })
+    }
+
+    // The index and term of the last entry, or (0, 0) if the log is empty.
+    pub fn last(&self) -> (Index, Term) {
+        (self.last_index, self.last_term)
+    }
+
+    pub fn commit_index(&self) -> (Index, Term) {
+        (self.commit_index, self.commit_term)
+    }
+
+    // The current term and the node voted for in it, if any.
+    pub fn get_term(&mut self) -> Result<(Term, Option<NodeId>)> {
+        let Some(value) = self.engine.get(&keycode::encode("term"))? else {
+            return Ok((0, None));
+        };
+        let (term, voted, voted_for) = keycode::decode::<(Term, bool, NodeId)>(&value)?;
+        Ok((term, voted.then_some(voted_for)))
+    }
+
+    pub fn set_term(&mut self, term: Term, voted_for: Option<NodeId>) -> Result<()> {
+        let value = keycode::encode(&(term, voted_for.is_some(), voted_for.unwrap_or_default()));
+        self.engine.set(&keycode::encode("term"), value)?;
+        self.engine.sync()
+    }
+
+    pub fn append(&mut self, term: Term, command: Option<Vec<u8>>) -> Result<Index> {
+        let entry = Entry { index: self.last_index + 1, term, command };
+        self.engine.set(&entry_key(entry.index), encode_entry(&entry))?;
+        self.engine.sync()?;
+        self.last_index = entry.index;
+        self.last_term = term;
+        Ok(entry.index)
+    }
+
+    // Marks the entries up to index as committed. The commit index never
+    // moves backwards.
+    pub fn commit(&mut self, index: Index) -> Result<()> {
+        if index <= self.commit_index {
+            return Ok(());
+        }
+        let entry = self
+            .get(index)?
+            .ok_or_else(|| Error::Internal(format!("can't commit missing entry {}", index)))?;
+        self.engine.set(&keycode::encode("commit"), keycode::encode(&(entry.index, entry.term)))?;
+        self.commit_index = entry.index;
+        self.commit_term = entry.term;
+        Ok(())
+    }
+
+    pub fn get(&mut self, index: Index) -> Result<Option<Entry>> {
+        self.engine.get(&entry_key(index))?.map(|value| decode_entry(&entry_key(index), &value)).transpose()
+    }
+
+    // Whether the log has an entry at index with the given term. The empty
+    // prefix of every log matches (0, 0).
+    pub fn has(&mut self, index: Index, term: Term) -> Result<bool> {
+        if index == 0 {
+            return Ok(term == 0);
+        }
+        Ok(self.get(index)?.is_some_and(|entry| entry.term == term))
+    }
+
+    // Returns up to limit entries starting at index.
+    pub fn scan(&mut self, index: Index, limit: usize) -> Result<Vec<Entry>> {
+        let range = entry_key(index)..keycode::encode(&("entry", Index::MAX));
+        self.engine
+            .scan(range)
+            .take(limit)
+            .map(|item| item.and_then(|(key, value)| decode_entry(&key, &value)))
+            .collect()
+    }
+
+    // Writes entries received from the leader, which must follow or overlap
+    // the log. Entries already in the log are skipped, and the first
+    // conflicting entry replaces the rest of the log, which must not be
+    // committed. Returns the new last index.
+    pub fn splice(&mut self, entries: Vec<Entry>) -> Result<Index> {
+        let mut batch = Vec::new();
+        let mut last = (self.last_index, self.last_term);
+        let mut truncated = false;
+        for entry in entries {
+            if entry.index > last.0 + 1 {
+                return Err(Error::Internal(format!("entry {} leaves a gap after {}", entry.index, last.0)));
+            }
+            if !truncated && entry.index <= self.last_index {
+                if self.has(entry.index, entry.term)? {
+                    continue;
+                }
+                if entry.index <= self.commit_index {
+                    return Err(Error::Internal(format!("can't replace committed entry {}", entry.index)));
+                }
+                batch.extend((entry.index..=self.last_index).map(|index| (entry_key(index), None)));
+                truncated = true;
+            }
+            batch.push((entry_key(entry.index), Some(encode_entry(&entry))));
+            last = (entry.index, entry.term);
+        }
+        if !batch.is_empty() {
+            self.engine.write_batch(batch)?;
+            self.engine.sync()?;
+            (self.last_index, self.last_term) = last;
+        }
+        Ok(self.last_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::{BitCask, Options};
+    use crate::storage::vfs::{Faults, FaultyFs, MemFs};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    fn entry(index: Index, term: Term, command: &[u8]) -> Entry {
+        Entry { index, term, command: Some(command.to_vec()) }
+    }
+
+    #[test]
+    fn test_log() -> Result<()> {
+        let dir = TempDir::new("raft").expect("Failed to create temporary directory");
+        let path = dir.path().join("log");
+        let mut log = Log::new(BitCask::new(path.clone())?)?;
+        assert_eq!((0, 0), log.last());
+        assert!(log.has(0, 0)?);
+
+        log.set_term(2, Some(3))?;
+        assert_eq!(1, log.append(1, None)?);
+        assert_eq!(2, log.append(1, Some(b"a".to_vec()))?);
+        assert_eq!(3, log.append(2, Some(b"b".to_vec()))?);
+        log.commit(2)?;
+        log.commit(1)?;
+        assert_eq!((2, 1), log.commit_index());
+
+        // Matching entries are skipped, and a conflict truncates the rest.
+        assert_eq!(3, log.splice(vec![entry(2, 1, b"a")])?);
+        assert_eq!(4, log.splice(vec![entry(3, 3, b"c"), entry(4, 3, b"d")])?);
+        assert!(log.splice(vec![entry(2, 3, b"x")]).is_err());
+        assert!(log.splice(vec![entry(6, 3, b"x")]).is_err());
+        drop(log);
+
+        let mut log = Log::new(BitCask::new(path)?)?;
+        assert_eq!((4, 3), log.last());
+        assert_eq!((2, 1), log.commit_index());
+        assert_eq!((2, Some(3)), log.get_term()?);
+        assert_eq!(
+            vec![Entry { index: 1, term: 1, command: None }, entry(2, 1, b"a"), entry(3, 3, b"c")],
+            log.scan(1, 3)?
+        );
+        assert_eq!(vec![entry(4, 3, b"d")], log.scan(4, 10)?);
+        assert_eq!(Vec::<Entry>::new(), log.scan(5, 10)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_sync() -> Result<()> {
+        let faults = Faults::default();
+        let options = Options { vfs: Arc::new(FaultyFs::new(Arc::new(MemFs::new()), faults.clone())), ..Default::default() };
+        let mut log = Log::new(BitCask::new_with_options(PathBuf::from("/raft/log"), options)?)?;
+
+        // The term, vote and entries are synced even though the engine's sync
+        // policy is Never, so a failed sync fails the call.
+        faults.fail_syncs(1, std::io::ErrorKind::Other);
+        assert!(log.set_term(1, Some(2)).is_err());
+        faults.fail_syncs(1, std::io::ErrorKind::Other);
+        assert!(log.append(1, None).is_err());
+        assert_eq!((0, 0), log.last());
+        faults.fail_syncs(1, std::io::ErrorKind::Other);
+        assert!(log.splice(vec![entry(1, 1, b"a")]).is_err());
+        assert_eq!((0, 0), log.last());
+        Ok(())
+    }
+}
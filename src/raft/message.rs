@@ -0,0 +1,24 @@
+use super::{Entry, Index, NodeId, Term};
+
+// A message between nodes, stamped with the sender's term.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub term: Term,
+    pub message: Message,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    // A candidate asks for votes, with its last entry to prove its log is at
+    // least as up to date as the voter's.
+    RequestVote { last_index: Index, last_term: Term },
+    Vote { granted: bool },
+    // The leader replicates entries following (prev_index, prev_term), or
+    // sends none as a heartbeat.
+    Append { prev_index: Index, prev_term: Term, entries: Vec<Entry>, commit_index: Index },
+    // On success, last_index is the last entry known to match the leader's
+    // log. On failure, it's a hint of where the follower's log ends.
+    AppendResponse { success: bool, last_index: Index },
+}
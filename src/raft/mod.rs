@@ -0,0 +1,22 @@
+// Raft consensus for replicating a state machine across nodes, as described
+// in "In Search of an Understandable Consensus Algorithm" (Ongaro and
+// Ousterhout). Nodes elect a leader, which appends client commands to its
+// log and replicates them, and every node applies committed commands to its
+// State in log order. The log is kept in a storage Engine.
+//
+// Nodes are driven by the caller: tick() advances logical time for election
+// timeouts and heartbeats, step() handles a message from a peer, and
+// outgoing messages are sent on a channel, so the transport between nodes is
+// up to the caller. Membership is static, and there are no snapshots or log
+// compaction.
+mod log;
+mod message;
+mod node;
+
+pub use log::{Entry, Log};
+pub use message::{Envelope, Message};
+pub use node::{Node, Options, State};
+
+pub type NodeId = u8;
+pub type Term = u64;
+pub type Index = u64;
@@ -0,0 +1,508 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::mpsc::Sender;
+
+use rand::Rng;
+
+use super::{Entry, Envelope, Index, Log, Message, NodeId, Term};
+use crate::error::{Error, Result};
+use crate::storage::Engine;
+
+// A deterministic state machine that committed commands are applied to.
+pub trait State {
+    // The index of the last applied entry. A state that persists itself
+    // returns it from storage, so entries aren't applied twice on restart.
+    fn applied_index(&self) -> Index;
+
+    // Applies a committed entry, which is the entry after applied_index.
+    // Entries without a command only advance the applied index.
+    fn apply(&mut self, entry: Entry) -> Result<()>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    // Followers and candidates start an election after a random number of
+    // ticks in this range without hearing from a leader.
+    pub election_timeout: Range<u64>,
+    // Ticks between leader heartbeats, which must be well below the election
+    // timeout.
+    pub heartbeat_interval: u64,
+    // The most entries sent in one Append message.
+    pub max_append_entries: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { election_timeout: 10..20, heartbeat_interval: 3, max_append_entries: 100 }
+    }
+}
+
+enum Role {
+    Follower { leader: Option<NodeId>, elapsed: u64, timeout: u64 },
+    Candidate { votes: HashSet<NodeId>, elapsed: u64, timeout: u64 },
+    Leader { progress: HashMap<NodeId, Progress>, since_heartbeat: u64 },
+}
+
+// The leader's view of a follower's log: the next entry to send, and the
+// last entry known to match.
+struct Progress {
+    next: Index,
+    matched: Index,
+}
+
+pub struct Node<E: Engine, S: State> {
+    id: NodeId,
+    peers: HashSet<NodeId>,
+    term: Term,
+    voted_for: Option<NodeId>,
+    log: Log<E>,
+    state: S,
+    outbox: Sender<Envelope>,
+    options: Options,
+    role: Role,
+}
+
+impl<E: Engine, S: State> Node<E, S> {
+    // Starts a node as a follower, after applying any committed entries the
+    // state hasn't seen. A node without peers elects itself right away.
+    pub fn new(
+        id: NodeId,
+        peers: Vec<NodeId>,
+        mut log: Log<E>,
+        state: S,
+        outbox: Sender<Envelope>,
+        options: Options,
+    ) -> Result<Self> {
+        let (term, voted_for) = log.get_term()?;
+        let timeout = random_timeout(&options);
+        let mut node = Self {
+            id,
+            peers: peers.into_iter().filter(|peer| *peer != id).collect(),
+            term,
+            voted_for,
+            log,
+            state,
+            outbox,
+            options,
+            role: Role::Follower { leader: None, elapsed: 0, timeout },
+        };
+        node.apply()?;
+        if node.peers.is_empty() {
+            node.campaign()?;
+        }
+        Ok(node)
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn term(&self) -> Term {
+        self.term
+    }
+
+    // The leader of the current term, if known.
+    pub fn leader(&self) -> Option<NodeId> {
+        match &self.role {
+            Role::Follower { leader, .. } => *leader,
+            Role::Candidate { .. } => None,
+            Role::Leader { .. } => Some(self.id),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader { .. })
+    }
+
+    pub fn commit_index(&self) -> Index {
+        self.log.commit_index().0
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    // Appends a command to the leader's log and starts replicating it.
+    // Returns its index; the command is applied once the commit index
+    // reaches it, and may be lost if leadership changes before that.
+    pub fn propose(&mut self, command: Vec<u8>) -> Result<Index> {
+        if !self.is_leader() {
+            return Err(Error::Value(match self.leader() {
+                Some(leader) => format!("node {} is not the leader, node {} is", self.id, leader),
+                None => format!("node {} is not the leader, and there is none", self.id),
+            }));
+        }
+        let index = self.log.append(self.term, Some(command))?;
+        self.replicate()?;
+        Ok(index)
+    }
+
+    // Advances logical time by one tick.
+    pub fn tick(&mut self) -> Result<()> {
+        match &mut self.role {
+            Role::Follower { elapsed, timeout, .. } | Role::Candidate { elapsed, timeout, .. } => {
+                *elapsed += 1;
+                if *elapsed >= *timeout {
+                    self.campaign()?;
+                }
+            }
+            Role::Leader { since_heartbeat, .. } => {
+                *since_heartbeat += 1;
+                if *since_heartbeat >= self.options.heartbeat_interval {
+                    *since_heartbeat = 0;
+                    for peer in self.peers.clone() {
+                        self.send_append(peer)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Handles a message from a peer.
+    pub fn step(&mut self, envelope: Envelope) -> Result<()> {
+        let Envelope { from, to, term, message } = envelope;
+        if to != self.id || !self.peers.contains(&from) {
+            log::warn!("Node {} dropping message from {} to {}", self.id, from, to);
+            return Ok(());
+        }
+        if term > self.term {
+            self.become_follower(term, None)?;
+        }
+        if term < self.term {
+            // Reply to stale candidates and leaders so they learn the term.
+            match message {
+                Message::RequestVote { .. } => self.send(from, Message::Vote { granted: false })?,
+                Message::Append { .. } => {
+                    self.send(from, Message::AppendResponse { success: false, last_index: 0 })?
+                }
+                Message::Vote { .. } | Message::AppendResponse { .. } => {}
+            }
+            return Ok(());
+        }
+
+        match message {
+            Message::RequestVote { last_index, last_term } => {
+                let (our_index, our_term) = self.log.last();
+                let granted = self.voted_for.is_none_or(|voted_for| voted_for == from)
+                    && (last_term, last_index) >= (our_term, our_index);
+                if granted {
+                    self.voted_for = Some(from);
+                    self.log.set_term(self.term, self.voted_for)?;
+                    if let Role::Follower { elapsed, .. } = &mut self.role {
+                        *elapsed = 0;
+                    }
+                }
+                self.send(from, Message::Vote { granted })?;
+            }
+
+            Message::Vote { granted } => {
+                if let Role::Candidate { votes, .. } = &mut self.role {
+                    if granted {
+                        votes.insert(from);
+                    }
+                    if votes.len() >= self.quorum() {
+                        self.become_leader()?;
+                    }
+                }
+            }
+
+            Message::Append { prev_index, prev_term, entries, commit_index } => {
+                match &mut self.role {
+                    Role::Follower { leader, elapsed, .. } => {
+                        *leader = Some(from);
+                        *elapsed = 0;
+                    }
+                    Role::Candidate { .. } => self.become_follower(self.term, Some(from))?,
+                    Role::Leader { .. } => {
+                        return Err(Error::Internal(format!("nodes {} and {} both lead term {}", self.id, from, term)));
+                    }
+                }
+                if !self.log.has(prev_index, prev_term)? {
+                    let last_index = prev_index.saturating_sub(1).min(self.log.last().0);
+                    return self.send(from, Message::AppendResponse { success: false, last_index });
+                }
+                let last_index = prev_index + entries.len() as Index;
+                self.log.splice(entries)?;
+                // Only entries known to match the leader's log can be
+                // committed.
+                if commit_index.min(last_index) > self.log.commit_index().0 {
+                    self.log.commit(commit_index.min(last_index))?;
+                    self.apply()?;
+                }
+                self.send(from, Message::AppendResponse { success: true, last_index })?;
+            }
+
+            Message::AppendResponse { success, last_index } => {
+                let log_last = self.log.last().0;
+                let Role::Leader { progress, .. } = &mut self.role else {
+                    return Ok(());
+                };
+                let Some(progress) = progress.get_mut(&from) else {
+                    return Ok(());
+                };
+                if success {
+                    progress.matched = progress.matched.max(last_index);
+                    progress.next = progress.matched + 1;
+                    let behind = progress.next <= log_last;
+                    self.maybe_commit()?;
+                    if behind {
+                        self.send_append(from)?;
+                    }
+                } else {
+                    // Back off towards the follower's last entry, but not
+                    // past an entry known to match.
+                    progress.next = progress.next.saturating_sub(1).min(last_index + 1).max(progress.matched + 1);
+                    self.send_append(from)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn quorum(&self) -> usize {
+        let size = self.peers.len() + 1;
+        size / 2 + 1
+    }
+
+    fn send(&self, to: NodeId, message: Message) -> Result<()> {
+        let envelope = Envelope { from: self.id, to, term: self.term, message };
+        self.outbox.send(envelope).map_err(|_| Error::Internal("Raft outbox is closed".to_string()))
+    }
+
+    fn become_follower(&mut self, term: Term, leader: Option<NodeId>) -> Result<()> {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.log.set_term(term, None)?;
+        }
+        self.role = Role::Follower { leader, elapsed: 0, timeout: random_timeout(&self.options) };
+        Ok(())
+    }
+
+    fn campaign(&mut self) -> Result<()> {
+        self.term += 1;
+        self.voted_for = Some(self.id);
+        self.log.set_term(self.term, self.voted_for)?;
+        let votes = HashSet::from([self.id]);
+        self.role = Role::Candidate { votes, elapsed: 0, timeout: random_timeout(&self.options) };
+        if self.quorum() == 1 {
+            return self.become_leader();
+        }
+        let (last_index, last_term) = self.log.last();
+        for peer in &self.peers {
+            self.send(*peer, Message::RequestVote { last_index, last_term })?;
+        }
+        Ok(())
+    }
+
+    // Takes over as leader, appending an empty entry: entries from earlier
+    // terms are only committed along with an entry from the current term.
+    fn become_leader(&mut self) -> Result<()> {
+        log::info!("Node {} is the leader for term {}", self.id, self.term);
+        let next = self.log.last().0 + 1;
+        let progress = self.peers.iter().map(|peer| (*peer, Progress { next, matched: 0 })).collect();
+        self.role = Role::Leader { progress, since_heartbeat: 0 };
+        self.log.append(self.term, None)?;
+        self.replicate()
+    }
+
+    fn replicate(&mut self) -> Result<()> {
+        for peer in self.peers.clone() {
+            self.send_append(peer)?;
+        }
+        self.maybe_commit()
+    }
+
+    fn send_append(&mut self, peer: NodeId) -> Result<()> {
+        let Role::Leader { progress, .. } = &self.role else {
+            return Ok(());
+        };
+        let Some(next) = progress.get(&peer).map(|progress| progress.next) else {
+            return Ok(());
+        };
+        let prev_index = next - 1;
+        let prev_term = match self.log.get(prev_index)? {
+            Some(entry) => entry.term,
+            None => 0,
+        };
+        let entries = self.log.scan(next, self.options.max_append_entries)?;
+        let commit_index = self.log.commit_index().0;
+        self.send(peer, Message::Append { prev_index, prev_term, entries, commit_index })
+    }
+
+    // Commits the last entry replicated to a quorum, if it's from the
+    // current term.
+    fn maybe_commit(&mut self) -> Result<()> {
+        let Role::Leader { progress, .. } = &self.role else {
+            return Ok(());
+        };
+        let mut matched: Vec<Index> = progress.values().map(|progress| progress.matched).collect();
+        matched.push(self.log.last().0);
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let index = matched[self.quorum() - 1];
+        if index <= self.log.commit_index().0 {
+            return Ok(());
+        }
+        if self.log.get(index)?.is_some_and(|entry| entry.term == self.term) {
+            self.log.commit(index)?;
+            self.apply()?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self) -> Result<()> {
+        let commit_index = self.log.commit_index().0;
+        while self.state.applied_index() < commit_index {
+            let index = self.state.applied_index() + 1;
+            let entry = self
+                .log
+                .get(index)?
+                .ok_or_else(|| Error::Internal(format!("committed entry {} is missing", index)))?;
+            self.state.apply(entry)?;
+        }
+        Ok(())
+    }
+}
+
+fn random_timeout(options: &Options) -> u64 {
+    rand::thread_rng().gen_range(options.election_timeout.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::Memory;
+    use std::collections::BTreeMap;
+    use std::sync::mpsc::{channel, Receiver};
+
+    // Records the applied commands.
+    #[derive(Default)]
+    struct Commands {
+        applied: Index,
+        commands: Vec<Vec<u8>>,
+    }
+
+    impl State for Commands {
+        fn applied_index(&self) -> Index {
+            self.applied
+        }
+
+        fn apply(&mut self, entry: Entry) -> Result<()> {
+            assert_eq!(self.applied + 1, entry.index);
+            self.applied = entry.index;
+            self.commands.extend(entry.command);
+            Ok(())
+        }
+    }
+
+    // Nodes connected by in-memory channels. Messages to or from an isolated
+    // node are dropped.
+    struct Cluster {
+        nodes: BTreeMap<NodeId, Node<Memory, Commands>>,
+        outboxes: BTreeMap<NodeId, Receiver<Envelope>>,
+        isolated: HashSet<NodeId>,
+    }
+
+    impl Cluster {
+        fn new(ids: Vec<NodeId>) -> Result<Self> {
+            let mut cluster = Cluster { nodes: BTreeMap::new(), outboxes: BTreeMap::new(), isolated: HashSet::new() };
+            for id in &ids {
+                let (tx, rx) = channel();
+                let log = Log::new(Memory::new())?;
+                let node = Node::new(*id, ids.clone(), log, Commands::default(), tx, Options::default())?;
+                cluster.nodes.insert(*id, node);
+                cluster.outboxes.insert(*id, rx);
+            }
+            Ok(cluster)
+        }
+
+        fn deliver(&mut self) -> Result<()> {
+            loop {
+                let messages: Vec<_> = self.outboxes.values().flat_map(|rx| rx.try_iter()).collect();
+                if messages.is_empty() {
+                    return Ok(());
+                }
+                for message in messages {
+                    if self.isolated.contains(&message.from) || self.isolated.contains(&message.to) {
+                        continue;
+                    }
+                    if let Some(node) = self.nodes.get_mut(&message.to) {
+                        node.step(message)?;
+                    }
+                }
+            }
+        }
+
+        // Ticks every node until the condition holds, failing after a while.
+        fn tick_until(&mut self, condition: impl Fn(&Cluster) -> bool) -> Result<()> {
+            for _ in 0..1000 {
+                if condition(self) {
+                    return Ok(());
+                }
+                for node in self.nodes.values_mut() {
+                    node.tick()?;
+                }
+                self.deliver()?;
+            }
+            panic!("condition not reached");
+        }
+
+        fn leader(&self) -> Option<NodeId> {
+            self.nodes.values().find(|node| node.is_leader() && !self.isolated.contains(&node.id())).map(|node| node.id())
+        }
+
+        fn node(&mut self, id: NodeId) -> &mut Node<Memory, Commands> {
+            self.nodes.get_mut(&id).expect("no such node")
+        }
+
+        fn commands(&self, id: NodeId) -> Vec<Vec<u8>> {
+            self.nodes[&id].state().commands.clone()
+        }
+    }
+
+    #[test]
+    fn test_single_node() -> Result<()> {
+        let (tx, _rx) = channel();
+        let mut node = Node::new(1, vec![1], Log::new(Memory::new())?, Commands::default(), tx, Options::default())?;
+        assert!(node.is_leader());
+        assert_eq!(2, node.propose(b"a".to_vec())?);
+        assert_eq!(2, node.commit_index());
+        assert_eq!(vec![b"a".to_vec()], node.state().commands);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cluster() -> Result<()> {
+        let mut c = Cluster::new(vec![1, 2, 3])?;
+        c.tick_until(|c| c.leader().is_some())?;
+        let leader = c.leader().expect("no leader");
+        c.tick_until(|c| c.nodes.values().all(|node| node.leader() == Some(leader)))?;
+
+        c.node(leader).propose(b"a".to_vec())?;
+        c.node(leader).propose(b"b".to_vec())?;
+        c.deliver()?;
+        c.tick_until(|c| c.nodes.keys().all(|id| c.commands(*id).len() == 2))?;
+        let follower = *c.nodes.keys().find(|id| **id != leader).expect("no follower");
+        assert!(c.node(follower).propose(b"x".to_vec()).is_err());
+
+        // An isolated leader can't commit, and the others elect a new one
+        // whose log replaces the uncommitted entry once the partition heals.
+        c.isolated.insert(leader);
+        c.node(leader).propose(b"lost".to_vec())?;
+        let term = c.nodes[&leader].term();
+        c.tick_until(|c| c.leader().is_some())?;
+        let new_leader = c.leader().expect("no leader");
+        assert!(c.nodes[&new_leader].term() > term);
+        c.node(new_leader).propose(b"c".to_vec())?;
+        c.deliver()?;
+        assert_eq!(2, c.commands(leader).len());
+
+        c.isolated.clear();
+        c.tick_until(|c| !c.nodes[&leader].is_leader() && c.nodes.keys().all(|id| c.commands(*id).len() == 3))?;
+        for id in [1, 2, 3] {
+            assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()], c.commands(id));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::clock::Clock;
+use crate::error::{Error, Result};
+
+// Capped exponential backoff with full jitter: the n-th retry sleeps for a
+// random duration in [0, min(max, initial * 2^n)].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(100),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let cap = self.initial
+            .checked_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+            .unwrap_or(self.max)
+            .min(self.max);
+        cap.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    // Runs f until it succeeds, fails with an error for which retryable
+    // returns false, or max_attempts is reached, in which case the last error
+    // is returned. Waits between attempts on the clock.
+    pub fn retry<T, F, R>(&self, clock: &dyn Clock, mut f: F, retryable: R) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+        R: Fn(&Error) -> bool,
+    {
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(err) if retryable(&err) && attempt + 1 < self.max_attempts => {
+                    log::debug!("Retrying after error (attempt {}): {}", attempt + 1, err);
+                    clock.sleep(self.delay(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_delay_is_capped() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+            max_attempts: 5,
+        };
+        for attempt in 0..64 {
+            assert!(backoff.delay(attempt) <= Duration::from_millis(50));
+        }
+        assert!(backoff.delay(0) <= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_retry() -> Result<()> {
+        let clock = MockClock::new(Duration::ZERO);
+        let backoff = Backoff::default();
+        let retryable = |err: &Error| matches!(err, Error::Transient(_));
+        let mut calls = 0;
+        let value = backoff.retry(&clock, || {
+            calls += 1;
+            if calls < 3 { Err(Error::Transient("busy".to_string())) } else { Ok(calls) }
+        }, retryable)?;
+        assert_eq!(3, value);
+        assert!(clock.now() <= Duration::from_millis(3));
+
+        let mut calls = 0;
+        let result: Result<()> = backoff.retry(&clock, || {
+            calls += 1;
+            Err(Error::Value("bad".to_string()))
+        }, retryable);
+        assert_eq!(Err(Error::Value("bad".to_string())), result);
+        assert_eq!(1, calls);
+
+        let backoff = Backoff { initial: Duration::ZERO, max: Duration::ZERO, max_attempts: 4 };
+        let mut calls = 0;
+        let result: Result<()> = backoff.retry(&clock, || { calls += 1; Err(Error::Abort) }, |_| true);
+        assert_eq!(Err(Error::Abort), result);
+        assert_eq!(4, calls);
+        Ok(())
+    }
+}
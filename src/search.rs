@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::encoding::keycode::{self, KeyDecode, KeyEncode};
+use crate::error::{Error, Result};
+use crate::storage::Engine;
+
+// A document's fields by name.
+pub type Document = BTreeMap<String, String>;
+
+// A full-text index over documents stored in an engine. Documents are stored
+// under ("doc", id), and the text of the designated fields is split into
+// lowercase alphanumeric terms, each with a posting under ("term", term, id),
+// so a term's posting list is a prefix scan in ID order and a term prefix is
+// one too. A document is stored with the terms it was indexed under, and it
+// and its postings are always written in one batch. Matches aren't ranked.
+pub struct Index<E: Engine> {
+    engine: E,
+    fields: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    // Documents containing the term.
+    Term(String),
+    // Documents containing a term starting with the prefix.
+    Prefix(String),
+    // Documents matching all of the queries, none if there are none.
+    And(Vec<Query>),
+    // Documents matching any of the queries.
+    Or(Vec<Query>),
+}
+
+fn doc_key(id: &[u8]) -> Vec<u8> {
+    keycode::encode(&("doc", id))
+}
+
+fn term_key(term: &str, id: &[u8]) -> Vec<u8> {
+    keycode::encode(&("term", term, id))
+}
+
+// The keycode encoding of ("term", prefix) without the string terminator,
+// which prefixes the keys of all terms starting with prefix.
+fn term_prefix(prefix: &str) -> Vec<u8> {
+    let mut key = keycode::encode(&("term", prefix));
+    key.truncate(key.len() - 2);
+    key
+}
+
+// A document is its field count followed by (name, text) pairs, then the
+// count of the terms it was indexed under followed by the terms. Replacing or
+// deleting it removes the postings of those terms, whatever fields the index
+// has by then.
+fn encode_document(document: &Document, terms: &BTreeSet<String>) -> Vec<u8> {
+    let mut out = Vec::new();
+    (document.len() as u32).encode_into(&mut out);
+    for field in document {
+        field.encode_into(&mut out);
+    }
+    (terms.len() as u32).encode_into(&mut out);
+    for term in terms {
+        term.encode_into(&mut out);
+    }
+    out
+}
+
+fn decode_document(bytes: &[u8]) -> Result<(Document, BTreeSet<String>)> {
+    let mut input = bytes;
+    let count = u32::decode_from(&mut input)?;
+    let document = (0..count).map(|_| <(String, String)>::decode_from(&mut input)).collect::<std::result::Result<_, keycode::Error>>()?;
+    let count = u32::decode_from(&mut input)?;
+    let terms = (0..count).map(|_| String::decode_from(&mut input)).collect::<std::result::Result<_, keycode::Error>>()?;
+    if !input.is_empty() {
+        return Err(Error::Corruption(format!("document has {} trailing bytes", input.len())));
+    }
+    Ok((document, terms))
+}
+
+// Splits text into lowercase terms at anything that isn't alphanumeric.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|term| !term.is_empty()).map(str::to_lowercase)
+}
+
+impl<E: Engine> Index<E> {
+    // Indexes the given fields of the documents. Changing the fields of an
+    // existing index only affects documents written afterwards: the others
+    // keep their postings until they're replaced or deleted.
+    pub fn new(engine: E, fields: Vec<String>) -> Self {
+        Self { engine, fields }
+    }
+
+    pub fn get(&mut self, id: &[u8]) -> Result<Option<Document>> {
+        Ok(self.read(id)?.map(|(document, _)| document))
+    }
+
+    // Stores the document, replacing any with the same ID, and updates the
+    // postings.
+    pub fn put(&mut self, id: &[u8], document: &Document) -> Result<()> {
+        let old = self.read(id)?.map(|(_, terms)| terms).unwrap_or_default();
+        let new = self.terms(document);
+        let mut batch: Vec<_> = old.difference(&new).map(|term| (term_key(term, id), None)).collect();
+        batch.extend(new.difference(&old).map(|term| (term_key(term, id), Some(Vec::new()))));
+        batch.push((doc_key(id), Some(encode_document(document, &new))));
+        self.engine.write_batch(batch)
+    }
+
+    pub fn delete(&mut self, id: &[u8]) -> Result<()> {
+        let Some((_, old)) = self.read(id)? else {
+            return Ok(());
+        };
+        let mut batch: Vec<_> = old.iter().map(|term| (term_key(term, id), None)).collect();
+        batch.push((doc_key(id), None));
+        self.engine.write_batch(batch)
+    }
+
+    // Returns the IDs of the matching documents, in order.
+    pub fn search(&mut self, query: &Query) -> Result<Vec<Vec<u8>>> {
+        Ok(self.evaluate(query)?.into_iter().collect())
+    }
+
+    fn evaluate(&mut self, query: &Query) -> Result<BTreeSet<Vec<u8>>> {
+        match query {
+            Query::Term(term) => self.postings(&keycode::encode(&("term", term.to_lowercase()))),
+            Query::Prefix(prefix) => self.postings(&term_prefix(&prefix.to_lowercase())),
+            Query::And(queries) => {
+                let mut queries = queries.iter();
+                let Some(first) = queries.next() else {
+                    return Ok(BTreeSet::new());
+                };
+                let mut ids = self.evaluate(first)?;
+                for query in queries {
+                    if ids.is_empty() {
+                        break;
+                    }
+                    let other = self.evaluate(query)?;
+                    ids.retain(|id| other.contains(id));
+                }
+                Ok(ids)
+            }
+            Query::Or(queries) => {
+                let mut ids = BTreeSet::new();
+                for query in queries {
+                    ids.append(&mut self.evaluate(query)?);
+                }
+                Ok(ids)
+            }
+        }
+    }
+
+    // Returns a stored document and the terms it was indexed under.
+    fn read(&mut self, id: &[u8]) -> Result<Option<(Document, BTreeSet<String>)>> {
+        self.engine.get(&doc_key(id))?.map(|bytes| decode_document(&bytes)).transpose()
+    }
+
+    // Returns the document IDs of the postings under the key prefix.
+    fn postings(&mut self, prefix: &[u8]) -> Result<BTreeSet<Vec<u8>>> {
+        self.engine
+            .scan_prefix(prefix)
+            .map(|item| {
+                let (key, _) = item?;
+                let (_, _, id) = keycode::decode::<(String, String, Vec<u8>)>(&key)?;
+                Ok(id)
+            })
+            .collect()
+    }
+
+    fn terms(&self, document: &Document) -> BTreeSet<String> {
+        self.fields.iter().filter_map(|field| document.get(field)).flat_map(|text| tokenize(text)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::Memory;
+
+    fn document(fields: &[(&str, &str)]) -> Document {
+        fields.iter().map(|(name, text)| (name.to_string(), text.to_string())).collect()
+    }
+
+    #[test]
+    fn test_search() -> Result<()> {
+        let mut index = Index::new(Memory::new(), vec!["title".to_string(), "body".to_string()]);
+        let term = |term: &str| Query::Term(term.to_string());
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.as_bytes().to_vec()).collect::<Vec<_>>();
+
+        let one = document(&[("title", "Log-structured storage"), ("body", "Bitcask appends to a log."), ("tag", "db")]);
+        index.put(b"1", &one)?;
+        index.put(b"2", &document(&[("title", "Raft"), ("body", "Replicating a LOG with consensus")]))?;
+        index.put(b"3", &document(&[("title", "Storage engines")]))?;
+        assert_eq!(Some(one), index.get(b"1")?);
+
+        assert_eq!(ids(&["1", "2"]), index.search(&term("log"))?);
+        assert_eq!(ids(&[]), index.search(&term("db"))?);
+        assert_eq!(ids(&["1", "3"]), index.search(&Query::Prefix("STOR".to_string()))?);
+        assert_eq!(ids(&["1"]), index.search(&Query::And(vec![term("log"), term("storage")]))?);
+        assert_eq!(
+            ids(&["2", "3"]),
+            index.search(&Query::Or(vec![term("raft"), Query::And(vec![term("engines"), term("storage")])]))?
+        );
+        assert_eq!(ids(&[]), index.search(&Query::And(vec![]))?);
+
+        // Replacing or deleting a document drops its old postings.
+        index.put(b"2", &document(&[("title", "Raft"), ("body", "Leader election")]))?;
+        assert_eq!(ids(&["1"]), index.search(&term("log"))?);
+        assert_eq!(ids(&["2"]), index.search(&term("election"))?);
+        index.delete(b"1")?;
+        assert_eq!(ids(&[]), index.search(&term("log"))?);
+        assert_eq!(ids(&["3"]), index.search(&Query::Prefix("stor".to_string()))?);
+        assert_eq!(None, index.get(b"1")?);
+
+        // A document's postings are the terms it was indexed under, even once
+        // the indexed fields change.
+        index.fields = vec!["title".to_string()];
+        index.delete(b"2")?;
+        assert_eq!(ids(&[]), index.search(&term("election"))?);
+        index.put(b"4", &document(&[("title", "Merging"), ("body", "Hint files")]))?;
+        assert_eq!(ids(&["4"]), index.search(&term("merging"))?);
+        assert_eq!(ids(&[]), index.search(&term("hint"))?);
+        Ok(())
+    }
+}
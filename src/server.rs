@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::storage::{Engine, Status};
+
+// Frames larger than this are refused, so a corrupt or hostile length prefix
+// can't make the peer allocate arbitrary amounts of memory.
+pub const MAX_FRAME_SIZE: u32 = 64 << 20;
+
+// Scans stop adding items to a response once their keys and values reach this
+// many bytes, so a response stays well below MAX_FRAME_SIZE and the engine
+// isn't locked for the whole range.
+const SCAN_PAGE_SIZE: usize = 4 << 20;
+
+// The wire protocol: each message is a big-endian u32 length followed by that
+// many bytes of bincode. The client sends a Request and the server answers
+// with a Result<Response>, in order, over one TCP connection.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    Get(Vec<u8>),
+    Set(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+    // Returns a page of the range. Each page is read under the engine's lock
+    // separately, so later pages may see writes made in between.
+    Scan(Bound<Vec<u8>>, Bound<Vec<u8>>),
+    Status,
+    // Buffers the connection's writes until Commit, which applies them as
+    // one atomic batch, or Rollback, which discards them. Reads see the
+    // buffered writes. There is no isolation from other connections.
+    Begin,
+    Commit,
+    Rollback,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Get(Option<Vec<u8>>),
+    Set,
+    Delete,
+    // The items and, if the range has more, the key to continue after.
+    Scan(Vec<(Vec<u8>, Vec<u8>)>, Option<Vec<u8>>),
+    Status(Status),
+    Begin,
+    Commit,
+    Rollback,
+}
+
+// Serves an engine to remote clients, one thread per connection. Requests
+// are executed under the engine's lock.
+pub struct Server<E: Engine + 'static> {
+    engine: Arc<Mutex<E>>,
+}
+
+impl<E: Engine + 'static> Server<E> {
+    pub fn new(engine: Arc<Mutex<E>>) -> Self {
+        Self { engine }
+    }
+
+    // Accepts connections forever. A failed accept is logged and skipped, since
+    // it usually only concerns that connection, e.g. one reset by the peer.
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::error!("Failed to accept a connection: {}", err);
+                    continue;
+                }
+            };
+            let mut session = Session { engine: self.engine.clone(), txn: None };
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                if let Err(err) = session.serve(stream) {
+                    log::error!("Connection from {} failed: {}", peer, err);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+// Sets (Some) and deletes (None) in order, as taken by Engine::write_batch.
+type Writes = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+struct Session<E: Engine> {
+    engine: Arc<Mutex<E>>,
+    // The writes buffered since Begin, if a transaction is open.
+    txn: Option<Writes>,
+}
+
+impl<E: Engine> Session<E> {
+    fn serve(&mut self, mut stream: TcpStream) -> Result<()> {
+        while let Some(request) = read_frame(&mut stream)? {
+            let response = self.execute(request);
+            // A response too large for a frame is answered with its error
+            // instead, keeping the connection usable.
+            if let Err(err @ Error::Value(_)) = write_frame(&mut stream, &response) {
+                write_frame(&mut stream, &Err::<Response, _>(err))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn engine(&self) -> MutexGuard<'_, E> {
+        self.engine.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn execute(&mut self, request: Request) -> Result<Response> {
+        Ok(match request {
+            Request::Get(key) => {
+                let buffered = self.txn.iter().flatten().rev().find(|(k, _)| *k == key);
+                match buffered {
+                    Some((_, value)) => Response::Get(value.clone()),
+                    None => Response::Get(self.engine().get(&key)?),
+                }
+            }
+            Request::Set(key, value) => {
+                match &mut self.txn {
+                    Some(writes) => writes.push((key, Some(value))),
+                    None => self.engine().set(&key, value)?,
+                }
+                Response::Set
+            }
+            Request::Delete(key) => {
+                match &mut self.txn {
+                    Some(writes) => writes.push((key, None)),
+                    None => self.engine().delete(&key)?,
+                }
+                Response::Delete
+            }
+            Request::Scan(start, end) => {
+                let mut items = BTreeMap::new();
+                let mut size = 0;
+                let mut next = None;
+                for item in self.engine().scan((start.clone(), end.clone())) {
+                    let (key, value) = item?;
+                    if size >= SCAN_PAGE_SIZE {
+                        next = items.keys().next_back().cloned();
+                        break;
+                    }
+                    size += key.len() + value.len();
+                    items.insert(key, value);
+                }
+                // Apply the buffered writes up to where the page ends.
+                let end = next.clone().map_or(end, Bound::Included);
+                let range = (start, end);
+                for (key, value) in self.txn.iter().flatten().filter(|(key, _)| range.contains(key)) {
+                    match value {
+                        Some(value) => items.insert(key.clone(), value.clone()),
+                        None => items.remove(key),
+                    };
+                }
+                Response::Scan(items.into_iter().collect(), next)
+            }
+            Request::Status => Response::Status(self.engine().status()?),
+            Request::Begin => {
+                if self.txn.is_some() {
+                    return Err(Error::Value("a transaction is already open".to_string()));
+                }
+                self.txn = Some(Vec::new());
+                Response::Begin
+            }
+            Request::Commit => {
+                let writes = self.txn.take().ok_or_else(|| Error::Value("no transaction is open".to_string()))?;
+                self.engine().write_batch(writes)?;
+                Response::Commit
+            }
+            Request::Rollback => {
+                self.txn.take().ok_or_else(|| Error::Value("no transaction is open".to_string()))?;
+                Response::Rollback
+            }
+        })
+    }
+}
+
+// Writes a length-prefixed bincode frame.
+pub(crate) fn write_frame<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<()> {
+    let payload = bincode::serialize(message).map_err(|err| Error::Internal(err.to_string()))?;
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_SIZE)
+        .ok_or_else(|| Error::Value(format!("message of {} bytes is too large", payload.len())))?;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Reads a length-prefixed bincode frame, or None if the peer closed the
+// connection between frames.
+pub(crate) fn read_frame<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_SIZE {
+        return Err(Error::Value(format!("frame of {} bytes is too large", len)));
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    bincode::deserialize(&payload).map(Some).map_err(|err| Error::Value(format!("invalid message: {}", err)))
+}
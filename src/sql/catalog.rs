@@ -0,0 +1,65 @@
+use super::types::{Row, Table, Value};
+use crate::encoding::keycode::{self, KeyDecode, KeyEncode};
+use crate::error::{Error, Result};
+use crate::storage::Engine;
+
+// Table schemas are stored under ("table", name) and rows under ("row",
+// table, primary key), so a table's rows are a contiguous prefix scan in
+// primary key order.
+pub fn table_key(name: &str) -> Vec<u8> {
+    keycode::encode(&("table", name))
+}
+
+pub fn row_key(table: &str, id: &Value) -> Vec<u8> {
+    keycode::encode(&("row", table, id))
+}
+
+fn rows_prefix(table: &str) -> Vec<u8> {
+    keycode::encode(&("row", table))
+}
+
+// A row is its column count followed by its values.
+pub fn encode_row(row: &Row) -> Vec<u8> {
+    let mut out = Vec::new();
+    (row.len() as u32).encode_into(&mut out);
+    for value in row {
+        value.encode_into(&mut out);
+    }
+    out
+}
+
+pub fn decode_row(bytes: &[u8]) -> Result<Row> {
+    let mut input = bytes;
+    let count = u32::decode_from(&mut input)?;
+    let row = (0..count).map(|_| Value::decode_from(&mut input)).collect::<std::result::Result<_, keycode::Error>>()?;
+    if !input.is_empty() {
+        return Err(Error::Corruption(format!("row has {} trailing bytes", input.len())));
+    }
+    Ok(row)
+}
+
+pub fn read_table<E: Engine>(engine: &mut E, name: &str) -> Result<Option<Table>> {
+    Ok(engine.get(&table_key(name))?.map(|bytes| keycode::decode(&bytes)).transpose()?)
+}
+
+pub fn must_read_table<E: Engine>(engine: &mut E, name: &str) -> Result<Table> {
+    read_table(engine, name)?.ok_or_else(|| Error::Value(format!("table {} does not exist", name)))
+}
+
+pub fn create_table<E: Engine>(engine: &mut E, table: &Table) -> Result<()> {
+    if read_table(engine, &table.name)?.is_some() {
+        return Err(Error::Value(format!("table {} already exists", table.name)));
+    }
+    engine.set(&table_key(&table.name), keycode::encode(table))
+}
+
+pub fn read_row<E: Engine>(engine: &mut E, table: &Table, id: &Value) -> Result<Option<Row>> {
+    engine.get(&row_key(&table.name, id))?.map(|bytes| decode_row(&bytes)).transpose()
+}
+
+pub fn scan_rows<E: Engine>(engine: &mut E, table: &Table) -> Result<Vec<Row>> {
+    engine
+        .scan_prefix(&rows_prefix(&table.name))
+        .map(|item| item.and_then(|(_, value)| decode_row(&value)))
+        .collect()
+}
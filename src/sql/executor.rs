@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use super::catalog;
+use super::parser::Direction;
+use super::planner::{Node, Plan};
+use super::types::{Row, Table, Value};
+use crate::error::{Error, Result};
+use crate::storage::Engine;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatementResult {
+    CreateTable { name: String },
+    Insert { count: u64 },
+    Update { count: u64 },
+    Delete { count: u64 },
+    Select { columns: Vec<String>, rows: Vec<Row> },
+}
+
+// Executes a plan. Each statement's writes go to the engine as one batch, so
+// a statement is applied completely or not at all.
+pub fn execute<E: Engine>(engine: &mut E, plan: Plan) -> Result<StatementResult> {
+    match plan {
+        Plan::CreateTable(table) => {
+            catalog::create_table(engine, &table)?;
+            Ok(StatementResult::CreateTable { name: table.name })
+        }
+
+        Plan::Insert { table, rows } => {
+            let count = rows.len() as u64;
+            let mut batch = Batch::new(&table);
+            for row in rows {
+                batch.put(engine, row, false)?;
+            }
+            batch.commit(engine)?;
+            Ok(StatementResult::Insert { count })
+        }
+
+        Plan::Update { table, source, set } => {
+            let rows = execute_node(engine, source)?;
+            let count = rows.len() as u64;
+            let mut batch = Batch::new(&table);
+            // Rows whose primary key changes are moved: their old keys are
+            // deleted first, and the new keys written after the rows updated
+            // in place, so keys swapped between rows don't conflict.
+            let mut moved = Vec::new();
+            for row in rows {
+                let mut updated = row.clone();
+                for (i, expr) in &set {
+                    updated[*i] = expr.evaluate(&row)?;
+                }
+                if updated[table.primary_key] == row[table.primary_key] {
+                    batch.put(engine, updated, true)?;
+                } else {
+                    batch.delete(&row[table.primary_key]);
+                    moved.push(updated);
+                }
+            }
+            for row in moved {
+                batch.put(engine, row, false)?;
+            }
+            batch.commit(engine)?;
+            Ok(StatementResult::Update { count })
+        }
+
+        Plan::Delete { table, source } => {
+            let rows = execute_node(engine, source)?;
+            let count = rows.len() as u64;
+            let mut batch = Batch::new(&table);
+            for row in rows {
+                batch.delete(&row[table.primary_key]);
+            }
+            batch.commit(engine)?;
+            Ok(StatementResult::Delete { count })
+        }
+
+        Plan::Select { node, columns } => {
+            Ok(StatementResult::Select { columns, rows: execute_node(engine, node)? })
+        }
+    }
+}
+
+// Rows are materialized in memory, which is fine for the table sizes this
+// layer is meant for.
+fn execute_node<E: Engine>(engine: &mut E, node: Node) -> Result<Vec<Row>> {
+    match node {
+        Node::Scan { table, filter } => {
+            let rows = catalog::scan_rows(engine, &table)?;
+            let Some(filter) = filter else {
+                return Ok(rows);
+            };
+            let mut matched = Vec::new();
+            for row in rows {
+                if filter.matches(&row)? {
+                    matched.push(row);
+                }
+            }
+            Ok(matched)
+        }
+
+        Node::KeyLookup { table, id, filter } => {
+            let row = catalog::read_row(engine, &table, &id)?;
+            match (row, filter) {
+                (Some(row), Some(filter)) if !filter.matches(&row)? => Ok(Vec::new()),
+                (row, _) => Ok(row.into_iter().collect()),
+            }
+        }
+
+        Node::Order { source, orders } => {
+            let rows = execute_node(engine, *source)?;
+            let mut keyed = rows
+                .into_iter()
+                .map(|row| {
+                    let keys = orders.iter().map(|(expr, _)| expr.evaluate(&row)).collect::<Result<Vec<_>>>()?;
+                    Ok((keys, row))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            keyed.sort_by(|(a, _), (b, _)| {
+                a.iter()
+                    .zip(b)
+                    .zip(&orders)
+                    .map(|((a, b), (_, direction))| match direction {
+                        Direction::Ascending => a.sort_cmp(b),
+                        Direction::Descending => b.sort_cmp(a),
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            Ok(keyed.into_iter().map(|(_, row)| row).collect())
+        }
+
+        Node::Limit { source, limit } => {
+            let mut rows = execute_node(engine, *source)?;
+            rows.truncate(limit);
+            Ok(rows)
+        }
+
+        Node::Projection { source, expressions } => execute_node(engine, *source)?
+            .into_iter()
+            .map(|row| expressions.iter().map(|expr| expr.evaluate(&row)).collect())
+            .collect(),
+    }
+}
+
+// Stages a statement's row writes by key, so that uniqueness of primary keys
+// is checked against the state after the statement rather than row by row.
+struct Batch<'a> {
+    table: &'a Table,
+    // The new row for each written key, or None if it's deleted.
+    pending: BTreeMap<Vec<u8>, Option<Row>>,
+}
+
+impl<'a> Batch<'a> {
+    fn new(table: &'a Table) -> Self {
+        Self { table, pending: BTreeMap::new() }
+    }
+
+    // Writes the row, which must not replace an existing row unless replace
+    // is set.
+    fn put<E: Engine>(&mut self, engine: &mut E, row: Row, replace: bool) -> Result<()> {
+        self.table.validate_row(&row)?;
+        let id = &row[self.table.primary_key];
+        let key = catalog::row_key(&self.table.name, id);
+        if !replace {
+            let exists = match self.pending.get(&key) {
+                Some(row) => row.is_some(),
+                None => engine.get(&key)?.is_some(),
+            };
+            if exists {
+                return Err(Error::Value(format!("primary key {} already exists in table {}", id, self.table.name)));
+            }
+        }
+        self.pending.insert(key, Some(row));
+        Ok(())
+    }
+
+    fn delete(&mut self, id: &Value) {
+        self.pending.insert(catalog::row_key(&self.table.name, id), None);
+    }
+
+    fn commit<E: Engine>(self, engine: &mut E) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let writes = self.pending.into_iter().map(|(key, row)| (key, row.map(|row| catalog::encode_row(&row))));
+        engine.write_batch(writes.collect())
+    }
+}
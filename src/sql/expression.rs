@@ -0,0 +1,125 @@
+use std::cmp::Ordering;
+
+use super::types::{Row, Value};
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Literal(Value),
+    // A column by name, as parsed. The planner resolves it to a Field.
+    Column(String),
+    // A column by index into the row.
+    Field(usize),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+    IsNull(Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Negate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    And,
+    Or,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Expression {
+    // Replaces column names with field indexes, using resolve to look them up.
+    pub fn resolve(self, resolve: &impl Fn(&str) -> Result<usize>) -> Result<Expression> {
+        Ok(match self {
+            Expression::Column(name) => Expression::Field(resolve(&name)?),
+            Expression::Unary(op, expr) => Expression::Unary(op, Box::new(expr.resolve(resolve)?)),
+            Expression::Binary(op, lhs, rhs) => {
+                Expression::Binary(op, Box::new(lhs.resolve(resolve)?), Box::new(rhs.resolve(resolve)?))
+            }
+            Expression::IsNull(expr) => Expression::IsNull(Box::new(expr.resolve(resolve)?)),
+            expr @ (Expression::Literal(_) | Expression::Field(_)) => expr,
+        })
+    }
+
+    // Evaluates the expression against a row, using SQL's three-valued logic:
+    // NULL propagates through comparisons and arithmetic, and AND/OR only
+    // return NULL when the other operand doesn't decide the result.
+    pub fn evaluate(&self, row: &Row) -> Result<Value> {
+        use Value::*;
+        Ok(match self {
+            Expression::Literal(value) => value.clone(),
+            Expression::Column(name) => {
+                return Err(Error::Internal(format!("unresolved column {}", name)));
+            }
+            Expression::Field(i) => row
+                .get(*i)
+                .cloned()
+                .ok_or_else(|| Error::Internal(format!("field {} out of range", i)))?,
+            Expression::IsNull(expr) => Boolean(expr.evaluate(row)? == Null),
+            Expression::Unary(op, expr) => match (op, expr.evaluate(row)?) {
+                (_, Null) => Null,
+                (UnaryOp::Not, Boolean(b)) => Boolean(!b),
+                (UnaryOp::Negate, Integer(i)) => Integer(
+                    i.checked_neg().ok_or_else(|| Error::Value("integer overflow".to_string()))?,
+                ),
+                (op, value) => return Err(Error::Value(format!("can't apply {:?} to {}", op, value))),
+            },
+            Expression::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(row)?, rhs.evaluate(row)?);
+                match op {
+                    BinaryOp::And => match (lhs, rhs) {
+                        (Boolean(false), _) | (_, Boolean(false)) => Boolean(false),
+                        (Boolean(true), Boolean(true)) => Boolean(true),
+                        (Boolean(_) | Null, Boolean(_) | Null) => Null,
+                        (lhs, rhs) => return Err(Error::Value(format!("can't AND {} and {}", lhs, rhs))),
+                    },
+                    BinaryOp::Or => match (lhs, rhs) {
+                        (Boolean(true), _) | (_, Boolean(true)) => Boolean(true),
+                        (Boolean(false), Boolean(false)) => Boolean(false),
+                        (Boolean(_) | Null, Boolean(_) | Null) => Null,
+                        (lhs, rhs) => return Err(Error::Value(format!("can't OR {} and {}", lhs, rhs))),
+                    },
+                    _ if lhs == Null || rhs == Null => Null,
+                    BinaryOp::Equal => Boolean(lhs.compare(&rhs)? == Ordering::Equal),
+                    BinaryOp::NotEqual => Boolean(lhs.compare(&rhs)? != Ordering::Equal),
+                    BinaryOp::LessThan => Boolean(lhs.compare(&rhs)? == Ordering::Less),
+                    BinaryOp::LessOrEqual => Boolean(lhs.compare(&rhs)? != Ordering::Greater),
+                    BinaryOp::GreaterThan => Boolean(lhs.compare(&rhs)? == Ordering::Greater),
+                    BinaryOp::GreaterOrEqual => Boolean(lhs.compare(&rhs)? != Ordering::Less),
+                    BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide => {
+                        let (Integer(a), Integer(b)) = (&lhs, &rhs) else {
+                            return Err(Error::Value(format!("can't apply {:?} to {} and {}", op, lhs, rhs)));
+                        };
+                        let result = match op {
+                            BinaryOp::Add => a.checked_add(*b),
+                            BinaryOp::Subtract => a.checked_sub(*b),
+                            BinaryOp::Multiply => a.checked_mul(*b),
+                            _ if *b == 0 => return Err(Error::Value("division by zero".to_string())),
+                            _ => a.checked_div(*b),
+                        };
+                        Integer(result.ok_or_else(|| Error::Value("integer overflow".to_string()))?)
+                    }
+                }
+            }
+        })
+    }
+
+    // Evaluates a WHERE predicate, which only matches rows where it is TRUE.
+    pub fn matches(&self, row: &Row) -> Result<bool> {
+        match self.evaluate(row)? {
+            Value::Boolean(b) => Ok(b),
+            Value::Null => Ok(false),
+            value => Err(Error::Value(format!("predicate returned {}, not a boolean", value))),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Number(String),
+    String(String),
+    Ident(String),
+    Keyword(Keyword),
+    Comma,
+    OpenParen,
+    CloseParen,
+    Semicolon,
+    Asterisk,
+    Plus,
+    Minus,
+    Slash,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::String(s) => write!(f, "'{}'", s),
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Keyword(k) => write!(f, "{:?}", k),
+            Token::Comma => write!(f, ","),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Asterisk => write!(f, "*"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Slash => write!(f, "/"),
+            Token::Equal => write!(f, "="),
+            Token::NotEqual => write!(f, "!="),
+            Token::LessThan => write!(f, "<"),
+            Token::LessOrEqual => write!(f, "<="),
+            Token::GreaterThan => write!(f, ">"),
+            Token::GreaterOrEqual => write!(f, ">="),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keyword {
+    And,
+    Asc,
+    Boolean,
+    By,
+    Create,
+    Delete,
+    Desc,
+    False,
+    From,
+    Insert,
+    Integer,
+    Into,
+    Is,
+    Key,
+    Limit,
+    Not,
+    Null,
+    Or,
+    Order,
+    Primary,
+    Select,
+    Set,
+    String,
+    Table,
+    True,
+    Update,
+    Values,
+    Where,
+}
+
+impl Keyword {
+    fn from_str(ident: &str) -> Option<Self> {
+        Some(match ident.to_uppercase().as_str() {
+            "AND" => Keyword::And,
+            "ASC" => Keyword::Asc,
+            "BOOL" | "BOOLEAN" => Keyword::Boolean,
+            "BY" => Keyword::By,
+            "CREATE" => Keyword::Create,
+            "DELETE" => Keyword::Delete,
+            "DESC" => Keyword::Desc,
+            "FALSE" => Keyword::False,
+            "FROM" => Keyword::From,
+            "INSERT" => Keyword::Insert,
+            "INT" | "INTEGER" => Keyword::Integer,
+            "INTO" => Keyword::Into,
+            "IS" => Keyword::Is,
+            "KEY" => Keyword::Key,
+            "LIMIT" => Keyword::Limit,
+            "NOT" => Keyword::Not,
+            "NULL" => Keyword::Null,
+            "OR" => Keyword::Or,
+            "ORDER" => Keyword::Order,
+            "PRIMARY" => Keyword::Primary,
+            "SELECT" => Keyword::Select,
+            "SET" => Keyword::Set,
+            "STRING" | "TEXT" | "VARCHAR" => Keyword::String,
+            "TABLE" => Keyword::Table,
+            "TRUE" => Keyword::True,
+            "UPDATE" => Keyword::Update,
+            "VALUES" => Keyword::Values,
+            "WHERE" => Keyword::Where,
+            _ => return None,
+        })
+    }
+}
+
+// Splits a query into tokens. Keywords and unquoted identifiers are case
+// insensitive, identifiers are lowercased unless double-quoted, and string
+// literals are single-quoted with '' for a quote.
+pub struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self { chars: input.chars().peekable() }
+    }
+
+    fn next_if(&mut self, predicate: impl Fn(char) -> bool) -> Option<char> {
+        self.chars.next_if(|c| predicate(*c))
+    }
+
+    fn next_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.next_if(&predicate) {
+            s.push(c);
+        }
+        s
+    }
+
+    fn scan(&mut self) -> Result<Option<Token>> {
+        self.next_while(char::is_whitespace);
+        let Some(&c) = self.chars.peek() else {
+            return Ok(None);
+        };
+        if c.is_ascii_digit() {
+            return Ok(Some(Token::Number(self.next_while(|c| c.is_ascii_digit()))));
+        }
+        if c.is_alphabetic() || c == '_' {
+            let ident = self.next_while(|c| c.is_alphanumeric() || c == '_');
+            return Ok(Some(match Keyword::from_str(&ident) {
+                Some(keyword) => Token::Keyword(keyword),
+                None => Token::Ident(ident.to_lowercase()),
+            }));
+        }
+        if c == '\'' || c == '"' {
+            self.chars.next();
+            let quoted = self.scan_quoted(c)?;
+            return Ok(Some(if c == '\'' { Token::String(quoted) } else { Token::Ident(quoted) }));
+        }
+        self.chars.next();
+        let token = match c {
+            ',' => Token::Comma,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            ';' => Token::Semicolon,
+            '*' => Token::Asterisk,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '/' => Token::Slash,
+            '=' => Token::Equal,
+            '!' if self.next_if(|c| c == '=').is_some() => Token::NotEqual,
+            '<' if self.next_if(|c| c == '=').is_some() => Token::LessOrEqual,
+            '<' if self.next_if(|c| c == '>').is_some() => Token::NotEqual,
+            '<' => Token::LessThan,
+            '>' if self.next_if(|c| c == '=').is_some() => Token::GreaterOrEqual,
+            '>' => Token::GreaterThan,
+            c => return Err(Error::Value(format!("unexpected character {}", c))),
+        };
+        Ok(Some(token))
+    }
+
+    // Scans up to the closing quote, where a doubled quote is a literal quote.
+    fn scan_quoted(&mut self, quote: char) -> Result<String> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some(c) if c == quote => match self.next_if(|c| c == quote) {
+                    Some(c) => s.push(c),
+                    None => return Ok(s),
+                },
+                Some(c) => s.push(c),
+                None => return Err(Error::Value("unterminated quoted string".to_string())),
+            }
+        }
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scan().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lexer() -> Result<()> {
+        let tokens = Lexer::new("SELECT \"Name\", 'it''s' FROM Users WHERE id <> 10 AND x>=-1;")
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            vec![
+                Token::Keyword(Keyword::Select),
+                Token::Ident("Name".to_string()),
+                Token::Comma,
+                Token::String("it's".to_string()),
+                Token::Keyword(Keyword::From),
+                Token::Ident("users".to_string()),
+                Token::Keyword(Keyword::Where),
+                Token::Ident("id".to_string()),
+                Token::NotEqual,
+                Token::Number("10".to_string()),
+                Token::Keyword(Keyword::And),
+                Token::Ident("x".to_string()),
+                Token::GreaterOrEqual,
+                Token::Minus,
+                Token::Number("1".to_string()),
+                Token::Semicolon,
+            ],
+            tokens
+        );
+        assert!(Lexer::new("'open").collect::<Result<Vec<_>>>().is_err());
+        assert!(Lexer::new("a # b").collect::<Result<Vec<_>>>().is_err());
+        Ok(())
+    }
+}
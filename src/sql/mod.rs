@@ -0,0 +1,131 @@
+// A minimal SQL layer over any storage engine: tables with a typed schema and
+// a single-column primary key, CREATE TABLE, INSERT, SELECT with WHERE, ORDER
+// BY and LIMIT, UPDATE and DELETE. Schemas and rows are stored under keycode
+// keys (see catalog), and each statement is written as one atomic batch.
+// There are no joins, secondary indexes or multi-statement transactions.
+mod catalog;
+pub mod executor;
+pub mod expression;
+pub mod lexer;
+pub mod parser;
+pub mod planner;
+pub mod types;
+
+pub use executor::StatementResult;
+
+use crate::error::Result;
+use crate::storage::Engine;
+
+// Parses, plans and executes a single statement.
+pub fn execute<E: Engine>(engine: &mut E, query: &str) -> Result<StatementResult> {
+    let statement = parser::Parser::new(query).parse()?;
+    let plan = planner::Planner::new(engine).build(statement)?;
+    executor::execute(engine, plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::types::Value;
+    use super::*;
+    use crate::storage::bitcask::BitCask;
+    use crate::storage::memory::Memory;
+    use tempdir::TempDir;
+
+    fn select(engine: &mut impl Engine, query: &str) -> Result<Vec<Vec<Value>>> {
+        match execute(engine, query)? {
+            StatementResult::Select { rows, .. } => Ok(rows),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    fn int(i: i64) -> Value {
+        Value::Integer(i)
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_sql() -> Result<()> {
+        let mut e = Memory::new();
+        execute(&mut e, "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL, age INT, admin BOOLEAN)")?;
+        assert!(execute(&mut e, "CREATE TABLE users (id INT PRIMARY KEY)").is_err());
+        assert!(execute(&mut e, "CREATE TABLE t (a INT, b INT)").is_err());
+
+        assert_eq!(
+            StatementResult::Insert { count: 3 },
+            execute(&mut e, "INSERT INTO users VALUES (2, 'bob', 40, FALSE), (1, 'alice', 30, TRUE), (3, 'carol', NULL, FALSE)")?
+        );
+        assert_eq!(
+            StatementResult::Insert { count: 1 },
+            execute(&mut e, "INSERT INTO users (name, id) VALUES ('dave', 4)")?
+        );
+        // Failed statements write nothing.
+        assert!(execute(&mut e, "INSERT INTO users VALUES (5, 'eve', 1, TRUE), (1, 'dup', 1, TRUE)").is_err());
+        assert!(execute(&mut e, "INSERT INTO users (id) VALUES (5)").is_err());
+        assert!(execute(&mut e, "INSERT INTO users (id, name) VALUES (5, 6)").is_err());
+        assert!(execute(&mut e, "INSERT INTO nope VALUES (1)").is_err());
+
+        assert_eq!(
+            vec![
+                vec![int(1), string("alice"), int(30), Value::Boolean(true)],
+                vec![int(2), string("bob"), int(40), Value::Boolean(false)],
+                vec![int(3), string("carol"), Value::Null, Value::Boolean(false)],
+                vec![int(4), string("dave"), Value::Null, Value::Null],
+            ],
+            select(&mut e, "SELECT * FROM users")?
+        );
+        // NULL ages don't match either comparison.
+        assert_eq!(
+            vec![vec![string("bob"), int(41)]],
+            select(&mut e, "SELECT name, age + 1 FROM users WHERE age > 30 OR age <= 0")?
+        );
+        assert_eq!(
+            vec![vec![string("dave")], vec![string("carol")]],
+            select(&mut e, "SELECT name FROM users WHERE age IS NULL ORDER BY name DESC")?
+        );
+        assert_eq!(
+            vec![vec![int(3)], vec![int(4)]],
+            select(&mut e, "SELECT id FROM users ORDER BY age, id LIMIT 2")?
+        );
+        assert_eq!(vec![vec![string("bob")]], select(&mut e, "SELECT name FROM users WHERE id = 2 AND NOT admin")?);
+        assert!(select(&mut e, "SELECT name FROM users WHERE id = 2 AND admin")?.is_empty());
+        assert!(select(&mut e, "SELECT missing FROM users").is_err());
+
+        assert_eq!(StatementResult::Update { count: 2 }, execute(&mut e, "UPDATE users SET age = 0 WHERE age IS NULL")?);
+        assert!(execute(&mut e, "UPDATE users SET name = NULL WHERE id = 1").is_err());
+        // Primary keys move, and may shift onto keys the statement frees up.
+        assert_eq!(StatementResult::Update { count: 4 }, execute(&mut e, "UPDATE users SET id = id + 1")?);
+        assert!(execute(&mut e, "UPDATE users SET id = 2 WHERE id = 3").is_err());
+        assert_eq!(
+            vec![vec![int(2), string("alice")], vec![int(3), string("bob")], vec![int(4), string("carol")], vec![int(5), string("dave")]],
+            select(&mut e, "SELECT id, name FROM users")?
+        );
+
+        assert_eq!(StatementResult::Delete { count: 2 }, execute(&mut e, "DELETE FROM users WHERE age = 0")?);
+        assert_eq!(StatementResult::Delete { count: 0 }, execute(&mut e, "DELETE FROM users WHERE id = 4")?);
+        assert_eq!(vec![vec![int(2)], vec![int(3)]], select(&mut e, "SELECT id FROM users")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sql_persistence() -> Result<()> {
+        let dir = TempDir::new("sql").expect("Failed to create temporary directory");
+        let path = dir.path().join("log");
+        {
+            let mut e = BitCask::new(path.clone())?;
+            execute(&mut e, "CREATE TABLE kv (k STRING PRIMARY KEY, v INT)")?;
+            execute(&mut e, "INSERT INTO kv VALUES ('b', 2), ('a', 1), ('a\u{0}b', 3)")?;
+            // Rows of other tables don't leak into the scan.
+            execute(&mut e, "CREATE TABLE kv2 (k STRING PRIMARY KEY)")?;
+            execute(&mut e, "INSERT INTO kv2 VALUES ('z')")?;
+        }
+        let mut e = BitCask::new(path)?;
+        assert_eq!(
+            vec![vec![string("a"), int(1)], vec![string("a\u{0}b"), int(3)], vec![string("b"), int(2)]],
+            select(&mut e, "SELECT * FROM kv")?
+        );
+        Ok(())
+    }
+}
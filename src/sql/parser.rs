@@ -0,0 +1,383 @@
+use std::iter::Peekable;
+
+use super::expression::{BinaryOp, Expression, UnaryOp};
+use super::lexer::{Keyword, Lexer, Token};
+use super::types::{DataType, Value};
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    CreateTable {
+        name: String,
+        columns: Vec<ColumnDef>,
+    },
+    Insert {
+        table: String,
+        // None inserts values for every column, in table order.
+        columns: Option<Vec<String>>,
+        values: Vec<Vec<Expression>>,
+    },
+    Select {
+        // None for *.
+        select: Option<Vec<Expression>>,
+        from: String,
+        filter: Option<Expression>,
+        order_by: Vec<(Expression, Direction)>,
+        limit: Option<Expression>,
+    },
+    Update {
+        table: String,
+        set: Vec<(String, Expression)>,
+        filter: Option<Expression>,
+    },
+    Delete {
+        table: String,
+        filter: Option<Expression>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    pub datatype: DataType,
+    pub primary_key: bool,
+    // None if neither NULL nor NOT NULL was given.
+    pub nullable: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+// A recursive descent parser for one statement, with precedence climbing for
+// expressions.
+pub struct Parser<'a> {
+    lexer: Peekable<Lexer<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(query: &'a str) -> Self {
+        Self { lexer: Lexer::new(query).peekable() }
+    }
+
+    // Parses a single statement, with an optional trailing semicolon.
+    pub fn parse(mut self) -> Result<Statement> {
+        let statement = self.parse_statement()?;
+        self.next_is(Token::Semicolon);
+        if let Some(token) = self.lexer.next().transpose()? {
+            return Err(Error::Value(format!("unexpected token {} after statement", token)));
+        }
+        Ok(statement)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        self.lexer.next().unwrap_or_else(|| Err(Error::Value("unexpected end of statement".to_string())))
+    }
+
+    fn peek(&mut self) -> Result<Option<&Token>> {
+        self.lexer.peek().map(|r| r.as_ref().map_err(|err| err.clone())).transpose()
+    }
+
+    // Consumes the next token if it equals token.
+    fn next_is(&mut self, token: Token) -> bool {
+        self.lexer.next_if(|t| t.as_ref() == Ok(&token)).is_some()
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(Error::Value(format!("expected {}, found {}", expected, token))),
+        }
+    }
+
+    fn next_ident(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            token => Err(Error::Value(format!("expected identifier, found {}", token))),
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement> {
+        match self.next()? {
+            Token::Keyword(Keyword::Create) => self.parse_create_table(),
+            Token::Keyword(Keyword::Insert) => self.parse_insert(),
+            Token::Keyword(Keyword::Select) => self.parse_select(),
+            Token::Keyword(Keyword::Update) => self.parse_update(),
+            Token::Keyword(Keyword::Delete) => self.parse_delete(),
+            token => Err(Error::Value(format!("unexpected token {}", token))),
+        }
+    }
+
+    fn parse_create_table(&mut self) -> Result<Statement> {
+        self.expect(Token::Keyword(Keyword::Table))?;
+        let name = self.next_ident()?;
+        self.expect(Token::OpenParen)?;
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.parse_column()?);
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+        }
+        self.expect(Token::CloseParen)?;
+        Ok(Statement::CreateTable { name, columns })
+    }
+
+    fn parse_column(&mut self) -> Result<ColumnDef> {
+        let name = self.next_ident()?;
+        let datatype = match self.next()? {
+            Token::Keyword(Keyword::Boolean) => DataType::Boolean,
+            Token::Keyword(Keyword::Integer) => DataType::Integer,
+            Token::Keyword(Keyword::String) => DataType::String,
+            token => return Err(Error::Value(format!("expected data type, found {}", token))),
+        };
+        let mut column = ColumnDef { name, datatype, primary_key: false, nullable: None };
+        loop {
+            if self.next_is(Token::Keyword(Keyword::Primary)) {
+                self.expect(Token::Keyword(Keyword::Key))?;
+                column.primary_key = true;
+            } else if self.next_is(Token::Keyword(Keyword::Null)) {
+                column.nullable = Some(true);
+            } else if self.next_is(Token::Keyword(Keyword::Not)) {
+                self.expect(Token::Keyword(Keyword::Null))?;
+                column.nullable = Some(false);
+            } else {
+                return Ok(column);
+            }
+        }
+    }
+
+    fn parse_insert(&mut self) -> Result<Statement> {
+        self.expect(Token::Keyword(Keyword::Into))?;
+        let table = self.next_ident()?;
+        let mut columns = None;
+        if self.next_is(Token::OpenParen) {
+            let mut names = vec![self.next_ident()?];
+            while self.next_is(Token::Comma) {
+                names.push(self.next_ident()?);
+            }
+            self.expect(Token::CloseParen)?;
+            columns = Some(names);
+        }
+        self.expect(Token::Keyword(Keyword::Values))?;
+        let mut values = Vec::new();
+        loop {
+            self.expect(Token::OpenParen)?;
+            values.push(self.parse_expressions()?);
+            self.expect(Token::CloseParen)?;
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+        }
+        Ok(Statement::Insert { table, columns, values })
+    }
+
+    fn parse_select(&mut self) -> Result<Statement> {
+        let select = if self.next_is(Token::Asterisk) { None } else { Some(self.parse_expressions()?) };
+        self.expect(Token::Keyword(Keyword::From))?;
+        let from = self.next_ident()?;
+        let filter = self.parse_where()?;
+        let mut order_by = Vec::new();
+        if self.next_is(Token::Keyword(Keyword::Order)) {
+            self.expect(Token::Keyword(Keyword::By))?;
+            loop {
+                let expr = self.parse_expression(0)?;
+                let direction = if self.next_is(Token::Keyword(Keyword::Desc)) {
+                    Direction::Descending
+                } else {
+                    self.next_is(Token::Keyword(Keyword::Asc));
+                    Direction::Ascending
+                };
+                order_by.push((expr, direction));
+                if !self.next_is(Token::Comma) {
+                    break;
+                }
+            }
+        }
+        let mut limit = None;
+        if self.next_is(Token::Keyword(Keyword::Limit)) {
+            limit = Some(self.parse_expression(0)?);
+        }
+        Ok(Statement::Select { select, from, filter, order_by, limit })
+    }
+
+    fn parse_update(&mut self) -> Result<Statement> {
+        let table = self.next_ident()?;
+        self.expect(Token::Keyword(Keyword::Set))?;
+        let mut set = Vec::new();
+        loop {
+            let column = self.next_ident()?;
+            self.expect(Token::Equal)?;
+            set.push((column, self.parse_expression(0)?));
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+        }
+        let filter = self.parse_where()?;
+        Ok(Statement::Update { table, set, filter })
+    }
+
+    fn parse_delete(&mut self) -> Result<Statement> {
+        self.expect(Token::Keyword(Keyword::From))?;
+        let table = self.next_ident()?;
+        let filter = self.parse_where()?;
+        Ok(Statement::Delete { table, filter })
+    }
+
+    fn parse_where(&mut self) -> Result<Option<Expression>> {
+        if !self.next_is(Token::Keyword(Keyword::Where)) {
+            return Ok(None);
+        }
+        Ok(Some(self.parse_expression(0)?))
+    }
+
+    fn parse_expressions(&mut self) -> Result<Vec<Expression>> {
+        let mut exprs = vec![self.parse_expression(0)?];
+        while self.next_is(Token::Comma) {
+            exprs.push(self.parse_expression(0)?);
+        }
+        Ok(exprs)
+    }
+
+    // Parses an expression whose binary operators bind at least as tightly as
+    // min_precedence. Binary operators are left-associative.
+    fn parse_expression(&mut self, min_precedence: u8) -> Result<Expression> {
+        let mut lhs = if self.next_is(Token::Keyword(Keyword::Not)) {
+            Expression::Unary(UnaryOp::Not, Box::new(self.parse_expression(3)?))
+        } else if self.next_is(Token::Minus) {
+            Expression::Unary(UnaryOp::Negate, Box::new(self.parse_expression(7)?))
+        } else {
+            self.parse_atom()?
+        };
+        loop {
+            if min_precedence <= 4 && self.next_is(Token::Keyword(Keyword::Is)) {
+                let not = self.next_is(Token::Keyword(Keyword::Not));
+                self.expect(Token::Keyword(Keyword::Null))?;
+                lhs = Expression::IsNull(Box::new(lhs));
+                if not {
+                    lhs = Expression::Unary(UnaryOp::Not, Box::new(lhs));
+                }
+                continue;
+            }
+            let Some((op, precedence)) = self.peek()?.and_then(binary_op) else {
+                break;
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            self.next()?;
+            let rhs = self.parse_expression(precedence + 1)?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_atom(&mut self) -> Result<Expression> {
+        Ok(match self.next()? {
+            Token::Number(n) => Expression::Literal(Value::Integer(
+                n.parse().map_err(|_| Error::Value(format!("invalid integer {}", n)))?,
+            )),
+            Token::String(s) => Expression::Literal(Value::String(s)),
+            Token::Keyword(Keyword::True) => Expression::Literal(Value::Boolean(true)),
+            Token::Keyword(Keyword::False) => Expression::Literal(Value::Boolean(false)),
+            Token::Keyword(Keyword::Null) => Expression::Literal(Value::Null),
+            Token::Ident(name) => Expression::Column(name),
+            Token::OpenParen => {
+                let expr = self.parse_expression(0)?;
+                self.expect(Token::CloseParen)?;
+                expr
+            }
+            token => return Err(Error::Value(format!("expected expression, found {}", token))),
+        })
+    }
+}
+
+// The binary operator for a token and its precedence, from OR (1) to * and / (6).
+// NOT is 3, IS NULL 4 and unary minus 7.
+fn binary_op(token: &Token) -> Option<(BinaryOp, u8)> {
+    Some(match token {
+        Token::Keyword(Keyword::Or) => (BinaryOp::Or, 1),
+        Token::Keyword(Keyword::And) => (BinaryOp::And, 2),
+        Token::Equal => (BinaryOp::Equal, 4),
+        Token::NotEqual => (BinaryOp::NotEqual, 4),
+        Token::LessThan => (BinaryOp::LessThan, 4),
+        Token::LessOrEqual => (BinaryOp::LessOrEqual, 4),
+        Token::GreaterThan => (BinaryOp::GreaterThan, 4),
+        Token::GreaterOrEqual => (BinaryOp::GreaterOrEqual, 4),
+        Token::Plus => (BinaryOp::Add, 5),
+        Token::Minus => (BinaryOp::Subtract, 5),
+        Token::Asterisk => (BinaryOp::Multiply, 6),
+        Token::Slash => (BinaryOp::Divide, 6),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str) -> Box<Expression> {
+        Box::new(Expression::Column(name.to_string()))
+    }
+
+    fn integer(i: i64) -> Box<Expression> {
+        Box::new(Expression::Literal(Value::Integer(i)))
+    }
+
+    #[test]
+    fn test_parser() -> Result<()> {
+        assert_eq!(
+            Statement::CreateTable {
+                name: "users".to_string(),
+                columns: vec![
+                    ColumnDef { name: "id".to_string(), datatype: DataType::Integer, primary_key: true, nullable: None },
+                    ColumnDef { name: "name".to_string(), datatype: DataType::String, primary_key: false, nullable: Some(false) },
+                ],
+            },
+            Parser::new("CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL);").parse()?
+        );
+
+        // AND binds tighter than OR, * tighter than +, and NOT covers the
+        // comparison.
+        assert_eq!(
+            Statement::Delete {
+                table: "t".to_string(),
+                filter: Some(Expression::Binary(
+                    BinaryOp::Or,
+                    Box::new(Expression::Binary(BinaryOp::Equal, column("a"), Box::new(Expression::Binary(
+                        BinaryOp::Add,
+                        integer(1),
+                        Box::new(Expression::Binary(BinaryOp::Multiply, integer(2), integer(3))),
+                    )))),
+                    Box::new(Expression::Binary(
+                        BinaryOp::And,
+                        Box::new(Expression::Unary(UnaryOp::Not, Box::new(Expression::Binary(BinaryOp::LessThan, column("b"), integer(1))))),
+                        Box::new(Expression::Unary(UnaryOp::Not, Box::new(Expression::IsNull(column("c"))))),
+                    )),
+                )),
+            },
+            Parser::new("delete from t where a = 1 + 2 * 3 or not b < 1 and c is not null").parse()?
+        );
+
+        // Subtraction is left-associative.
+        assert_eq!(
+            Statement::Update {
+                table: "t".to_string(),
+                set: vec![("a".to_string(), Expression::Binary(
+                    BinaryOp::Subtract,
+                    Box::new(Expression::Binary(BinaryOp::Subtract, integer(1), integer(2))),
+                    integer(3),
+                ))],
+                filter: None,
+            },
+            Parser::new("UPDATE t SET a = 1 - 2 - 3").parse()?
+        );
+
+        assert!(Parser::new("SELECT * FROM").parse().is_err());
+        assert!(Parser::new("SELECT * FROM t t").parse().is_err());
+        assert!(Parser::new("DROP TABLE t").parse().is_err());
+        Ok(())
+    }
+}
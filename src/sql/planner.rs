@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+
+use super::catalog;
+use super::expression::{BinaryOp, Expression};
+use super::parser::{Direction, Statement};
+use super::types::{Column, Row, Table, Value};
+use crate::error::{Error, Result};
+use crate::storage::Engine;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Plan {
+    CreateTable(Table),
+    Insert { table: Table, rows: Vec<Row> },
+    // Each assignment is a column index and an expression over the old row.
+    Update { table: Table, source: Node, set: Vec<(usize, Expression)> },
+    Delete { table: Table, source: Node },
+    Select { node: Node, columns: Vec<String> },
+}
+
+// A tree of operators producing rows. Expressions are resolved against the
+// rows of the node's source.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Scan { table: Table, filter: Option<Expression> },
+    // Reads the row with the given primary key, if it matches the filter.
+    KeyLookup { table: Table, id: Value, filter: Option<Expression> },
+    Order { source: Box<Node>, orders: Vec<(Expression, Direction)> },
+    Limit { source: Box<Node>, limit: usize },
+    Projection { source: Box<Node>, expressions: Vec<Expression> },
+}
+
+// Builds plans from statements, resolving tables against the catalog and
+// columns to row indexes.
+pub struct Planner<'a, E: Engine> {
+    engine: &'a mut E,
+}
+
+impl<'a, E: Engine> Planner<'a, E> {
+    pub fn new(engine: &'a mut E) -> Self {
+        Self { engine }
+    }
+
+    pub fn build(&mut self, statement: Statement) -> Result<Plan> {
+        match statement {
+            Statement::CreateTable { name, columns } => {
+                let primary_keys: Vec<_> =
+                    columns.iter().enumerate().filter(|(_, c)| c.primary_key).map(|(i, _)| i).collect();
+                let [primary_key] = primary_keys[..] else {
+                    return Err(Error::Value(format!("table {} must have exactly one primary key", name)));
+                };
+                let mut names = HashSet::new();
+                let mut table_columns = Vec::new();
+                for (i, column) in columns.into_iter().enumerate() {
+                    if !names.insert(column.name.clone()) {
+                        return Err(Error::Value(format!("duplicate column {}", column.name)));
+                    }
+                    if i == primary_key && column.nullable == Some(true) {
+                        return Err(Error::Value(format!("primary key {} can't be nullable", column.name)));
+                    }
+                    let nullable = column.nullable.unwrap_or(i != primary_key);
+                    table_columns.push(Column { name: column.name, datatype: column.datatype, nullable });
+                }
+                Ok(Plan::CreateTable(Table { name, columns: table_columns, primary_key }))
+            }
+
+            Statement::Insert { table, columns, values } => {
+                let table = catalog::must_read_table(self.engine, &table)?;
+                let indexes = match columns {
+                    Some(columns) => columns.iter().map(|c| table.column_index(c)).collect::<Result<Vec<_>>>()?,
+                    None => (0..table.columns.len()).collect(),
+                };
+                let rows = values
+                    .into_iter()
+                    .map(|exprs| {
+                        if exprs.len() != indexes.len() {
+                            return Err(Error::Value(format!(
+                                "expected {} values, got {}",
+                                indexes.len(),
+                                exprs.len()
+                            )));
+                        }
+                        // Unlisted columns are NULL.
+                        let mut row = vec![Value::Null; table.columns.len()];
+                        for (i, expr) in indexes.iter().zip(exprs) {
+                            row[*i] = constant(expr)?;
+                        }
+                        Ok(row)
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(Plan::Insert { table, rows })
+            }
+
+            Statement::Select { select, from, filter, order_by, limit } => {
+                let table = catalog::must_read_table(self.engine, &from)?;
+                let columns = match &select {
+                    None => table.columns.iter().map(|c| c.name.clone()).collect(),
+                    Some(exprs) => exprs
+                        .iter()
+                        .map(|expr| match expr {
+                            Expression::Column(name) => name.clone(),
+                            _ => "?".to_string(),
+                        })
+                        .collect(),
+                };
+                let resolve = |name: &str| table.column_index(name);
+                let orders = order_by
+                    .into_iter()
+                    .map(|(expr, direction)| Ok((expr.resolve(&resolve)?, direction)))
+                    .collect::<Result<Vec<_>>>()?;
+                let expressions =
+                    select.map(|exprs| exprs.into_iter().map(|e| e.resolve(&resolve)).collect::<Result<Vec<_>>>());
+                let expressions = expressions.transpose()?;
+                let limit = limit
+                    .map(|expr| match constant(expr)? {
+                        Value::Integer(n) if n >= 0 => Ok(n as usize),
+                        value => Err(Error::Value(format!("invalid LIMIT {}", value))),
+                    })
+                    .transpose()?;
+
+                let mut node = self.build_source(table, filter)?;
+                if !orders.is_empty() {
+                    node = Node::Order { source: Box::new(node), orders };
+                }
+                if let Some(limit) = limit {
+                    node = Node::Limit { source: Box::new(node), limit };
+                }
+                if let Some(expressions) = expressions {
+                    node = Node::Projection { source: Box::new(node), expressions };
+                }
+                Ok(Plan::Select { node, columns })
+            }
+
+            Statement::Update { table, set, filter } => {
+                let table = catalog::must_read_table(self.engine, &table)?;
+                let resolve = |name: &str| table.column_index(name);
+                let set = set
+                    .into_iter()
+                    .map(|(column, expr)| Ok((table.column_index(&column)?, expr.resolve(&resolve)?)))
+                    .collect::<Result<_>>()?;
+                let source = self.build_source(table.clone(), filter)?;
+                Ok(Plan::Update { table, source, set })
+            }
+
+            Statement::Delete { table, filter } => {
+                let table = catalog::must_read_table(self.engine, &table)?;
+                let source = self.build_source(table.clone(), filter)?;
+                Ok(Plan::Delete { table, source })
+            }
+        }
+    }
+
+    // Reads the table's rows matching the filter, as a key lookup if the
+    // filter pins the primary key to a constant and a full scan otherwise.
+    fn build_source(&mut self, table: Table, filter: Option<Expression>) -> Result<Node> {
+        let filter = filter.map(|expr| expr.resolve(&|name: &str| table.column_index(name))).transpose()?;
+        if let Some(id) = filter.as_ref().and_then(|f| primary_key_lookup(f, table.primary_key)) {
+            return Ok(Node::KeyLookup { table, id, filter });
+        }
+        Ok(Node::Scan { table, filter })
+    }
+}
+
+// Evaluates an expression that can't reference columns, e.g. an INSERT value.
+fn constant(expr: Expression) -> Result<Value> {
+    expr.resolve(&|name: &str| Err(Error::Value(format!("unexpected column {}", name))))?.evaluate(&Vec::new())
+}
+
+// Returns the primary key value if the filter is pk = constant, possibly
+// ANDed with other conditions.
+fn primary_key_lookup(filter: &Expression, primary_key: usize) -> Option<Value> {
+    match filter {
+        Expression::Binary(BinaryOp::And, lhs, rhs) => {
+            primary_key_lookup(lhs, primary_key).or_else(|| primary_key_lookup(rhs, primary_key))
+        }
+        Expression::Binary(BinaryOp::Equal, lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+            (Expression::Field(i), Expression::Literal(value))
+            | (Expression::Literal(value), Expression::Field(i))
+                if *i == primary_key && *value != Value::Null =>
+            {
+                Some(value.clone())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
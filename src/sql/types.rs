@@ -0,0 +1,202 @@
+use std::cmp::Ordering;
+
+use crate::encoding::keycode::{self, KeyDecode, KeyEncode};
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataType {
+    Boolean,
+    Integer,
+    String,
+}
+
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::Boolean => write!(f, "BOOLEAN"),
+            DataType::Integer => write!(f, "INTEGER"),
+            DataType::String => write!(f, "STRING"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+}
+
+pub type Row = Vec<Value>;
+
+impl Value {
+    // None for NULL, which has every type.
+    pub fn datatype(&self) -> Option<DataType> {
+        match self {
+            Value::Null => None,
+            Value::Boolean(_) => Some(DataType::Boolean),
+            Value::Integer(_) => Some(DataType::Integer),
+            Value::String(_) => Some(DataType::String),
+        }
+    }
+
+    // Compares values of the same type. Fails for values of different types,
+    // and should not be called with NULL, which compares as unknown in SQL.
+    pub fn compare(&self, other: &Value) -> Result<Ordering> {
+        match (self, other) {
+            (Value::Boolean(a), Value::Boolean(b)) => Ok(a.cmp(b)),
+            (Value::Integer(a), Value::Integer(b)) => Ok(a.cmp(b)),
+            (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+            (a, b) => Err(Error::Value(format!("can't compare {} and {}", a, b))),
+        }
+    }
+
+    // A total order for sorting, with NULL first and values of different
+    // types ordered by type.
+    pub fn sort_cmp(&self, other: &Value) -> Ordering {
+        self.compare(other).unwrap_or_else(|_| self.tag().cmp(&other.tag()))
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Value::Null => 0x00,
+            Value::Boolean(_) => 0x01,
+            Value::Integer(_) => 0x02,
+            Value::String(_) => 0x03,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Boolean(true) => write!(f, "TRUE"),
+            Value::Boolean(false) => write!(f, "FALSE"),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// A type tag followed by the value, so primary keys of one type sort in
+// their natural order.
+impl KeyEncode for Value {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.tag().encode_into(out);
+        match self {
+            Value::Null => {}
+            Value::Boolean(b) => b.encode_into(out),
+            Value::Integer(i) => i.encode_into(out),
+            Value::String(s) => s.encode_into(out),
+        }
+    }
+}
+
+impl KeyDecode for Value {
+    fn decode_from(input: &mut &[u8]) -> std::result::Result<Self, keycode::Error> {
+        match u8::decode_from(input)? {
+            0x00 => Ok(Value::Null),
+            0x01 => Ok(Value::Boolean(bool::decode_from(input)?)),
+            0x02 => Ok(Value::Integer(i64::decode_from(input)?)),
+            0x03 => Ok(Value::String(String::decode_from(input)?)),
+            tag => Err(keycode::Error::Invalid(format!("invalid value tag {:#04x}", tag))),
+        }
+    }
+}
+
+impl KeyEncode for DataType {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        let tag: u8 = match self {
+            DataType::Boolean => 0x01,
+            DataType::Integer => 0x02,
+            DataType::String => 0x03,
+        };
+        tag.encode_into(out)
+    }
+}
+
+impl KeyDecode for DataType {
+    fn decode_from(input: &mut &[u8]) -> std::result::Result<Self, keycode::Error> {
+        match u8::decode_from(input)? {
+            0x01 => Ok(DataType::Boolean),
+            0x02 => Ok(DataType::Integer),
+            0x03 => Ok(DataType::String),
+            tag => Err(keycode::Error::Invalid(format!("invalid data type tag {:#04x}", tag))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+    // Index of the primary key column, whose values are unique and not NULL.
+    pub primary_key: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub datatype: DataType,
+    pub nullable: bool,
+}
+
+impl Table {
+    pub fn column_index(&self, name: &str) -> Result<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.name == name)
+            .ok_or_else(|| Error::Value(format!("unknown column {} in table {}", name, self.name)))
+    }
+
+    // Checks that the row has a value of the right type for every column.
+    pub fn validate_row(&self, row: &Row) -> Result<()> {
+        if row.len() != self.columns.len() {
+            return Err(Error::Value(format!(
+                "table {} has {} columns, got {} values",
+                self.name,
+                self.columns.len(),
+                row.len()
+            )));
+        }
+        for (column, value) in self.columns.iter().zip(row) {
+            match value.datatype() {
+                None if !column.nullable => {
+                    return Err(Error::Value(format!("column {} can't be NULL", column.name)));
+                }
+                Some(datatype) if datatype != column.datatype => {
+                    return Err(Error::Value(format!(
+                        "column {} has type {}, got {}",
+                        column.name, column.datatype, datatype
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KeyEncode for Table {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        (&self.name, self.primary_key as u32, self.columns.len() as u32).encode_into(out);
+        for column in &self.columns {
+            (&column.name, column.datatype, column.nullable).encode_into(out);
+        }
+    }
+}
+
+impl KeyDecode for Table {
+    fn decode_from(input: &mut &[u8]) -> std::result::Result<Self, keycode::Error> {
+        let (name, primary_key, count) = <(String, u32, u32)>::decode_from(input)?;
+        let columns = (0..count)
+            .map(|_| {
+                let (name, datatype, nullable) = KeyDecode::decode_from(input)?;
+                Ok(Column { name, datatype, nullable })
+            })
+            .collect::<std::result::Result<_, keycode::Error>>()?;
+        Ok(Table { name, columns, primary_key: primary_key as usize })
+    }
+}
@@ -1,32 +1,341 @@
-
-
-use std::collections::btree_map::Values;
-use std::fmt::write;
-use std::fs;
-use std::io::{SeekFrom, Seek, BufWriter, Write, Read, BufReader};
-use std::iter::Scan;
-use std::ops::RangeBounds;
-use std::path::PathBuf;
+use std::io::{SeekFrom, Seek, Read, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use std::vec::Vec;
-use fs4::FileExt;
+use lndb_core::entry;
 use log::{info};
+use serde::{Deserialize, Serialize};
 use super::Status;
 
-use crate::error::Result;
+use crate::clock::{self, Clock};
+use crate::error::{Error, Result};
 use super::Engine;
+use super::scheduler::{IoClass, IoScheduler};
+use super::vfs::{self, StdFs, Vfs};
+
+mod scrub;
+mod shared;
+mod worker;
+pub use scrub::{RepairSource, ScrubReport};
+pub use shared::SharedReader;
+pub use worker::{CompactionWorker, Scrubber};
+
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    // How long a tombstone survives compaction after the delete, so replicas
+    // and backups that catch up late still learn about it. Compaction records
+    // the deletion time of retained tombstones in the hint file; tombstones
+    // found when opening the log without one are assumed to have been
+    // written at open time.
+    pub tombstone_retention: Duration,
+    pub clock: Arc<dyn Clock>,
+    pub vfs: Arc<dyn Vfs>,
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    pub io_scheduler: Option<Arc<IoScheduler>>,
+    pub compaction_schedule: CompactionSchedule,
+    pub write_validator: Option<Arc<dyn WriteValidator>>,
+    // Values up to this many bytes are kept in memory next to the keydir once
+    // written or read, so tiny-value workloads avoid disk reads. 0 disables it.
+    pub inline_value_size: usize,
+    // Upper bound on the key and value bytes held inline.
+    pub inline_memory_budget: u64,
+    pub write_coalescing: Option<WriteCoalescing>,
+    // The active log segment is sealed and a new one started once an append
+    // would grow it past this many bytes.
+    pub max_segment_size: u64,
+    pub sync_policy: SyncPolicy,
+    // Consecutive failed writes or syncs after which the store turns
+    // read-only, refusing writes with Error::Degraded until recover succeeds,
+    // rather than keep hammering a failing disk. 0 never degrades.
+    pub max_write_failures: u32,
+    // Records in new active segments start at multiples of this many bytes
+    // and are zero-padded up to the next one, e.g. 4096 so a torn write never
+    // spans a record that was already acknowledged, and the file can be read
+    // with direct I/O. Must be a power of two; 0 or 1 disables it. Compaction
+    // writes unaligned segments, and the padding counts as garbage.
+    pub record_alignment: u32,
+    // Reserves max_segment_size bytes of disk for each new active segment up
+    // front, so appends don't allocate blocks and the segment is laid out
+    // contiguously. The file's size still only covers the written records,
+    // and the unused reservation is released when the segment is sealed.
+    // Ignored on filesystems without fallocate.
+    pub preallocate_segments: bool,
+    // Opens the store without writing to it: writes and compaction are
+    // refused, and the store is locked shared rather than exclusively, so
+    // several read-only handles can be open at once, but not alongside a
+    // writer. BitCask::open_read_only also refuses writes, but takes no lock
+    // at all, so it can read a snapshot while a writer has the store open.
+    pub read_only: bool,
+    // Publishes the keydir to path.keydir for SharedReaders when the store is
+    // opened, a segment is sealed and a merge finishes, besides explicit
+    // calls to BitCask::publish_keydir.
+    pub publish_keydir: bool,
+    // Counts gets per region of the log and saves the most read regions to
+    // path.access on drop, for warm_up to read ahead after the next open.
+    pub track_reads: bool,
+}
 
+// When appended entries are synced to disk. Until then an acknowledged write
+// only lives in the OS page cache and is lost if the machine crashes, though
+// not if just the process does. Write batches are always synced, and sealed
+// segments are unless the policy is Never.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SyncPolicy {
+    // Leave writeback to the OS.
+    Never,
+    EveryWrite,
+    // Sync on the first write once this long has passed since the last sync,
+    // bounding the window of writes a crash can lose on a busy store.
+    Interval(Duration),
+}
 
+// Buffers writes in memory and only appends the latest write of each key
+// when the buffer is flushed, so a storm of overwrites of the same key (e.g. a
+// counter) produces one log entry instead of one per write. Buffered writes
+// are lost if the process crashes before the flush.
+//
+// The buffer is flushed by the first write after `interval` has passed since
+// the oldest buffered write, once it holds more than `max_bytes`, before scans
+// and compaction, by BitCask::flush and when the store is dropped. Status and
+// parallel scans only reflect flushed writes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteCoalescing {
+    pub interval: Duration,
+    pub max_bytes: usize,
+}
 
-struct BitCask {
-    log: Log,
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tombstone_retention: Duration::ZERO,
+            clock: clock::system(),
+            vfs: Arc::new(StdFs),
+            compaction_filter: None,
+            io_scheduler: None,
+            compaction_schedule: CompactionSchedule::default(),
+            write_validator: None,
+            inline_value_size: 0,
+            inline_memory_budget: 64 << 20,
+            write_coalescing: None,
+            max_segment_size: 256 << 20,
+            sync_policy: SyncPolicy::Never,
+            max_write_failures: 5,
+            record_alignment: 0,
+            preallocate_segments: false,
+            read_only: false,
+            publish_keydir: false,
+            track_reads: false,
+        }
+    }
+}
+
+// When maybe_compact is allowed to compact the log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CompactionSchedule {
+    // Minimum fraction of the log that must be garbage.
+    pub garbage_ratio: f64,
+    // Times of day (UTC) during which compaction may run, or any time if
+    // empty.
+    pub windows: Vec<CompactionWindow>,
+    // Only compact while the store serves fewer operations per second than
+    // this, measured since the previous maybe_compact call.
+    pub max_ops_per_sec: Option<f64>,
+}
+
+impl Default for CompactionSchedule {
+    fn default() -> Self {
+        Self { garbage_ratio: 0.2, windows: Vec::new(), max_ops_per_sec: None }
+    }
+}
+
+// A time-of-day window given as offsets from midnight UTC. A window whose end
+// is before its start wraps around midnight, e.g. 22:00-02:00.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CompactionWindow {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl CompactionWindow {
+    pub fn hours(start: u64, end: u64) -> Self {
+        Self { start: Duration::from_secs(start * 3600), end: Duration::from_secs(end * 3600) }
+    }
+
+    fn contains(&self, now: Duration) -> bool {
+        let time_of_day = Duration::from_secs(now.as_secs() % 86400);
+        if self.start <= self.end {
+            self.start <= time_of_day && time_of_day < self.end
+        } else {
+            self.start <= time_of_day || time_of_day < self.end
+        }
+    }
+}
+
+// Called for every live entry during compaction, e.g. to purge expired or
+// soft-deleted application data without a separate delete pass. Dropped keys
+// are treated as deleted, including tombstone retention.
+pub trait CompactionFilter: std::fmt::Debug + Send + Sync {
+    fn filter(&self, key: &[u8], value: &[u8]) -> FilterDecision;
+}
+
+// Checks every set and delete before it is written, e.g. to enforce size
+// limits, a value schema or forbidden key prefixes at the storage boundary.
+// Returning an error rejects the write with Error::Rejected and that reason.
+pub trait WriteValidator: std::fmt::Debug + Send + Sync {
+    // value is None for deletes.
+    fn validate(&self, key: &[u8], value: Option<&[u8]>) -> std::result::Result<(), String>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterDecision {
+    Keep,
+    Drop,
+    Rewrite(Vec<u8>),
+}
+
+pub struct BitCask {
+    path: PathBuf,
+    // Log segments by file ID. The newest is the active segment, stored at
+    // path and appended to, the others are sealed and stored at path.<id>.
+    segments: Segments,
+    active_size: u64,
     keydir: KeyDir,
+    // Deletion time of tombstones retained through compaction, only tracked
+    // when tombstone_retention is set.
+    tombstones: Tombstones,
+    options: Options,
+    logical_bytes_written: u64,
+    physical_bytes_written: u64,
+    // Number of gets per ACCESS_CHUNK-sized region of each segment, saved on
+    // drop to guide warm_up after the next open. Only tracked when
+    // track_reads is set.
+    chunk_reads: std::collections::HashMap<(u64, u64), u64>,
+    // Operations served, and the time and count at the last maybe_compact
+    // call, to measure load for the compaction schedule.
+    ops: u64,
+    last_load_sample: (Duration, u64),
+    inline_values: std::collections::HashMap<Vec<u8>, Vec<u8>>,
+    inline_bytes: u64,
+    // Writes buffered for coalescing (None for deletes), their key and value
+    // bytes, and when the oldest of them was buffered.
+    pending: std::collections::BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    pending_bytes: usize,
+    pending_since: Duration,
+    // Whether the active segment has appends that haven't been synced, and
+    // when it was last synced.
+    unsynced: bool,
+    last_sync: Duration,
+    // Whether a merge job was started and not yet finished.
+    merging: bool,
+    // Consecutive failed appends and syncs, and whether they made the store
+    // read-only.
+    write_failures: u32,
+    degraded: bool,
+    // Time, total_disk_size and garbage_disk_size when the store was opened
+    // or last compacted, the baseline for the growth forecast.
+    growth_baseline: (Duration, u64, u64),
+    // The lock on path.lock, held while the store is open.
+    _lock_file: Option<Box<dyn vfs::File>>,
 }
 
 impl BitCask {
     pub fn new(path: PathBuf) -> Result<Self> {
-        let mut log = Log::new(path)?;
-        let keydir = log.build_keydir()?;
-        Ok(Self {log, keydir})
+        Self::new_with_options(path, Options::default())
+    }
+
+    pub fn new_with_options(path: PathBuf, options: Options) -> Result<Self> {
+        Self::open(path, options, &mut |_| {})
+    }
+
+    // Opens the store, reporting progress while the keydir is rebuilt so callers
+    // can surface recovery status for large logs.
+    pub fn new_with_progress(path: PathBuf, mut progress: impl FnMut(&Progress)) -> Result<Self> {
+        Self::open(path, Options::default(), &mut progress)
+    }
+
+    // Opens a snapshot of the store as of now for reading, e.g. for backups
+    // or analytics, without modifying any of its files. Writes are refused as
+    // with Options::read_only, which this sets, but the store and segments
+    // aren't locked, not even shared, so this works while a writer has the
+    // store open, and the writer isn't kept out. Writes made after opening
+    // aren't seen. A torn tail, possibly a write in progress, is ignored
+    // rather than truncated.
+    pub fn open_read_only(path: PathBuf) -> Result<Self> {
+        Self::open_read_only_with_options(path, Options::default())
+    }
+
+    pub fn open_read_only_with_options(path: PathBuf, options: Options) -> Result<Self> {
+        let options = Options { read_only: true, ..options };
+        Self::open_with_lock(path, options, LockMode::Unlocked, &mut |_| {})
+    }
+
+    fn open(path: PathBuf, options: Options, progress: &mut dyn FnMut(&Progress)) -> Result<Self> {
+        let lock = match options.read_only {
+            true => LockMode::Shared,
+            false => LockMode::Exclusive,
+        };
+        Self::open_with_lock(path, options, lock, progress)
+    }
+
+    fn open_with_lock(
+        path: PathBuf,
+        options: Options,
+        lock: LockMode,
+        progress: &mut dyn FnMut(&Progress),
+    ) -> Result<Self> {
+        if options.record_alignment > 1 && !options.record_alignment.is_power_of_two() {
+            return Err(Error::Value(format!(
+                "record alignment {} is not a power of two",
+                options.record_alignment
+            )));
+        }
+        let lock_file = lock_store(&options.vfs, &path, lock)?;
+        let (mut segments, hints) = open_segments(&options.vfs, &path, options.record_alignment, lock)?;
+        let track_tombstones = !options.tombstone_retention.is_zero();
+        let (keydir, deleted) =
+            build_keydir(&options.vfs, &path, &mut segments, &hints, track_tombstones, progress)?;
+        let active_size = match segments.values().next_back() {
+            Some(log) => log.file.size()?,
+            None => 0,
+        };
+        let now = options.clock.now();
+        let tombstones = deleted
+            .into_iter()
+            .map(|(key, (deleted_at, file_id))| (key, (if deleted_at.is_zero() { now } else { deleted_at }, file_id)))
+            .collect();
+        let mut bitcask = Self {
+            ops: 0,
+            last_load_sample: (now, 0),
+            inline_values: std::collections::HashMap::new(),
+            inline_bytes: 0,
+            pending: std::collections::BTreeMap::new(),
+            pending_bytes: 0,
+            pending_since: Duration::ZERO,
+            unsynced: false,
+            last_sync: now,
+            merging: false,
+            write_failures: 0,
+            degraded: false,
+            growth_baseline: (now, 0, 0),
+            path,
+            segments,
+            active_size,
+            keydir,
+            tombstones,
+            options,
+            logical_bytes_written: 0,
+            physical_bytes_written: 0,
+            chunk_reads: std::collections::HashMap::new(),
+            _lock_file: lock_file,
+        };
+        bitcask.reset_growth_baseline()?;
+        bitcask.preallocate_active();
+        if bitcask.options.publish_keydir && !bitcask.options.read_only {
+            bitcask.publish_keydir()?;
+        }
+        Ok(bitcask)
     }
 
     pub fn new_with_compact(path: PathBuf, garbage_ratio: f64) -> Result<Self> {
@@ -36,13 +345,13 @@ impl BitCask {
         if status.garbage_disk_size as f64 / status.total_disk_size as f64> garbage_ratio {
             log::info!(
                 "Compacting {} to remove {:.3}MB garbage ({:.0}% of {:.3}MB)",
-                bitcask.log.path.display(),
+                bitcask.path.display(),
                 status.garbage_disk_size / 1024 / 1024,
                 garbage_ratio * 100.0,
                 status.total_disk_size / 1024 / 1024
             );
             
-            bitcask.compact();
+            bitcask.compact()?;
         }
 
         Ok(bitcask)
@@ -54,29 +363,94 @@ impl Engine for BitCask {
 
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
         info!("Write key {:?}, value {:?}", key, value);
-        let (value_pos, value_len)  = self.log.write_entry(key, Some(&*value))?;
-        self.keydir.insert(key.to_vec(), (value_pos, value_len));
+        self.ops += 1;
+        self.check_writable()?;
+        self.validate(key, Some(&value))?;
+        let bytes = (key.len() + value.len()) as u64;
+        if self.options.write_coalescing.is_some() {
+            self.buffer(key, Some(value))?;
+        } else {
+            self.write_set(key, &value)?;
+        }
+        self.logical_bytes_written += bytes;
         Ok(())
     }
 
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        if let Some((value_pos, value_len)) = self.keydir.get(key) {
-            Ok(Some(self.log.read_entry(*value_pos, *value_len)?))
-        } else {
-            Ok(None)
+        self.ops += 1;
+        if let Some(value) = self.pending.get(key) {
+            return Ok(value.clone());
+        }
+        if let Some(value) = self.inline_values.get(key) {
+            return Ok(Some(value.clone()));
         }
+        let Some(&(file_id, value_pos, value_len)) = self.keydir.get(key) else {
+            return Ok(None);
+        };
+        if self.options.track_reads {
+            *self.chunk_reads.entry((file_id, value_pos / ACCESS_CHUNK)).or_default() += 1;
+        }
+        self.throttle(IoClass::Foreground, value_len as u64);
+        let value = read_value(&self.segments, key, (file_id, value_pos, value_len))?;
+        self.inline(key, &value);
+        Ok(Some(value))
     }
 
     fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.log.write_entry(key, None)?;
-        self.keydir.remove(key);
+        self.ops += 1;
+        self.check_writable()?;
+        self.validate(key, None)?;
+        if self.options.write_coalescing.is_some() {
+            self.buffer(key, None)?;
+        } else {
+            self.write_delete(key)?;
+        }
+        self.logical_bytes_written += key.len() as u64;
+        Ok(())
+    }
+
+    // Appends the whole batch with a single write and sync, and only updates
+    // the keydir once it is durable. Writes buffered for coalescing are
+    // flushed first.
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        self.ops += batch.len() as u64;
+        self.check_writable()?;
+        for (key, value) in &batch {
+            self.validate(key, value.as_deref())?;
+        }
+        self.flush()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let entries = self.append_batch(&batch)?;
+        self.logical_bytes_written += batch
+            .iter()
+            .map(|(key, value)| (key.len() + value.as_ref().map_or(0, |v| v.len())) as u64)
+            .sum::<u64>();
+        for ((key, value), entry) in batch.iter().zip(entries) {
+            match value {
+                Some(value) => self.index_set(key, value, entry),
+                None => self.index_delete(key, entry.0),
+            }
+        }
         Ok(())
     }
 
+    fn sync(&mut self) -> Result<()> {
+        BitCask::sync(self)
+    }
+
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_>
         where 
             Self: Sized {
-        ScanIterator { inner: self.keydir.range(range), log: &mut self.log }
+        let error = self.flush().err();
+        ScanIterator {
+            inner: self.keydir.range(range),
+            segments: &self.segments,
+            inline_values: &self.inline_values,
+            error,
+            failed: false,
+        }
     }
 
     fn scan_dyn(
@@ -86,226 +460,1776 @@ impl Engine for BitCask {
         Box::new(self.scan(range))
     }
 
+    fn hint_sequential(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Result<()> {
+        let mut spans = std::collections::BTreeMap::new();
+        for (key, (file_id, value_pos, value_len)) in self.keydir.range(range) {
+            let start = value_pos.saturating_sub(entry::CHECKSUM_SIZE + entry::HEADER_SIZE + key.len() as u64);
+            let (start, end) = (start, value_pos + *value_len as u64);
+            let span = spans.entry(*file_id).or_insert((start, end));
+            *span = (span.0.min(start), span.1.max(end));
+        }
+        for (file_id, (start, end)) in spans {
+            if let Some(log) = self.segments.get(&file_id) {
+                log.file.readahead(start, end - start)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sample_keys(&mut self, n: usize) -> Result<Vec<Vec<u8>>> {
+        self.flush()?;
+        let sample = super::reservoir_sample(self.keydir.keys().map(Ok), n)?;
+        Ok(sample.into_iter().cloned().collect())
+    }
+
+    // The keydir is fully built in memory at open, so warming up only reads
+    // ahead the log regions that were read most before the last shutdown.
+    fn warm_up(&mut self) -> Result<()> {
+        let path = self.access_path();
+        if !self.options.vfs.read_dir(path.parent().unwrap_or(Path::new(".")))?.contains(&path) {
+            return Ok(());
+        }
+        let file = self.options.vfs.open(&path)?;
+        let mut data = vec![0; file.size()? as usize];
+        file.read_exact_at(&mut data, 0)?;
+        for chunk in data.chunks_exact(16) {
+            let file_id = u64::from_be_bytes(chunk[..8].try_into().unwrap_or_default());
+            let start = u64::from_be_bytes(chunk[8..].try_into().unwrap_or_default()) * ACCESS_CHUNK;
+            let Some(log) = self.segments.get(&file_id) else {
+                continue;
+            };
+            let log_size = log.file.size()?;
+            if start < log_size {
+                log.file.readahead(start, ACCESS_CHUNK.min(log_size - start))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_compacting(&self) -> bool {
+        self.merging
+    }
+
     fn status(&self) -> Result<super::Status> {
         let keys = self.keydir.len() as u64;
-        let total_disk_size = self.log.file.metadata()?.len();
+        let total_disk_size = self.segments.values().try_fold(0, |size, log| {
+            Ok::<_, Error>(size + log.file.size()?)
+        })?;
         let size = self.keydir
             .iter()
-            .fold(0, |size, (key, (_, value_len))|
+            .fold(0, |size, (key, (_, _, value_len))|
             size + key.len() as u64 + *value_len as u64
         );
-        let live_disk_size = size + 8 * keys as u64;
-        let garbage_disk_size = total_disk_size - live_disk_size;
+        // Entry headers and checksums, and the file headers of the segments.
+        let overhead = self.keydir.values().fold(0, |size, (file_id, _, _)| {
+            size + entry::HEADER_SIZE + self.segments.get(file_id).map_or(0, |log| log.checksum_size())
+        }) + self.segments.values().map(|log| log.data_start()).sum::<u64>();
+        let tombstone_size = self.tombstones.iter().fold(0, |size, (key, (_, file_id))| {
+            let checksum_size = self.segments.get(file_id).map_or(0, |log| log.checksum_size());
+            size + entry::HEADER_SIZE + checksum_size + key.len() as u64
+        });
+        let live_disk_size = size + overhead + tombstone_size;
+        let garbage_disk_size = total_disk_size.checked_sub(live_disk_size).ok_or_else(|| {
+            Error::Internal(format!(
+                "live data size {} exceeds log size {} for {}",
+                live_disk_size, total_disk_size, self.path.display()
+            ))
+        })?;
         let name = "Bitcask".to_string();
+        let forecast = Some(self.forecast(total_disk_size, garbage_disk_size)?);
         Ok(Status {
             name,
             keys, 
             size, 
             total_disk_size, 
             live_disk_size, 
-            garbage_disk_size 
+            garbage_disk_size,
+            logical_bytes_written: self.logical_bytes_written,
+            physical_bytes_written: self.physical_bytes_written,
+            forecast,
         })
     }
     
 }
 
 impl BitCask {
+    // Scans all keys, like scan(..), but with an iterator that knows how many
+    // are left.
+    pub fn scan_all(&mut self) -> FullScanIterator<'_> {
+        let error = self.flush().err();
+        ScanIterator {
+            inner: self.keydir.iter(),
+            segments: &self.segments,
+            inline_values: &self.inline_values,
+            error,
+            failed: false,
+        }
+    }
+
+    // Seals the active segment and merges all sealed segments into one.
     pub fn compact(&mut self) -> Result<()> {
-        let mut temp_path = self.log.path.clone();
-        temp_path.set_extension("new");
+        match self.start_merge()? {
+            Some(job) => self.finish_merge(job.run()),
+            None => Ok(()),
+        }
+    }
+
+    // Rewrites a store written by an older format version in the current
+    // one. Older segments are still read and appended to, so this is only
+    // needed to get what the newer format adds, e.g. a checksum on every
+    // entry. Compacting writes the merged and the new active segment in the
+    // current format, and the older ones are removed. Returns the oldest
+    // format version found, or None if the store was already current.
+    pub fn convert(&mut self) -> Result<Option<u32>> {
+        let oldest = self.segments.values().map(|log| log.version).min().filter(|version| *version < 2);
+        if oldest.is_some() {
+            self.compact()?;
+        }
+        Ok(oldest)
+    }
+
+    // Zeroes the overwritten and deleted entries in the sealed segments,
+    // punching holes over the whole blocks among them to return their disk
+    // space to the filesystem without waiting for compaction to rewrite the
+    // segments. Sizes and offsets are unchanged. Each segment first gets a
+    // hint file listing its live entries and tombstones, which is read back
+    // and verified before anything is zeroed. If the hint file is later
+    // lost, opening the store scans the segment and skips the zeroed ranges,
+    // which loses the tombstones, but the entries they deleted are zeroed
+    // too. To keep it that way, segments are punched oldest first, stopping
+    // at the first version 1 segment, which has no checksums to find the
+    // next entry after a zeroed range by, and at the first one whose
+    // existing hint file fails its checksum. Fails while a merge is
+    // running, since it reads the entries as of its start.
+    pub fn punch_holes(&mut self) -> Result<()> {
+        self.check_writable()?;
+        if self.merging {
+            return Err(Error::Value(format!("{} is being compacted", self.path.display())));
+        }
+        let files: std::collections::HashSet<_> = self
+            .options
+            .vfs
+            .read_dir(segment_dir(&self.path))?
+            .into_iter()
+            .filter_map(|file| file.file_name().map(|name| name.to_owned()))
+            .collect();
+        let mut sealed: Vec<u64> = self.segments.keys().copied().collect();
+        sealed.pop();
+        let mut live: std::collections::HashMap<u64, Vec<(u64, u64)>> = std::collections::HashMap::new();
+        for (key, &(file_id, value_pos, value_len)) in &self.keydir {
+            let Some(log) = self.segments.get(&file_id) else {
+                continue;
+            };
+            let start = value_pos.saturating_sub(log.checksum_size() + entry::HEADER_SIZE + key.len() as u64);
+            live.entry(file_id).or_default().push((start, value_pos + value_len as u64));
+        }
+
+        for id in sealed {
+            let mut log = self.segments[&id].clone();
+            if log.version == 1 {
+                log::warn!("Not punching holes in version 1 segment {} of {} or later", id, self.path.display());
+                break;
+            }
+            let size = log.file.size()?;
+            let mut ranges = live.remove(&id).unwrap_or_default();
+            ranges.push((0, log.data_start()));
+            ranges.push((size, size));
+            ranges.sort_unstable();
+            let mut holes = Vec::new();
+            let mut end = 0;
+            for (start, stop) in ranges {
+                if start > end {
+                    holes.push((end, start));
+                }
+                end = end.max(stop);
+            }
+            if holes.is_empty() {
+                continue;
+            }
 
-        let (mut new_log, new_keydir) = self.write_log(temp_path)?;
+            // The keys whose last entry in the segment is a tombstone, from
+            // its hint file if it has one, since it may already have holes.
+            let hint_path = segment_path(&self.path, id, ".hint");
+            let mut deleted = Tombstones::new();
+            if hint_path.file_name().is_some_and(|name| files.contains(name)) {
+                let file = self.options.vfs.open(&hint_path)?;
+                let mut data = vec![0; file.size()? as usize];
+                file.read_exact_at(&mut data, 0)?;
+                let Some((_, entries)) = decode_hint(&data).filter(|(hinted, _)| *hinted == size) else {
+                    log::warn!("Not punching holes in segment {} of {} or later with an invalid hint file", id, self.path.display());
+                    break;
+                };
+                deleted.extend(
+                    entries.into_iter().filter(|(_, value, _)| value.is_none()).map(|(key, _, at)| (key, (at, id))),
+                );
+            } else {
+                log.build_keydir(id, 0, &mut KeyDir::new(), &mut deleted, true, &mut |_| {})?;
+            }
+            // Keep the deletion times of the tombstones still retained.
+            for (key, (deleted_at, file_id)) in &self.tombstones {
+                if let Some(tombstone) = deleted.get_mut(key).filter(|_| *file_id == id) {
+                    tombstone.0 = *deleted_at;
+                }
+            }
 
-        std::fs::rename(&new_log.path, &self.log.path)?;
-        new_log.path = self.log.path.clone();
+            let keydir: KeyDir = self
+                .keydir
+                .iter()
+                .filter(|(_, (file_id, _, _))| *file_id == id)
+                .map(|(key, entry)| (key.clone(), *entry))
+                .collect();
+            let hint = encode_hint(size, &keydir, &deleted);
+            let new_path = segment_path(&self.path, id, ".hint.new");
+            let hint_file = self.options.vfs.open(&new_path)?;
+            hint_file.set_len(0)?;
+            hint_file.append(&hint)?;
+            hint_file.sync()?;
+            let mut written = vec![0; hint_file.size()? as usize];
+            hint_file.read_exact_at(&mut written, 0)?;
+            if written != hint || decode_hint(&written).is_none() {
+                log::warn!("Not punching holes in segment {} of {} or later after a bad hint file write", id, self.path.display());
+                self.options.vfs.remove(&new_path)?;
+                break;
+            }
+            self.options.vfs.rename(&new_path, &hint_path)?;
 
-        self.log = new_log;
-        self.keydir = new_keydir;
+            for (start, end) in holes {
+                log.file.punch_hole(start, end - start)?;
+            }
+        }
         Ok(())
     }
 
-    fn write_log(&mut self, path: PathBuf) -> Result<(Log, KeyDir)> {
-        let mut keydir = KeyDir::new();
-        let mut log = Log::new(path)?;
+    // Reads the values in the range using up to `threads` threads, each
+    // handling a contiguous slice of the keys, and calls f for every entry.
+    // Entries are visited concurrently and in no particular order. An error
+    // stops the thread that hit it, and the error from the lowest key slice
+    // is returned once all threads are done.
+    pub fn parallel_scan(
+        &self,
+        range: impl std::ops::RangeBounds<Vec<u8>>,
+        threads: usize,
+        f: impl Fn(&[u8], &[u8]) -> Result<()> + Sync,
+    ) -> Result<()> {
+        let entries: Vec<_> = self.keydir.range(range).collect();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let chunk_size = entries.len().div_ceil(threads.max(1));
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = entries
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let f = &f;
+                    scope.spawn(move || -> Result<()> {
+                        for (key, entry) in chunk {
+                            f(key, &read_value(&self.segments, key, **entry)?)?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers.into_iter().try_for_each(|worker| {
+                worker.join().map_err(|_| Error::Internal("parallel scan worker panicked".to_string()))?
+            })
+        })
+    }
 
-        for (key, (value_pos, value_len)) in self.keydir.iter() {
-            let value = log.read_entry(*value_pos, *value_len)?;
-            let (pos, len) = log.write_entry(key, Some(&value))?;
-            keydir.insert(key.to_vec(), (pos, len));
+    // Like parallel_scan, but collects the entries in key order.
+    pub fn parallel_scan_collect(
+        &self,
+        range: impl std::ops::RangeBounds<Vec<u8>>,
+        threads: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let items = std::sync::Mutex::new(Vec::new());
+        self.parallel_scan(range, threads, |key, value| {
+            let mut items = items.lock().map_err(|err| Error::Internal(err.to_string()))?;
+            items.push((key.to_vec(), value.to_vec()));
+            Ok(())
+        })?;
+        let mut items = items.into_inner().map_err(|err| Error::Internal(err.to_string()))?;
+        items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Ok(items)
+    }
+
+    // Compacts the log if it has enough garbage and the compaction schedule
+    // allows it right now. Meant to be called periodically, e.g. from a
+    // maintenance thread. Returns whether compaction ran.
+    pub fn maybe_compact(&mut self) -> Result<bool> {
+        if !self.compaction_due()? {
+            return Ok(false);
         }
+        self.compact()?;
+        Ok(true)
+    }
+
+    // Whether the log has enough garbage and the compaction schedule allows
+    // compacting it right now.
+    fn compaction_due(&mut self) -> Result<bool> {
+        let now = self.options.clock.now();
+        let (last_time, last_ops) = std::mem::replace(&mut self.last_load_sample, (now, self.ops));
+        let schedule = &self.options.compaction_schedule;
 
-        Ok((log, keydir))
+        if !schedule.windows.is_empty() && !schedule.windows.iter().any(|w| w.contains(now)) {
+            return Ok(false);
+        }
+        if let Some(max_ops_per_sec) = schedule.max_ops_per_sec {
+            let elapsed = now.saturating_sub(last_time).as_secs_f64();
+            let ops = (self.ops - last_ops) as f64;
+            if elapsed == 0.0 || ops / elapsed >= max_ops_per_sec {
+                return Ok(false);
+            }
+        }
+        let status = self.status()?;
+        if status.total_disk_size == 0
+            || (status.garbage_disk_size as f64) < status.total_disk_size as f64 * schedule.garbage_ratio
+        {
+            return Ok(false);
+        }
+        log::info!(
+            "Compacting {} to remove {} bytes of garbage",
+            self.path.display(),
+            status.garbage_disk_size
+        );
+        Ok(true)
     }
-}
 
+    // Replaces the compaction schedule at runtime.
+    pub fn set_compaction_schedule(&mut self, schedule: CompactionSchedule) {
+        self.options.compaction_schedule = schedule;
+    }
 
-impl std::fmt::Display for BitCask {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "bitcask")
+    // Appends all writes buffered for coalescing to the log.
+    pub fn flush(&mut self) -> Result<()> {
+        while let Some((key, value)) = self.pending.pop_first() {
+            let result = match &value {
+                Some(value) => self.write_set(&key, value),
+                None => self.write_delete(&key),
+            };
+            if let Err(err) = result {
+                self.pending.insert(key, value);
+                return Err(err);
+            }
+        }
+        self.pending_bytes = 0;
+        Ok(())
     }
-}
 
+    // Appends all buffered writes and syncs them to disk, whatever the sync
+    // policy.
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        let result = self.sync_active();
+        self.track_write(result)
+    }
 
-type KeyDir = std::collections::BTreeMap<Vec<u8>, (u64, u32)>;
+    // Whether repeated write failures made the store read-only.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
 
-struct Log {
-    path: PathBuf,
-    file: std::fs::File,
-}
+    // Tries to leave the read-only degraded mode, e.g. once the disk was
+    // fixed or space freed: drops any partial entries the failed writes left
+    // at the end of the active segment, and syncs it.
+    pub fn recover(&mut self) -> Result<()> {
+        if !self.degraded {
+            return Ok(());
+        }
+        if let Some(log) = self.segments.values().next_back() {
+            log.file.set_len(self.active_size)?;
+            log.file.sync()?;
+        }
+        log::info!("Leaving read-only mode for {}", self.path.display());
+        self.degraded = false;
+        self.write_failures = 0;
+        self.unsynced = false;
+        Ok(())
+    }
 
-impl Log {
-    pub fn new(path: PathBuf) -> Result<Self> {
-        if let Some(dir) = path.parent() {
-            std::fs::create_dir_all(dir);
+    fn reset_growth_baseline(&mut self) -> Result<()> {
+        let status = self.status()?;
+        self.growth_baseline = (self.options.clock.now(), status.total_disk_size, status.garbage_disk_size);
+        Ok(())
+    }
+
+    // Extrapolates the growth since the baseline linearly.
+    fn forecast(&self, total_disk_size: u64, garbage_disk_size: u64) -> Result<super::Forecast> {
+        let (since, total_baseline, garbage_baseline) = self.growth_baseline;
+        let elapsed = self.options.clock.now().saturating_sub(since).as_secs_f64();
+        let rate = |now: u64, then: u64| match elapsed {
+            0.0 => 0.0,
+            elapsed => now.saturating_sub(then) as f64 / elapsed,
+        };
+        let growth_rate = rate(total_disk_size, total_baseline);
+        let garbage_rate = rate(garbage_disk_size, garbage_baseline);
+        let until = |bytes: f64, rate: f64| match rate > 0.0 {
+            true => Duration::try_from_secs_f64(bytes.max(0.0) / rate).ok(),
+            false => None,
+        };
+
+        let until_disk_full = self
+            .options
+            .vfs
+            .available_space(&self.path)?
+            .and_then(|available| until(available as f64, growth_rate));
+        // Solves (garbage + garbage_rate * t) / (total + growth_rate * t) = ratio.
+        let ratio = self.options.compaction_schedule.garbage_ratio;
+        let until_compaction = match total_disk_size {
+            0 => None,
+            total if garbage_disk_size as f64 >= total as f64 * ratio => Some(Duration::ZERO),
+            total => until(total as f64 * ratio - garbage_disk_size as f64, garbage_rate - ratio * growth_rate),
+        };
+        Ok(super::Forecast { growth_rate, garbage_rate, until_disk_full, until_compaction })
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.options.read_only {
+            return Err(Error::Value(format!("{} is open read-only", self.path.display())));
         }
+        if self.degraded {
+            return Err(Error::Degraded(format!(
+                "{} is read-only after {} consecutive write failures",
+                self.path.display(),
+                self.write_failures
+            )));
+        }
+        Ok(())
+    }
 
-        let file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&path)?;
+    // Counts consecutive failed writes, making the store read-only once there
+    // are max_write_failures of them.
+    fn track_write<T>(&mut self, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.write_failures = 0,
+            Err(err) => {
+                self.write_failures += 1;
+                let max = self.options.max_write_failures;
+                if max > 0 && self.write_failures >= max && !self.degraded {
+                    log::error!(
+                        "Making {} read-only after {} consecutive write failures, the last: {}",
+                        self.path.display(),
+                        self.write_failures,
+                        err
+                    );
+                    self.degraded = true;
+                }
+            }
+        }
+        result
+    }
 
-        // file.try_lock_exclusive()?; use exclusive-lock
-        
+    fn sync_active(&mut self) -> Result<()> {
+        if self.unsynced {
+            if let Some(log) = self.segments.values().next_back() {
+                log.file.sync()?;
+            }
+            self.unsynced = false;
+        }
+        self.last_sync = self.options.clock.now();
+        Ok(())
+    }
 
-        Ok(Self {path, file})
+    // Syncs the active segment after a write if the sync policy calls for it.
+    fn sync_for_policy(&mut self) -> Result<()> {
+        let due = match self.options.sync_policy {
+            SyncPolicy::Never => false,
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::Interval(interval) => {
+                self.options.clock.now().saturating_sub(self.last_sync) >= interval
+            }
+        };
+        if due {
+            self.sync_active()?;
+        }
+        Ok(())
     }
 
-    fn write_entry(&mut self, key: &[u8], values: Option<&[u8]>) -> Result<(u64, u32)> {
-        let key_len = key.len() as u32;
-        let value_len = values.map_or(0, |v| v.len() as u32);
-        let value_len_or_tombstone = values.map_or(-1, |v| v.len() as i32);
-        info!("key_len {}, value_len_or_tombstone {}", key_len, value_len);
-        
-        let len: u32 = 4 + 4 + key_len + value_len;
-        let pos = self.file.seek(SeekFrom::End(0))?;
-        info!("files current position {}", pos);
-
-        let mut w: BufWriter<&mut fs::File> = BufWriter::with_capacity(len as usize, &mut self.file);
-        w.write_all(&key_len.to_be_bytes())?;
-        w.write_all(&value_len_or_tombstone.to_be_bytes())?;
-        w.write_all(key)?;
-        
-        if let Some(values) = values {
-            w.write_all(values)?;
+    fn buffer(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+        let now = self.options.clock.now();
+        if self.pending.is_empty() {
+            self.pending_since = now;
         }
-        
-        w.flush()?;
-        
-        info!("current write position: {}; write length: {}", pos, len);
-        Ok((pos + len as u64 - value_len as u64, value_len))
+        self.pending_bytes += key.len() + value.as_ref().map_or(0, |v| v.len());
+        if let Some(Some(old)) = self.pending.insert(key.to_vec(), value) {
+            self.pending_bytes -= key.len() + old.len();
+        }
+        let Some(coalescing) = &self.options.write_coalescing else {
+            return Ok(());
+        };
+        if self.pending_bytes > coalescing.max_bytes
+            || now.saturating_sub(self.pending_since) >= coalescing.interval
+        {
+            self.flush()?;
+        }
+        Ok(())
     }
 
-    fn read_entry(&mut self, value_pos: u64, value_len: u32) -> Result<Vec<u8>> {
-        let mut value: Vec<u8> = vec![0; value_len as usize];
-        self.file.seek(SeekFrom::Start(value_pos))?;
-        self.file.read_exact(&mut value)?;
-        Ok(value)
+    fn write_set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.throttle(IoClass::Foreground, entry::CHECKSUM_SIZE + entry::HEADER_SIZE + (key.len() + value.len()) as u64);
+        let entry = self.append(key, Some(value))?;
+        self.index_set(key, value, entry);
+        Ok(())
     }
 
-    fn build_keydir(&mut self) -> Result<KeyDir> {
-        let mut keydir = KeyDir::new();
+    fn write_delete(&mut self, key: &[u8]) -> Result<()> {
+        self.throttle(IoClass::Foreground, entry::CHECKSUM_SIZE + entry::HEADER_SIZE + key.len() as u64);
+        let (file_id, _, _) = self.append(key, None)?;
+        self.index_delete(key, file_id);
+        Ok(())
+    }
 
-        let mut key_len_buf = [0u8; 4];
-        let mut value_len_buf = [0u8; 4];
+    fn index_set(&mut self, key: &[u8], value: &[u8], entry: (u64, u64, u32)) {
+        self.keydir.insert(key.to_vec(), entry);
+        self.tombstones.remove(key);
+        self.uninline(key);
+        self.inline(key, value);
+    }
 
-        let file_len = self.file.metadata()?.len();
-        let mut reader = BufReader::new(&mut self.file);
+    fn index_delete(&mut self, key: &[u8], file_id: u64) {
+        self.keydir.remove(key);
+        self.uninline(key);
+        if !self.options.tombstone_retention.is_zero() {
+            self.tombstones.insert(key.to_vec(), (self.options.clock.now(), file_id));
+        }
+    }
 
-        let mut pos = reader.seek(SeekFrom::Start(0))?;
+    // Appends an entry to the active segment, first sealing it if the entry
+    // would grow it past max_segment_size, and returns its keydir entry.
+    fn append(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(u64, u64, u32)> {
+        self.check_writable()?;
+        let result = self.try_append(key, value);
+        if result.is_err() {
+            self.roll_back();
+        }
+        self.track_write(result)
+    }
 
-        while pos < file_len {
+    fn try_append(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(u64, u64, u32)> {
+        let len = entry::CHECKSUM_SIZE + entry::HEADER_SIZE + (key.len() + value.map_or(0, |v| v.len())) as u64;
+        if !self.active_is_empty() && self.active_size + len > self.options.max_segment_size {
+            self.rotate()?;
+        }
+        let (file_id, log) = self
+            .segments
+            .iter_mut()
+            .next_back()
+            .ok_or_else(|| Error::Internal(format!("no active segment for {}", self.path.display())))?;
+        let (value_pos, value_len) = log.write_entry(key, value)?;
+        let file_id = *file_id;
+        let end = entry::align(value_pos + value_len as u64, log.alignment);
+        // Sync before taking the entry into active_size, so that roll_back
+        // cuts it off if the sync fails.
+        self.unsynced = true;
+        self.sync_for_policy()?;
+        self.physical_bytes_written += end - self.active_size;
+        self.active_size = end;
+        Ok((file_id, value_pos, value_len))
+    }
 
-            let result = || -> std::result::Result<(Vec<u8>, u64, Option<u32>), std::io::Error> {
-                reader.read_exact(&mut key_len_buf)?;
-                let key_len = u32::from_be_bytes(key_len_buf);
+    // Appends a batch to the active segment, like append, and returns the
+    // keydir entries of its writes in order. Version 1 segments can't mark
+    // batches, so a version 1 active segment is always sealed first.
+    fn append_batch(&mut self, batch: &[(Vec<u8>, Option<Vec<u8>>)]) -> Result<Vec<(u64, u64, u32)>> {
+        self.check_writable()?;
+        let result = self.try_append_batch(batch);
+        if result.is_err() {
+            self.roll_back();
+        }
+        self.track_write(result)
+    }
 
-                reader.read_exact(&mut value_len_buf)?;
-                let value_len_or_tombstone =  match i32::from_be_bytes(value_len_buf) {
-                    l if l >= 0 => Some(l as u32),
-                    _ => None
-                };
+    // Cuts off whatever a failed append left at the end of the active
+    // segment. Otherwise later writes would follow a torn record, and
+    // reopening the store would truncate them along with it. If that fails
+    // too, the store turns read-only until recover succeeds.
+    fn roll_back(&mut self) {
+        let Some(log) = self.segments.values().next_back() else {
+            return;
+        };
+        let result = match log.file.size() {
+            Ok(size) if size == self.active_size => return,
+            Ok(_) => log.file.set_len(self.active_size),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::error!("Making {} read-only after failing to roll back a failed write: {}", self.path.display(), err);
+            self.degraded = true;
+        }
+    }
 
-                let value_pos = pos + 4 + 4 + key_len as u64;
-                let mut key = vec![0; key_len as usize];
-                reader.read_exact(&mut key);
-                if let Some(value_len) = value_len_or_tombstone{
-                    if value_len as u64 + value_pos > file_len {
-                        return Err(
-                            std::io::Error::new(
-                                std::io::ErrorKind::UnexpectedEof,
-                                "value extends beyond end of file",
-                            )
-                        );
-                    }
-                    reader.seek_relative(value_len as i64)?;
-                }
+    fn try_append_batch(&mut self, batch: &[(Vec<u8>, Option<Vec<u8>>)]) -> Result<Vec<(u64, u64, u32)>> {
+        let len = entry::BATCH_MARKER_SIZE
+            + batch
+                .iter()
+                .map(|(key, value)| {
+                    entry::CHECKSUM_SIZE
+                        + entry::HEADER_SIZE
+                        + (key.len() + value.as_ref().map_or(0, |v| v.len())) as u64
+                })
+                .sum::<u64>();
+        let version = self.segments.values().next_back().map_or(entry::VERSION, |log| log.version);
+        if !self.active_is_empty() && (version < 2 || self.active_size + len > self.options.max_segment_size) {
+            self.rotate()?;
+        }
+        self.throttle(IoClass::Foreground, len);
+        let (file_id, log) = self
+            .segments
+            .iter_mut()
+            .next_back()
+            .ok_or_else(|| Error::Internal(format!("no active segment for {}", self.path.display())))?;
+        let (end, entries) = log.write_batch(batch)?;
+        self.physical_bytes_written += end - self.active_size;
+        self.active_size = end;
+        self.unsynced = false;
+        self.last_sync = self.options.clock.now();
+        Ok(entries.into_iter().map(|(value_pos, value_len)| (*file_id, value_pos, value_len)).collect())
+    }
 
-                Ok((key, value_pos, value_len_or_tombstone))
-                
-            }();
+    fn active_is_empty(&self) -> bool {
+        self.segments.values().next_back().is_none_or(|log| self.active_size <= log.data_start())
+    }
 
-            match result {
-                Ok((key, value_pos, Some(value_len))) => {
-                    keydir.insert(key, (value_pos, value_len));
-                    pos = value_pos + value_len as u64;
-                }
+    // Seals the active segment by moving it to path.<id>, and starts a new
+    // active segment at path.
+    fn rotate(&mut self) -> Result<()> {
+        if self.options.sync_policy != SyncPolicy::Never {
+            self.sync_active()?;
+        }
+        let Some(mut active) = self.segments.last_entry() else {
+            return Err(Error::Internal(format!("no active segment for {}", self.path.display())));
+        };
+        let id = *active.key();
+        if self.options.preallocate_segments {
+            // Truncating to the current size frees the blocks reserved past it.
+            active.get().file.set_len(self.active_size)?;
+        }
+        let sealed_path = segment_path(&self.path, id, "");
+        self.options.vfs.rename(&self.path, &sealed_path)?;
+        active.get_mut().path = sealed_path;
+        let log = Log::new(self.options.vfs.clone(), self.path.clone(), self.options.record_alignment, LockMode::Exclusive)?;
+        self.active_size = log.file.size()?;
+        self.segments.insert(id + 1, log);
+        self.preallocate_active();
+        if self.options.publish_keydir {
+            self.write_keydir()?;
+        }
+        Ok(())
+    }
 
-                Ok((key, value_pos, None)) => {
-                    keydir.remove(&key);
-                    pos = value_pos;
-                }
+    // Reserves space for the active segment if preallocate_segments is set
+    // and the store is open exclusively for writing. Failing to is only
+    // logged, since appends allocate as they go anyway.
+    fn preallocate_active(&self) {
+        if !self.options.preallocate_segments || self.options.read_only {
+            return;
+        }
+        let Some(active) = self.segments.values().next_back().filter(|active| active.writable) else {
+            return;
+        };
+        if let Err(err) = active.file.preallocate(self.options.max_segment_size) {
+            log::warn!("Failed to preallocate {}: {}", self.path.display(), err);
+        }
+    }
 
-                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
-                    // log::error 
-                    self.file.set_len(pos)?;
-                    break;
-                }
+    // Seals the active segment and prepares a merge of all sealed segments,
+    // or returns None if there are none. The job only reads the sealed
+    // segments, so it can run without holding the store, and finish_merge
+    // then swaps in its result. Fails while another merge is running.
+    fn start_merge(&mut self) -> Result<Option<MergeJob>> {
+        self.check_writable()?;
+        if self.merging {
+            return Err(Error::Value(format!("{} is already being compacted", self.path.display())));
+        }
+        self.flush()?;
+        if !self.active_is_empty() {
+            self.rotate()?;
+        }
+        let Some(&last_id) = self.segments.keys().rev().nth(1) else {
+            return Ok(None);
+        };
+        self.merging = true;
+        Ok(Some(MergeJob {
+            path: self.path.clone(),
+            last_id,
+            segments: self.segments.range(..=last_id).map(|(id, log)| (*id, log.clone())).collect(),
+            keydir: self
+                .keydir
+                .iter()
+                .filter(|(_, (file_id, _, _))| *file_id <= last_id)
+                .map(|(key, entry)| (key.clone(), *entry))
+                .collect(),
+            tombstones: self.tombstones.clone(),
+            options: self.options.clone(),
+        }))
+    }
 
-                Err(err) => return Err(err.into()),
+    // Replaces the merged segments with the result of a merge job, unless it
+    // failed. Writes made while the job ran are in newer segments and take
+    // precedence over the merged entries.
+    fn finish_merge(&mut self, merged: Result<Merged>) -> Result<()> {
+        self.merging = false;
+        let Merged { last_id: last, mut log, keydir, tombstones, bytes_written } = merged?;
+
+        let newer = self.segments.split_off(&(last + 1));
+        for id in std::mem::replace(&mut self.segments, newer).into_keys() {
+            remove_segment(&self.options.vfs, &self.path, id)?;
+        }
+        let merged_path = std::mem::replace(&mut log.path, segment_path(&self.path, last, ""));
+        self.options.vfs.rename(&merged_path, &log.path)?;
+        self.options.vfs.rename(
+            &segment_path(&self.path, last, ".hint.new"),
+            &segment_path(&self.path, last, ".hint"),
+        )?;
+        self.physical_bytes_written += bytes_written;
+        self.segments.insert(last, log);
+
+        // Keys the compaction filter dropped are missing from the new keydir.
+        self.keydir.retain(|key, (file_id, _, _)| *file_id > last || keydir.contains_key(key));
+        for (key, entry) in keydir {
+            if let Some(current) = self.keydir.get_mut(&key).filter(|(file_id, _, _)| *file_id <= last) {
+                *current = entry;
+            }
+        }
+        self.tombstones.retain(|_, (_, file_id)| *file_id > last);
+        for (key, tombstone) in tombstones {
+            if !self.keydir.contains_key(&key) {
+                self.tombstones.entry(key).or_insert(tombstone);
             }
-            
         }
-        Ok(keydir)
 
+        self.reset_growth_baseline()?;
+        self.chunk_reads.clear();
+        if self.options.publish_keydir {
+            self.write_keydir()?;
+        }
+        // A compaction filter may have dropped or rewritten inlined values.
+        if self.options.compaction_filter.is_some() {
+            self.inline_values.clear();
+            self.inline_bytes = 0;
+        }
+        Ok(())
     }
 
-}
+    fn inline(&mut self, key: &[u8], value: &[u8]) {
+        let bytes = (key.len() + value.len()) as u64;
+        if value.len() > self.options.inline_value_size
+            || self.inline_bytes + bytes > self.options.inline_memory_budget
+        {
+            return;
+        }
+        self.inline_values.insert(key.to_vec(), value.to_vec());
+        self.inline_bytes += bytes;
+    }
 
-pub struct ScanIterator<'a> {
-    inner: std::collections::btree_map::Range<'a, Vec <u8>, (u64, u32)>,
-    log: &'a mut Log,
-}
+    fn uninline(&mut self, key: &[u8]) {
+        if let Some(value) = self.inline_values.remove(key) {
+            self.inline_bytes -= (key.len() + value.len()) as u64;
+        }
+    }
 
+    fn validate(&self, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        match &self.options.write_validator {
+            Some(validator) => validator.validate(key, value).map_err(Error::Rejected),
+            None => Ok(()),
+        }
+    }
 
-impl <'a> ScanIterator<'a> {
-    fn map(&mut self, item: (&Vec<u8>, &(u64, u32))) -> <Self as Iterator>::Item {
-        let (key, (value_pos, value_len)) = item;
-        Ok((key.clone(), self.log.read_entry(*value_pos, *value_len)?))
+    fn throttle(&self, class: IoClass, bytes: u64) {
+        if let Some(scheduler) = &self.options.io_scheduler {
+            scheduler.acquire(class, bytes);
+        }
     }
-}
 
-impl<'a> Iterator for ScanIterator<'a> {
-    type Item = Result<(Vec<u8>, Vec<u8>)>;
+    fn access_path(&self) -> PathBuf {
+        sibling_path(&self.path, ".access")
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|item: (&Vec<u8>, &(u64, u32))| self.map(item))
+    // Writes the most read chunks, hottest first, as pairs of big-endian u64
+    // file IDs and chunk numbers.
+    fn save_access_counts(&self) -> Result<()> {
+        let mut chunks: Vec<_> = self.chunk_reads.iter().collect();
+        chunks.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let data: Vec<u8> = chunks
+            .into_iter()
+            .take(ACCESS_CHUNKS_SAVED)
+            .flat_map(|((file_id, chunk), _)| [file_id.to_be_bytes(), chunk.to_be_bytes()].concat())
+            .collect();
+
+        let temp_path = sibling_path(&self.path, ".access.new");
+        let file = self.options.vfs.open(&temp_path)?;
+        file.set_len(0)?;
+        file.append(&data)?;
+        file.sync()?;
+        self.options.vfs.rename(&temp_path, &self.access_path())?;
+        Ok(())
     }
 }
 
-impl<'a> DoubleEndedIterator for ScanIterator<'a> {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        self.inner.next_back().map(|item|self.map(item))
-    }
+// A merge of the sealed segments up to last_id, from a snapshot of their keydir
+// entries and the retained tombstones taken by BitCask::start_merge.
+struct MergeJob {
+    path: PathBuf,
+    last_id: u64,
+    segments: Segments,
+    keydir: KeyDir,
+    tombstones: Tombstones,
+    options: Options,
 }
 
-impl<'a> super::ScanIterator for ScanIterator<'a> {}
+// The result of a merge job, a segment at path.<last_id>.merged with its hint
+// file at path.<last_id>.hint.new.
+struct Merged {
+    last_id: u64,
+    log: Log,
+    keydir: KeyDir,
+    tombstones: Tombstones,
+    bytes_written: u64,
+}
 
-#[cfg(test)]
-mod tests {
-    use std::{path::Path, env, borrow::BorrowMut};
-    use super::*;
-    
+impl MergeJob {
+    // Rewrites the live entries into a single segment that takes the newest
+    // merged ID, so entries in newer segments still take precedence when the
+    // keydir is rebuilt, and writes its hint file. The segment is renamed to
+    // path.<id>.merged once complete, which lets open_segments finish the
+    // merge if we crash while replacing the old segments.
+    fn run(self) -> Result<Merged> {
+        let (mut log, keydir, tombstones) = self.write_log(segment_path(&self.path, self.last_id, ".new"))?;
+        log.file.sync()?;
+        let size = log.file.size()?;
+        let hint = encode_hint(size, &keydir, &tombstones);
+        let hint_file = self.options.vfs.open(&segment_path(&self.path, self.last_id, ".hint.new"))?;
+        hint_file.set_len(0)?;
+        hint_file.append(&hint)?;
+        hint_file.sync()?;
+        let merged_path = segment_path(&self.path, self.last_id, ".merged");
+        self.options.vfs.rename(&log.path, &merged_path)?;
+        log.path = merged_path;
+        Ok(Merged { last_id: self.last_id, log, keydir, tombstones, bytes_written: size + hint.len() as u64 })
+    }
+
+    // Writes the live entries of the snapshot to a new segment file, followed
+    // by the retained tombstones.
+    fn write_log(&self, path: PathBuf) -> Result<(Log, KeyDir, Tombstones)> {
+        let last_id = self.last_id;
+        let mut keydir = KeyDir::new();
+        // Replaces any leftovers of a merge that didn't complete.
+        let mut log = Log::create(self.options.vfs.clone(), path)?;
+
+        let now = self.options.clock.now();
+        let mut dropped = Tombstones::new();
+        for (key, &(file_id, value_pos, value_len)) in self.keydir.iter() {
+            self.throttle(value_len as u64);
+            let mut value = read_value(&self.segments, key, (file_id, value_pos, value_len))?;
+            if let Some(filter) = &self.options.compaction_filter {
+                match filter.filter(key, &value) {
+                    FilterDecision::Keep => {}
+                    FilterDecision::Drop => {
+                        dropped.insert(key.to_vec(), (now, last_id));
+                        continue;
+                    }
+                    FilterDecision::Rewrite(new_value) => value = new_value,
+                }
+            }
+            self.throttle(entry::CHECKSUM_SIZE + entry::HEADER_SIZE + (key.len() + value.len()) as u64);
+            let (pos, len) = log.write_entry(key, Some(&value))?;
+            keydir.insert(key.to_vec(), (last_id, pos, len));
+        }
+
+        let mut tombstones = Tombstones::new();
+        for (key, (deleted_at, _)) in self.tombstones.iter().chain(dropped.iter()) {
+            if now.saturating_sub(*deleted_at) < self.options.tombstone_retention {
+                self.throttle(entry::CHECKSUM_SIZE + entry::HEADER_SIZE + key.len() as u64);
+                log.write_entry(key, None)?;
+                tombstones.insert(key.to_vec(), (*deleted_at, last_id));
+            }
+        }
+
+        Ok((log, keydir, tombstones))
+    }
+
+    fn throttle(&self, bytes: u64) {
+        if let Some(scheduler) = &self.options.io_scheduler {
+            scheduler.acquire(IoClass::Compaction, bytes);
+        }
+    }
+}
+
+
+impl Drop for BitCask {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            log::error!("Failed to flush buffered writes to {}: {}", self.path.display(), err);
+        }
+        if self.options.sync_policy != SyncPolicy::Never {
+            if let Err(err) = self.sync_active() {
+                log::error!("Failed to sync {}: {}", self.path.display(), err);
+            }
+        }
+        if !self.options.track_reads || self.chunk_reads.is_empty() || self.options.read_only {
+            return;
+        }
+        if let Err(err) = self.save_access_counts() {
+            log::warn!("Failed to save access counts for {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+// Lets the CompactionWorker drive a store behind wrappers like Instrumented.
+impl AsMut<BitCask> for BitCask {
+    fn as_mut(&mut self) -> &mut BitCask {
+        self
+    }
+}
+
+impl std::fmt::Display for BitCask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bitcask")
+    }
+}
+
+
+// Maps each live key to the file ID, position and length of its value.
+type KeyDir = std::collections::BTreeMap<Vec<u8>, (u64, u64, u32)>;
+
+type Segments = std::collections::BTreeMap<u64, Log>;
+
+// Maps each retained tombstone's key to its deletion time and file ID.
+type Tombstones = std::collections::BTreeMap<Vec<u8>, (Duration, u64)>;
+
+// Granularity of the read access counts used by warm_up, and the number of
+// hottest chunks kept across restarts.
+const ACCESS_CHUNK: u64 = 1 << 20;
+const ACCESS_CHUNKS_SAVED: usize = 256;
+
+// Bytes scanned between two progress reports while rebuilding the keydir.
+const PROGRESS_INTERVAL: u64 = 1 << 20;
+
+// Bytes read at a time while looking for the end of a range zeroed by
+// BitCask::punch_holes.
+const HOLE_CHUNK: u64 = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub segments_scanned: u64,
+    pub segments_total: u64,
+    pub bytes_processed: u64,
+    pub bytes_total: u64,
+    pub elapsed: std::time::Duration,
+}
+
+impl Progress {
+    pub fn percent(&self) -> f64 {
+        if self.bytes_total == 0 {
+            return 100.0;
+        }
+        self.bytes_processed as f64 * 100.0 / self.bytes_total as f64
+    }
+
+    // Estimated time remaining, extrapolated from the throughput so far.
+    pub fn eta(&self) -> Option<std::time::Duration> {
+        if self.bytes_processed == 0 {
+            return None;
+        }
+        let remaining = self.bytes_total.saturating_sub(self.bytes_processed) as f64;
+        Some(self.elapsed.mul_f64(remaining / self.bytes_processed as f64))
+    }
+}
+
+// An entry read by Log::build_keydir: key, value position, value length (None
+// for a tombstone), whether its checksum matches, and whether it is a batch
+// marker.
+type ScannedEntry = (Vec<u8>, u64, Option<u32>, bool, bool);
+
+#[derive(Clone)]
+struct Log {
+    path: PathBuf,
+    file: Arc<dyn vfs::File>,
+    // Format version of the segment. Version 1 segments are still read and
+    // appended to. New segments are written with version 3 if they have a
+    // record alignment, and version 2 otherwise.
+    version: u32,
+    // Records start at multiples of this, 1 if they aren't aligned.
+    alignment: u64,
+    // Whether the segment is locked exclusively, and may be written to.
+    writable: bool,
+}
+
+// How Log::new locks a segment. Only exclusively locked segments are
+// written to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LockMode {
+    Exclusive,
+    Shared,
+    Unlocked,
+}
+
+impl Log {
+    // Opens and locks the segment at path, creating it with the given record
+    // alignment if it doesn't exist. An existing segment keeps its own
+    // alignment. Segments that aren't locked exclusively must exist, and
+    // aren't written to.
+    pub fn new(vfs: Arc<dyn Vfs>, path: PathBuf, alignment: u32, lock: LockMode) -> Result<Self> {
+        let file: Arc<dyn vfs::File> = match lock {
+            LockMode::Exclusive => {
+                if let Some(dir) = path.parent() {
+                    vfs.create_dir_all(dir)?;
+                }
+                Arc::from(vfs.open(&path)?)
+            }
+            LockMode::Shared | LockMode::Unlocked => Arc::from(vfs.open_read(&path)?),
+        };
+        take_lock(&*file, lock, &path)?;
+
+        let size = file.size()?;
+        let version = if size == 0 && lock != LockMode::Exclusive {
+            2
+        } else if size == 0 {
+            let header = entry::encode_file_header(alignment);
+            file.append(&header)?;
+            if alignment > 1 { 3 } else { 2 }
+        } else if size >= entry::FILE_HEADER_SIZE {
+            let mut header = [0; entry::FILE_HEADER_SIZE as usize];
+            file.read_exact_at(&mut header, 0)?;
+            entry::decode_file_header(header).unwrap_or(1)
+        } else {
+            1
+        };
+        if version > entry::VERSION {
+            return Err(Error::Value(format!(
+                "{} has format version {}, newer than the supported {}",
+                path.display(), version, entry::VERSION
+            )));
+        }
+
+        let alignment = if version >= 3 {
+            let mut alignment = [0; 4];
+            file.read_exact_at(&mut alignment, entry::FILE_HEADER_SIZE)?;
+            u32::from_be_bytes(alignment)
+        } else {
+            1
+        };
+        if !alignment.is_power_of_two() {
+            return Err(Error::Corruption(format!(
+                "{} has invalid record alignment {}", path.display(), alignment
+            )));
+        }
+
+        Ok(Self {path, file, version, alignment: alignment as u64, writable: lock == LockMode::Exclusive})
+    }
+
+    // Like new, but replaces any existing file.
+    fn create(vfs: Arc<dyn Vfs>, path: PathBuf) -> Result<Self> {
+        vfs.open(&path)?.set_len(0)?;
+        Self::new(vfs, path, 0, LockMode::Exclusive)
+    }
+
+    // Cuts a torn tail off the segment. Segments that aren't writable are
+    // left alone, and the tail ignored, since a writer may be appending it.
+    fn truncate(&self, len: u64) -> Result<()> {
+        if self.writable {
+            self.file.set_len(len)?;
+        }
+        Ok(())
+    }
+
+    // Offset of the first entry.
+    fn data_start(&self) -> u64 {
+        match self.version {
+            1 => 0,
+            2 => entry::FILE_HEADER_SIZE,
+            _ => entry::align(entry::ALIGNED_FILE_HEADER_SIZE, self.alignment),
+        }
+    }
+
+    // Appends zero padding to data up to the record alignment. Aligned
+    // segments always end at a multiple of the alignment, so the record
+    // starts at one.
+    fn pad(&self, data: &mut Vec<u8>) {
+        data.resize(entry::align(data.len() as u64, self.alignment) as usize, 0);
+    }
+
+    // Size of the checksum preceding each entry.
+    fn checksum_size(&self) -> u64 {
+        if self.version >= 2 { entry::CHECKSUM_SIZE } else { 0 }
+    }
+
+    fn write_entry(&mut self, key: &[u8], values: Option<&[u8]>) -> Result<(u64, u32)> {
+        let header = entry::Header::new(key, values)?;
+        info!("key_len {}, value_len_or_tombstone {:?}", header.key_len, header.value_len);
+
+        let mut data = match self.version {
+            1 => entry::encode(key, values)?,
+            _ => entry::encode_checked(key, values)?,
+        };
+        self.pad(&mut data);
+        let pos = self.file.append(&data)?;
+        
+        info!("current write position: {}; write length: {}", pos, data.len());
+        Ok((pos + self.checksum_size() + header.value_offset(), header.value_len.unwrap_or(0)))
+    }
+
+    // Appends the batch behind a batch marker with a single write and syncs
+    // it. Returns the new end of the segment and the value positions and
+    // lengths of the batch's writes.
+    fn write_batch(&mut self, batch: &[(Vec<u8>, Option<Vec<u8>>)]) -> Result<(u64, Vec<(u64, u32)>)> {
+        if self.version < 2 {
+            return Err(Error::Internal(format!(
+                "can't write a batch to version {} segment {}",
+                self.version, self.path.display()
+            )));
+        }
+        let mut data = Vec::new();
+        let mut entries = Vec::with_capacity(batch.len());
+        for (key, value) in batch {
+            let header = entry::Header::new(key, value.as_deref())?;
+            let value_pos = entry::BATCH_MARKER_SIZE + data.len() as u64 + entry::CHECKSUM_SIZE + header.value_offset();
+            entries.push((value_pos, header.value_len.unwrap_or(0)));
+            data.extend_from_slice(&entry::encode_checked(key, value.as_deref())?);
+        }
+        let mut buf = entry::encode_batch_marker(data.len() as u64);
+        buf.extend_from_slice(&data);
+        self.pad(&mut buf);
+
+        let pos = self.file.append(&buf)?;
+        self.file.sync()?;
+        let entries = entries.into_iter().map(|(value_pos, value_len)| (pos + value_pos, value_len)).collect();
+        Ok((pos + buf.len() as u64, entries))
+    }
+
+    // Reads the value of key's entry, verifying the entry's checksum.
+    fn read_entry(&self, key: &[u8], value_pos: u64, value_len: u32) -> Result<Vec<u8>> {
+        if self.version == 1 {
+            let mut value: Vec<u8> = vec![0; value_len as usize];
+            self.file.read_exact_at(&mut value, value_pos)?;
+            return Ok(value);
+        }
+        let key_offset = self.checksum_size() + entry::HEADER_SIZE;
+        let start = value_pos
+            .checked_sub(key_offset + key.len() as u64)
+            .ok_or_else(|| self.corruption(value_pos, "value position is inside the file header"))?;
+        let mut data = vec![0; (value_pos - start) as usize + value_len as usize];
+        self.file.read_exact_at(&mut data, start)?;
+        if !entry::verify_checksum(&data) {
+            return Err(self.corruption(start, "checksum mismatch"));
+        }
+        let value = data.split_off((value_pos - start) as usize);
+        if data[key_offset as usize..] != *key {
+            return Err(self.corruption(start, "entry doesn't match the keydir"));
+        }
+        Ok(value)
+    }
+
+    fn corruption(&self, pos: u64, message: &str) -> Error {
+        Error::Corruption(format!("{} at offset {} of {}", message, pos, self.path.display()))
+    }
+
+    // Applies the segment's entries from offset `from` to the keydir and, if
+    // requested, to the keys whose latest entry is a tombstone, with an
+    // unknown (zero) deletion time. Reports the
+    // bytes scanned so far every PROGRESS_INTERVAL, and returns the segment
+    // size, which is less than before if a torn tail was truncated.
+    fn build_keydir(
+        &mut self,
+        file_id: u64,
+        from: u64,
+        keydir: &mut KeyDir,
+        tombstones: &mut Tombstones,
+        track_tombstones: bool,
+        progress: &mut dyn FnMut(u64),
+    ) -> Result<u64> {
+        let mut checksum_buf = [0u8; entry::CHECKSUM_SIZE as usize];
+        let mut header_buf = [0u8; entry::HEADER_SIZE as usize];
+        let checksum_size = self.checksum_size();
+
+        let file_len = self.file.size()?;
+        let mut reader = BufReader::new(vfs::Reader::new(&*self.file));
+
+        // The span of the batch being read, if any, and the writes read since
+        // the last ones were applied.
+        let mut batch: Option<(u64, u64)> = None;
+        let mut batched = Vec::new();
+
+        let mut pos = reader.seek(SeekFrom::Start(from))?;
+        progress(pos);
+        let mut last_report = pos;
+        if pos < self.data_start() {
+            pos = reader.seek(SeekFrom::Start(self.data_start()))?;
+        }
+
+        while pos < file_len {
+            if pos - last_report >= PROGRESS_INTERVAL {
+                progress(pos);
+                last_report = pos;
+            }
+
+            // A batch marker's value position is the start of the batch, and
+            // its key is the batch length.
+            let result = || -> std::result::Result<ScannedEntry, std::io::Error> {
+                if checksum_size > 0 {
+                    reader.read_exact(&mut checksum_buf)?;
+                }
+                reader.read_exact(&mut header_buf)?;
+                if checksum_size > 0 && entry::Header::is_batch_marker(header_buf) {
+                    let mut batch_len = [0; 8];
+                    reader.read_exact(&mut batch_len)?;
+                    let batch_pos = pos + entry::BATCH_MARKER_SIZE;
+                    if batch_pos.saturating_add(u64::from_be_bytes(batch_len)) > file_len {
+                        return Err(
+                            std::io::Error::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "batch extends beyond end of file",
+                            )
+                        );
+                    }
+                    let valid = u32::from_be_bytes(checksum_buf) == entry::crc32(&[&header_buf[..], &batch_len].concat());
+                    return Ok((batch_len.to_vec(), batch_pos, None, valid, true));
+                }
+                let header = entry::Header::decode(header_buf);
+                let (key_len, value_len_or_tombstone) = (header.key_len, header.value_len);
+
+                let value_pos = pos + checksum_size + header.value_offset();
+                if value_pos > file_len {
+                    return Err(
+                        std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "key extends beyond end of file",
+                        )
+                    );
+                }
+                let mut key = vec![0; key_len as usize];
+                reader.read_exact(&mut key)?;
+                let mut value = Vec::new();
+                if let Some(value_len) = value_len_or_tombstone{
+                    if value_len as u64 + value_pos > file_len {
+                        return Err(
+                            std::io::Error::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "value extends beyond end of file",
+                            )
+                        );
+                    }
+                    if checksum_size > 0 {
+                        value.resize(value_len as usize, 0);
+                        reader.read_exact(&mut value)?;
+                    } else {
+                        reader.seek_relative(value_len as i64)?;
+                    }
+                }
+                let valid = checksum_size == 0
+                    || u32::from_be_bytes(checksum_buf) == entry::crc32(&[&header_buf[..], &key, &value].concat());
+
+                Ok((key, value_pos, value_len_or_tombstone, valid, false))
+                
+            }();
+
+            match result {
+                Ok((_, value_pos, value_len, false, _)) => {
+                    // A torn write of the last entry or batch can leave it
+                    // with the right length but the wrong contents, anything
+                    // before it is corrupt.
+                    let alignment = self.alignment;
+                    if let Some((start, _)) = batch.filter(|(_, end)| entry::align(*end, alignment) >= file_len) {
+                        log::warn!("Truncating torn batch at offset {} of {}", start, self.path.display());
+                        self.truncate(start)?;
+                        pos = start;
+                        break;
+                    }
+                    if entry::align(value_pos + value_len.unwrap_or(0) as u64, alignment) >= file_len {
+                        log::warn!("Truncating torn entry at offset {} of {}", pos, self.path.display());
+                        self.truncate(pos)?;
+                        break;
+                    }
+                    if checksum_size > 0 && batch.is_none() && checksum_buf == [0; 4] && header_buf == [0; 8] {
+                        match self.skip_hole(pos, file_len)? {
+                            Some(next) => {
+                                pos = reader.seek(SeekFrom::Start(next))?;
+                                continue;
+                            }
+                            None => {
+                                log::warn!("Truncating zeroed tail at offset {} of {}", pos, self.path.display());
+                                self.truncate(pos)?;
+                                break;
+                            }
+                        }
+                    }
+                    return Err(self.corruption(pos, "checksum mismatch"));
+                }
+
+                // The batch's writes are only applied once all of them have
+                // been read, so a torn batch is dropped as a whole.
+                Ok((batch_len, value_pos, _, true, true)) => {
+                    let mut len = [0; 8];
+                    len.copy_from_slice(&batch_len);
+                    batch = Some((pos, value_pos + u64::from_be_bytes(len)));
+                    pos = value_pos;
+                }
+
+                Ok((key, value_pos, value_len, true, false)) => {
+                    batched.push((key, value_pos, value_len));
+                    pos = value_pos + value_len.unwrap_or(0) as u64;
+                }
+
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    // log::error 
+                    let start = batch.map_or(pos, |(start, _)| start);
+                    self.truncate(start)?;
+                    pos = start;
+                    break;
+                }
+
+                Err(err) => return Err(err.into()),
+            }
+
+            if batch.is_none_or(|(_, end)| pos >= end) {
+                batch = None;
+                for (key, value_pos, value_len) in batched.drain(..) {
+                    match value_len {
+                        Some(value_len) => {
+                            if track_tombstones {
+                                tombstones.remove(&key);
+                            }
+                            keydir.insert(key, (file_id, value_pos, value_len));
+                        }
+                        None => {
+                            keydir.remove(&key);
+                            if track_tombstones {
+                                tombstones.insert(key, (Duration::ZERO, file_id));
+                            }
+                        }
+                    }
+                }
+
+                // Skip the padding after the record. If a crash cut it
+                // short, the record is complete and the padding is restored.
+                let aligned = entry::align(pos, self.alignment);
+                if aligned > file_len && self.writable {
+                    self.file.set_len(aligned)?;
+                }
+                reader.seek_relative((aligned - pos) as i64)?;
+                pos = aligned;
+            }
+        }
+        Ok(pos)
+    }
+
+    // Finds the first entry after the range of zeroes at pos, as left by
+    // BitCask::punch_holes, or returns None if the zeroes run to the end of
+    // the segment. The entry starts at most CHECKSUM_SIZE + HEADER_SIZE - 1
+    // bytes before the first nonzero byte, since its checksum and lengths
+    // can begin with zeroes, so each such aligned offset is tried in turn.
+    fn skip_hole(&self, pos: u64, file_len: u64) -> Result<Option<u64>> {
+        let mut nonzero = None;
+        let mut chunk = pos;
+        while chunk < file_len && nonzero.is_none() {
+            let mut buf = vec![0; HOLE_CHUNK.min(file_len - chunk) as usize];
+            self.file.read_exact_at(&mut buf, chunk)?;
+            nonzero = buf.iter().position(|b| *b != 0).map(|i| chunk + i as u64);
+            chunk += buf.len() as u64;
+        }
+        let Some(nonzero) = nonzero else {
+            return Ok(None);
+        };
+        let first = nonzero.saturating_sub(entry::CHECKSUM_SIZE + entry::HEADER_SIZE - 1).max(pos);
+        for start in (first..=nonzero).filter(|start| start % self.alignment == 0) {
+            if self.is_entry_at(start, file_len)? {
+                return Ok(Some(start));
+            }
+        }
+        Err(self.corruption(nonzero, "no entry after zeroed range"))
+    }
+
+    // Returns whether a complete entry or batch marker with a valid checksum
+    // starts at pos.
+    fn is_entry_at(&self, pos: u64, file_len: u64) -> Result<bool> {
+        let mut header_buf = [0u8; (entry::CHECKSUM_SIZE + entry::HEADER_SIZE) as usize];
+        if pos + header_buf.len() as u64 > file_len {
+            return Ok(false);
+        }
+        self.file.read_exact_at(&mut header_buf, pos)?;
+        let mut header = [0; entry::HEADER_SIZE as usize];
+        header.copy_from_slice(&header_buf[entry::CHECKSUM_SIZE as usize..]);
+        let len = entry::CHECKSUM_SIZE + entry::Header::decode(header).entry_len();
+        if pos + len > file_len {
+            return Ok(false);
+        }
+        let mut buf = vec![0; len as usize];
+        self.file.read_exact_at(&mut buf, pos)?;
+        Ok(entry::verify_checksum(&buf))
+    }
+}
+
+// Returns the path of a sealed segment, or of a file derived from it when
+// suffix isn't empty.
+fn segment_path(path: &Path, id: u64, suffix: &str) -> PathBuf {
+    sibling_path(path, &format!(".{:06}{}", id, suffix))
+}
+
+// Returns the path of a file kept next to the log and named after it, e.g.
+// path.lock for the suffix ".lock".
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// Locks the file as requested, failing with Error::InUse if another handle
+// holds a conflicting lock.
+fn take_lock(file: &dyn vfs::File, lock: LockMode, path: &Path) -> Result<()> {
+    let locked = match lock {
+        LockMode::Exclusive => file.lock(),
+        LockMode::Shared => file.lock_shared(),
+        LockMode::Unlocked => Ok(()),
+    };
+    match locked {
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+            Err(Error::InUse(format!("{} is open elsewhere", path.display())))
+        }
+        result => Ok(result?),
+    }
+}
+
+// Locks the store through its lock file at path.lock, which is held for as
+// long as the store is open. It is taken before open_segments cleans up or
+// completes a merge, so a second writer fails without touching the files of
+// a running one. Stores last opened before lock files were introduced have
+// none for shared opens to lock, but their segment locks still keep writers
+// out.
+fn lock_store(vfs: &Arc<dyn Vfs>, path: &Path, lock: LockMode) -> Result<Option<Box<dyn vfs::File>>> {
+    let lock_path = sibling_path(path, ".lock");
+    let file = match lock {
+        LockMode::Exclusive => {
+            vfs.create_dir_all(segment_dir(path))?;
+            vfs.open(&lock_path)?
+        }
+        LockMode::Shared => match vfs.open_read(&lock_path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            result => result?,
+        },
+        LockMode::Unlocked => return Ok(None),
+    };
+    take_lock(&*file, lock, path)?;
+    Ok(Some(file))
+}
+
+// Returns the directory holding the log's segments.
+fn segment_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+// Opens the sealed segments and the active segment at path, whose ID follows
+// the newest sealed one, and returns them with the IDs of the segments that
+// have a hint file. Completes a merge that was interrupted after the merged
+// segment was written, and removes leftovers of one that wasn't.
+fn open_segments(
+    vfs: &Arc<dyn Vfs>,
+    path: &Path,
+    alignment: u32,
+    lock: LockMode,
+) -> Result<(Segments, std::collections::BTreeSet<u64>)> {
+    // Without an exclusive lock another process may own the store, so
+    // leftovers of an interrupted merge are skipped rather than cleaned up.
+    let writable = lock == LockMode::Exclusive;
+    let dir = segment_dir(path);
+    if writable {
+        vfs.create_dir_all(dir)?;
+    }
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::Value(format!("invalid log path {}", path.display())))?;
+
+    let mut sealed = std::collections::BTreeSet::new();
+    let mut hints = std::collections::BTreeSet::new();
+    let mut merged = None;
+    for file in vfs.read_dir(dir)? {
+        let Some(suffix) = file
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.strip_prefix(name)?.strip_prefix('.'))
+        else {
+            continue;
+        };
+        let (id, kind) = suffix.split_once('.').unwrap_or((suffix, ""));
+        if !id.bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        let Ok(id) = id.parse::<u64>() else {
+            continue;
+        };
+        match kind {
+            "" => {
+                sealed.insert(id);
+            }
+            "hint" => {
+                hints.insert(id);
+            }
+            "merged" => merged = merged.max(Some(id)),
+            "new" | "hint.new" if writable => vfs.remove(&segment_path(path, id, &format!(".{}", kind)))?,
+            _ => {}
+        }
+    }
+    if let Some(id) = merged {
+        let newer = sealed.split_off(&(id + 1));
+        let older = std::mem::replace(&mut sealed, newer);
+        if writable {
+            for old in older {
+                remove_segment(vfs, path, old)?;
+            }
+        }
+        hints = hints.split_off(&(id + 1));
+        if writable {
+            vfs.rename(&segment_path(path, id, ".merged"), &segment_path(path, id, ""))?;
+        }
+        sealed.insert(id);
+    }
+
+    let mut segments = Segments::new();
+    for id in sealed {
+        let suffix = if !writable && merged == Some(id) { ".merged" } else { "" };
+        segments.insert(id, Log::new(vfs.clone(), segment_path(path, id, suffix), 0, lock)?);
+    }
+    let active = segments.keys().next_back().map_or(1, |id| id + 1);
+    segments.insert(active, Log::new(vfs.clone(), path.to_path_buf(), alignment, lock)?);
+    Ok((segments, hints))
+}
+
+// Removes a sealed segment and its hint file, if any.
+fn remove_segment(vfs: &Arc<dyn Vfs>, path: &Path, id: u64) -> Result<()> {
+    vfs.remove(&segment_path(path, id, ""))?;
+    match vfs.remove(&segment_path(path, id, ".hint")) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+// A hint file lists the keydir entries of a segment written by compaction, so
+// opening the store can load them without reading the values. It is laid out
+// as the segment size it covers, as a big-endian u64, followed by one record
+// per key:
+//
+//   key_len: u32 (big-endian)
+//   value_len: i32 (big-endian), -1 for a tombstone
+//   value_pos: u64 (big-endian), or for a tombstone its deletion time in
+//              milliseconds since the Unix epoch, 0 if unknown
+//   key: [u8; key_len]
+//
+// and a big-endian CRC32 of everything before it. A hint file that fails the
+// checksum is ignored, and the segment is scanned instead, skipping any
+// ranges zeroed by BitCask::punch_holes.
+fn encode_hint(size: u64, keydir: &KeyDir, tombstones: &Tombstones) -> Vec<u8> {
+    let mut data = size.to_be_bytes().to_vec();
+    let entries = keydir
+        .iter()
+        .map(|(key, (_, value_pos, value_len))| (key, *value_len as i32, *value_pos))
+        .chain(tombstones.iter().map(|(key, (deleted_at, _))| (key, -1, deleted_at.as_millis() as u64)));
+    for (key, value_len, value_pos) in entries {
+        data.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        data.extend_from_slice(&value_len.to_be_bytes());
+        data.extend_from_slice(&value_pos.to_be_bytes());
+        data.extend_from_slice(key);
+    }
+    data.extend_from_slice(&entry::crc32(&data).to_be_bytes());
+    data
+}
+
+// A key with its value position and length, or None for a tombstone, and the
+// tombstone's deletion time, zero if unknown.
+type HintEntry = (Vec<u8>, Option<(u64, u32)>, Duration);
+
+// Returns the segment size a hint file covers and its entries, or None if the
+// file is truncated or fails its checksum.
+fn decode_hint(data: &[u8]) -> Option<(u64, Vec<HintEntry>)> {
+    let (data, checksum) = data.split_last_chunk::<4>()?;
+    if u32::from_be_bytes(*checksum) != entry::crc32(data) {
+        return None;
+    }
+    let (size, mut rest) = data.split_first_chunk::<8>()?;
+    let mut entries = Vec::new();
+    while !rest.is_empty() {
+        let (header, tail) = rest.split_first_chunk::<16>()?;
+        let key_len = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        let value_len = i32::from_be_bytes(header[4..8].try_into().ok()?);
+        let value_pos = u64::from_be_bytes(header[8..].try_into().ok()?);
+        if tail.len() < key_len {
+            return None;
+        }
+        let (key, tail) = tail.split_at(key_len);
+        let value = (value_len >= 0).then_some((value_pos, value_len as u32));
+        let deleted_at = if value.is_none() { Duration::from_millis(value_pos) } else { Duration::ZERO };
+        entries.push((key.to_vec(), value, deleted_at));
+        rest = tail;
+    }
+    Some((u64::from_be_bytes(*size), entries))
+}
+
+// Returns the keydir and, if requested, the keys whose latest entry is a
+// tombstone with its deletion time, if a hint file recorded it, and the ID of
+// the segment holding it, replaying the segments oldest first. Segments with
+// a hint file are loaded from it, and only the part written after it is
+// scanned.
+fn build_keydir(
+    vfs: &Arc<dyn Vfs>,
+    path: &Path,
+    segments: &mut Segments,
+    hints: &std::collections::BTreeSet<u64>,
+    track_tombstones: bool,
+    progress: &mut dyn FnMut(&Progress),
+) -> Result<(KeyDir, Tombstones)> {
+    let mut keydir = KeyDir::new();
+    let mut tombstones = Tombstones::new();
+    let start = std::time::Instant::now();
+
+    let segments_total = segments.len() as u64;
+    let mut bytes_total = 0;
+    for log in segments.values() {
+        bytes_total += log.file.size()?;
+    }
+    let mut report = |segments_scanned: u64, bytes_processed: u64| {
+        progress(&Progress {
+            segments_scanned,
+            segments_total,
+            bytes_processed,
+            bytes_total,
+            elapsed: start.elapsed(),
+        })
+    };
+
+    let mut bytes_done = 0;
+    for (scanned, (file_id, log)) in segments.iter_mut().enumerate() {
+        let mut from = 0;
+        if hints.contains(file_id) {
+            let file = vfs.open_read(&segment_path(path, *file_id, ".hint"))?;
+            let mut data = vec![0; file.size()? as usize];
+            file.read_exact_at(&mut data, 0)?;
+            match decode_hint(&data) {
+                Some((size, entries)) if size <= log.file.size()? => {
+                    for (key, value, deleted_at) in entries {
+                        match value {
+                            Some((value_pos, value_len)) => {
+                                if track_tombstones {
+                                    tombstones.remove(&key);
+                                }
+                                keydir.insert(key, (*file_id, value_pos, value_len));
+                            }
+                            None => {
+                                keydir.remove(&key);
+                                if track_tombstones {
+                                    tombstones.insert(key, (deleted_at, *file_id));
+                                }
+                            }
+                        }
+                    }
+                    from = size;
+                }
+                _ => log::warn!("Ignoring invalid hint file for segment {} of {}", file_id, path.display()),
+            }
+        }
+        bytes_done += log.build_keydir(
+            *file_id,
+            from,
+            &mut keydir,
+            &mut tombstones,
+            track_tombstones,
+            &mut |pos| report(scanned as u64, bytes_done + pos),
+        )?;
+    }
+    report(segments_total, bytes_done);
+    Ok((keydir, tombstones))
+}
+
+fn read_value(segments: &Segments, key: &[u8], (file_id, value_pos, value_len): (u64, u64, u32)) -> Result<Vec<u8>> {
+    let log = segments
+        .get(&file_id)
+        .ok_or_else(|| Error::Internal(format!("missing log segment {}", file_id)))?;
+    log.read_entry(key, value_pos, value_len)
+}
+
+pub struct ScanIterator<'a, I = std::collections::btree_map::Range<'a, Vec<u8>, (u64, u64, u32)>> {
+    inner: I,
+    segments: &'a Segments,
+    inline_values: &'a std::collections::HashMap<Vec<u8>, Vec<u8>>,
+    // An error flushing buffered writes before the scan, returned as the
+    // first and only item.
+    error: Option<Error>,
+    failed: bool,
+}
+
+
+// A scan of the whole keydir, which unlike a range knows its length.
+pub type FullScanIterator<'a> = ScanIterator<'a, std::collections::btree_map::Iter<'a, Vec<u8>, (u64, u64, u32)>>;
+
+impl<'a, I> ScanIterator<'a, I>
+where
+    I: Iterator<Item = (&'a Vec<u8>, &'a (u64, u64, u32))>,
+{
+    fn take_error(&mut self) -> Option<Option<<Self as Iterator>::Item>> {
+        if let Some(err) = self.error.take() {
+            self.failed = true;
+            return Some(Some(Err(err)));
+        }
+        self.failed.then_some(None)
+    }
+
+    fn map(&mut self, item: (&Vec<u8>, &(u64, u64, u32))) -> <Self as Iterator>::Item {
+        let (key, entry) = item;
+        if let Some(value) = self.inline_values.get(key) {
+            return Ok((key.clone(), value.clone()));
+        }
+        Ok((key.clone(), read_value(self.segments, key, *entry)?))
+    }
+}
+
+impl<'a, I> Iterator for ScanIterator<'a, I>
+where
+    I: Iterator<Item = (&'a Vec<u8>, &'a (u64, u64, u32))>,
+{
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.take_error() {
+            return err;
+        }
+        self.inner.next().map(|item: (&Vec<u8>, &(u64, u64, u32))| self.map(item))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match (&self.error, self.failed) {
+            (Some(_), _) => (1, Some(1)),
+            (None, true) => (0, Some(0)),
+            (None, false) => self.inner.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for FullScanIterator<'_> {}
+
+impl<'a, I> DoubleEndedIterator for ScanIterator<'a, I>
+where
+    I: DoubleEndedIterator<Item = (&'a Vec<u8>, &'a (u64, u64, u32))>,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.take_error() {
+            return err;
+        }
+        self.inner.next_back().map(|item|self.map(item))
+    }
+}
+
+impl<'a, I> super::ScanIterator for ScanIterator<'a, I>
+where
+    I: DoubleEndedIterator<Item = (&'a Vec<u8>, &'a (u64, u64, u32))>,
+{}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use super::*;
+    
     use tempdir::{self, TempDir};
     const TEST_DIR: &str = "src/storage/bitcask/test/";
 
@@ -313,7 +2237,9 @@ mod tests {
     #[test]
     fn setup_log() -> Result<()> {
         
-        let mut s: BitCask = BitCask::new(PathBuf::from(TEST_DIR).join("setup_log_test"))?;
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let mut s: BitCask = BitCask::new(temp_dir.path().join("setup_log_test"))?;
         s.set(b"b", vec![0x01])?;
         s.set(b"b", vec![0x02])?;
 
@@ -354,7 +2280,7 @@ mod tests {
         .expect("Failed to create temporary directory");
         let temp_dir_path = temp_dir.path().join("set_test");
 
-        let mut s: BitCask = BitCask::new(PathBuf::from(temp_dir_path))?;
+        let mut s: BitCask = BitCask::new(temp_dir_path)?;
         s.set(b"a", vec![0x01])?;
         s.set(b"b", vec![0x02])?;
 
@@ -382,29 +2308,1225 @@ mod tests {
             s.scan(..).collect::<Result<Vec<_>>>()?
         );
 
+        let mut iter = s.scan_all();
+        assert_eq!(3, iter.len());
+        assert_eq!(Some(Ok((b"c".to_vec(), vec![0x03]))), iter.next_back());
+        assert_eq!(2, iter.len());
+        assert_eq!(s.scan(..).collect::<Result<Vec<_>>>()?, s.scan_all().collect::<Result<Vec<_>>>()?);
+
         Ok(())
 
     }
     
     #[test]
     fn test_delete() -> Result<()> {
-    //     let temp_dir = TempDir::new("bitcask_test")
-    //     .expect("Failed to create temporary directory");
-    //     let temp_dir_path: PathBuf = temp_dir.path().join("set_test");
-        let mut s: BitCask = BitCask::new(PathBuf::from(TEST_DIR).join("delete_test_1"))?;
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let temp_dir_path = temp_dir.path().join("delete_test_1");
+        let mut s: BitCask = BitCask::new(temp_dir_path.clone())?;
         s.set(b"a", vec![0x01])?;
         s.set(b"b", vec![0x02])?;
         s.set(b"c", vec![0x03])?;
 
-        assert_eq!(vec![02], s.get(b"b")?.unwrap());
+        assert_eq!(vec![0x02], s.get(b"b")?.unwrap());
 
         s.delete(b"a")?;
+        drop(s);
 
-        let mut t_s = BitCask::new(PathBuf::from(TEST_DIR).join("delete_test_1"))?;
+        let mut t_s = BitCask::new(temp_dir_path)?;
         assert_eq!(None, t_s.get(b"a")?);
-        assert_eq!(vec![02], t_s.get(b"b")?.unwrap());
-        assert_eq!(vec![03], t_s.get(b"c")?.unwrap());
+        assert_eq!(vec![0x02], t_s.get(b"b")?.unwrap());
+        assert_eq!(vec![0x03], t_s.get(b"c")?.unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hint_sequential() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let mut s = BitCask::new(temp_dir.path().join("hint_test"))?;
+        s.hint_sequential(..)?;
+
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02; 4096])?;
+        s.set(b"c", vec![0x03])?;
+        s.hint_sequential(b"a".to_vec()..b"c".to_vec())?;
+        s.hint_sequential(b"x".to_vec()..)?;
+
+        assert_eq!(vec![0x02; 4096], s.get(b"b")?.unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("progress_test");
+
+        let mut s = BitCask::new(path.clone())?;
+        for i in 0..3u8 {
+            s.set(&[i], vec![i; PROGRESS_INTERVAL as usize / 2])?;
+        }
+        let total = s.status()?.total_disk_size;
+        drop(s);
+
+        let mut events = Vec::new();
+        BitCask::new_with_progress(path, |p| events.push(p.clone()))?;
+        assert_eq!(
+            vec![0, 8 + 2 * (PROGRESS_INTERVAL / 2 + 13), total],
+            events.iter().map(|p| p.bytes_processed).collect::<Vec<_>>()
+        );
+        let last = events.last().unwrap();
+        assert_eq!((1, 1), (last.segments_scanned, last.segments_total));
+        assert_eq!(100.0, last.percent());
+        assert_eq!(Some(std::time::Duration::ZERO), last.eta());
+        assert_eq!(None, events[0].eta());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("compact_test");
+
+        let mut s = BitCask::new(path.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"a", vec![0x02])?;
+        s.set(b"b", vec![0x03])?;
+        s.delete(b"b")?;
+        s.set(b"c", vec![0x04])?;
+        s.compact()?;
+
+        let status = s.status()?;
+        // The merged and the new active segment each start with an 8-byte
+        // file header, and entries take 12 bytes besides the key and value.
+        assert_eq!((2, 0, 16 + 28), (status.keys, status.garbage_disk_size, status.total_disk_size));
+        // The compacted log is followed by a hint file with an 8-byte header,
+        // 17 bytes per key and a 4-byte checksum.
+        assert_eq!((9, 69 + 36 + 46), (status.logical_bytes_written, status.physical_bytes_written));
+        assert!((status.write_amplification() - 151.0 / 9.0).abs() < 1e-9);
+        drop(s);
+
+        let mut s = BitCask::new(path)?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x02]), (b"c".to_vec(), vec![0x04])],
+            s.scan(..).collect::<Result<Vec<_>>>()?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tombstone_retention() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("tombstone_test");
+        let clock = crate::clock::MockClock::new(Duration::from_secs(1000));
+        let options = Options {
+            tombstone_retention: Duration::from_secs(60),
+            clock: Arc::new(clock.clone()),
+            ..Default::default()
+        };
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.delete(b"a")?;
+        s.delete(b"c")?;
+        s.set(b"c", vec![0x03])?;
+
+        clock.advance(Duration::from_secs(30));
+        s.compact()?;
+        let status = s.status()?;
+        assert_eq!((0, 16 + 28 + 13), (status.garbage_disk_size, status.total_disk_size));
+
+        // Tombstones read back from a hint file keep their deletion time.
+        drop(s);
+        let mut s = BitCask::new_with_options(path, options)?;
+        assert_eq!(None, s.get(b"a")?);
+        clock.advance(Duration::from_secs(29));
+        s.compact()?;
+        assert_eq!(16 + 28 + 13, s.status()?.total_disk_size);
+
+        clock.advance(Duration::from_secs(1));
+        s.compact()?;
+        assert_eq!(16 + 28, s.status()?.total_disk_size);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_with_concurrent_writes() -> Result<()> {
+        let options = Options {
+            vfs: Arc::new(crate::storage::vfs::MemFs::new()),
+            tombstone_retention: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let path = PathBuf::from("/db/log");
+        let expect = vec![
+            (b"a".to_vec(), vec![0x02]),
+            (b"b".to_vec(), vec![0x03]),
+            (b"d".to_vec(), vec![0x04]),
+        ];
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x01])?;
+        s.set(b"c", vec![0x01])?;
+        s.delete(b"d")?;
+        s.set(b"a", vec![0x02])?;
+
+        // Writes made while the merge job runs win over the merged entries.
+        let job = s.start_merge()?.ok_or_else(|| Error::Internal("nothing to merge".to_string()))?;
+        assert!(s.compact().is_err());
+        s.set(b"b", vec![0x03])?;
+        s.delete(b"c")?;
+        s.set(b"d", vec![0x04])?;
+        s.finish_merge(job.run())?;
+
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        assert_eq!(vec![&b"c".to_vec()], s.tombstones.keys().collect::<Vec<_>>());
+        s.compact()?;
+        drop(s);
+        let mut s = BitCask::new_with_options(path, options)?;
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_filter() -> Result<()> {
+        // Drops expired sessions and strips the padding byte from everything else.
+        #[derive(Debug)]
+        struct Filter;
+
+        impl CompactionFilter for Filter {
+            fn filter(&self, key: &[u8], value: &[u8]) -> FilterDecision {
+                match (key.starts_with(b"session/"), value) {
+                    (true, [0x00]) => FilterDecision::Drop,
+                    (_, [value, 0xff]) => FilterDecision::Rewrite(vec![*value]),
+                    _ => FilterDecision::Keep,
+                }
+            }
+        }
+
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("filter_test");
+        let options = Options { compaction_filter: Some(Arc::new(Filter)), ..Default::default() };
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        s.set(b"session/1", vec![0x00])?;
+        s.set(b"session/2", vec![0x01])?;
+        s.set(b"user/1", vec![0x02, 0xff])?;
+        s.compact()?;
+
+        let expect = vec![(b"session/2".to_vec(), vec![0x01]), (b"user/1".to_vec(), vec![0x02])];
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        assert_eq!(0, s.status()?.garbage_disk_size);
+        drop(s);
+        let mut s = BitCask::new(path)?;
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_schedule() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        // 1970-01-01 01:00 UTC.
+        let clock = crate::clock::MockClock::new(Duration::from_secs(3600));
+        let options = Options {
+            clock: Arc::new(clock.clone()),
+            compaction_schedule: CompactionSchedule {
+                garbage_ratio: 0.5,
+                windows: vec![CompactionWindow::hours(22, 2)],
+                max_ops_per_sec: Some(1.0),
+            },
+            ..Default::default()
+        };
+        let mut s = BitCask::new_with_options(temp_dir.path().join("schedule_test"), options)?;
+        s.set(b"a", vec![0x01])?;
+        clock.advance(Duration::from_secs(10));
+        assert!(!s.maybe_compact()?, "not enough garbage");
+
+        s.set(b"a", vec![0x02])?;
+        s.set(b"a", vec![0x03])?;
+        clock.advance(Duration::from_secs(1));
+        assert!(!s.maybe_compact()?, "too busy");
+
+        clock.advance(Duration::from_secs(3 * 3600));
+        assert!(!s.maybe_compact()?, "outside the window");
+
+        clock.advance(Duration::from_secs(20 * 3600));
+        assert!(s.maybe_compact()?);
+        assert_eq!(0, s.status()?.garbage_disk_size);
+
+        s.set(b"a", vec![0x04])?;
+        s.set_compaction_schedule(CompactionSchedule { garbage_ratio: 0.0, ..Default::default() });
+        assert!(s.maybe_compact()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_validator() -> Result<()> {
+        #[derive(Debug)]
+        struct Validator;
+
+        impl WriteValidator for Validator {
+            fn validate(&self, key: &[u8], value: Option<&[u8]>) -> std::result::Result<(), String> {
+                if key.starts_with(b"_system/") {
+                    return Err("reserved prefix _system/".to_string());
+                }
+                match value {
+                    Some(value) if value.len() > 4 => Err(format!("value of {} bytes exceeds 4", value.len())),
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let options = Options { write_validator: Some(Arc::new(Validator)), ..Default::default() };
+        let mut s = BitCask::new_with_options(temp_dir.path().join("validator_test"), options)?;
+        s.set(b"a", vec![0; 4])?;
+        assert_eq!(
+            Err(Error::Rejected("value of 5 bytes exceeds 4".to_string())),
+            s.set(b"a", vec![0; 5])
+        );
+        assert_eq!(
+            Err(Error::Rejected("reserved prefix _system/".to_string())),
+            s.delete(b"_system/a")
+        );
+        assert_eq!(Some(vec![0; 4]), s.get(b"a")?);
+        assert_eq!(8 + 17, s.status()?.total_disk_size);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_values() -> Result<()> {
+        let mem = crate::storage::vfs::MemFs::new();
+        let faults = crate::storage::vfs::Faults::default();
+        let options = Options {
+            vfs: Arc::new(crate::storage::vfs::FaultyFs::new(Arc::new(mem), faults.clone())),
+            inline_value_size: 2,
+            inline_memory_budget: 6,
+            ..Default::default()
+        };
+        let path = PathBuf::from("/db/log");
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02; 3])?;
+        s.set(b"c", vec![0x03, 0x03])?;
+        s.set(b"d", vec![0x04])?;
+
+        // a and c fit within the budget, b is too large and d over budget.
+        faults.fail_reads(u32::MAX, std::io::ErrorKind::Other);
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert_eq!(Some(vec![0x03, 0x03]), s.get(b"c")?);
+        assert!(s.get(b"b").is_err());
+        assert!(s.get(b"d").is_err());
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01])],
+            s.scan(..b"b".to_vec()).collect::<Result<Vec<_>>>()?
+        );
+
+        // Overwrites and deletes replace or evict the inlined value, freeing
+        // budget for d once it is read from disk.
+        faults.clear();
+        s.set(b"c", vec![0x05; 3])?;
+        s.delete(b"a")?;
+        assert_eq!(Some(vec![0x04]), s.get(b"d")?);
+        faults.fail_reads(u32::MAX, std::io::ErrorKind::Other);
+        assert_eq!(None, s.get(b"a")?);
+        assert_eq!(Some(vec![0x04]), s.get(b"d")?);
+        assert!(s.get(b"c").is_err());
+        faults.clear();
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_coalescing() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("coalescing_test");
+        let clock = crate::clock::MockClock::new(Duration::from_secs(1000));
+        let options = Options {
+            clock: Arc::new(clock.clone()),
+            write_coalescing: Some(WriteCoalescing { interval: Duration::from_secs(1), max_bytes: 100 }),
+            ..Default::default()
+        };
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        for i in 0..10u8 {
+            s.set(b"counter", vec![i])?;
+        }
+        s.set(b"a", vec![0x01])?;
+        s.delete(b"a")?;
+        assert_eq!(Some(vec![9]), s.get(b"counter")?);
+        assert_eq!(None, s.get(b"a")?);
+        assert_eq!(8, s.status()?.total_disk_size);
+
+        // The interval has passed, so the next write flushes the buffer: one
+        // entry for the counter and a tombstone for a.
+        clock.advance(Duration::from_secs(1));
+        s.set(b"counter", vec![10])?;
+        let status = s.status()?;
+        assert_eq!((8 + 20 + 13, 13), (status.total_disk_size, status.garbage_disk_size));
+
+        // Exceeding max_bytes flushes too.
+        s.set(b"big", vec![0; 100])?;
+        assert_eq!(41 + 115, s.status()?.total_disk_size);
+
+        s.set(b"counter", vec![11])?;
+        assert_eq!(
+            vec![(b"big".to_vec(), vec![0; 100]), (b"counter".to_vec(), vec![11])],
+            s.scan(..).collect::<Result<Vec<_>>>()?
+        );
+
+        // Dropping flushes whatever is still buffered.
+        s.set(b"counter", vec![12])?;
+        drop(s);
+        let mut s = BitCask::new(path)?;
+        assert_eq!(Some(vec![12]), s.get(b"counter")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_keys() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let mut s = BitCask::new(temp_dir.path().join("sample_test"))?;
+        assert!(s.sample_keys(3)?.is_empty());
+
+        for i in 0..100u8 {
+            s.set(&[i], vec![i])?;
+        }
+        s.delete(&[7])?;
+
+        for sample in [s.sample_keys(10)?, crate::storage::Instrumented::new(s).sample_keys(10)?] {
+            let mut keys = sample.clone();
+            keys.sort();
+            keys.dedup();
+            assert_eq!(10, keys.len());
+            assert!(keys.iter().all(|k| k.len() == 1 && k[0] < 100 && k[0] != 7));
+        }
+
+        let mut counts = [0; 4];
+        for _ in 0..1000 {
+            for key in crate::storage::reservoir_sample((0..4).map(Ok), 1)? {
+                counts[key as usize] += 1;
+            }
+        }
+        assert!(counts.iter().all(|c| *c > 150), "skewed sample: {:?}", counts);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_errors() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let file = temp_dir.path().join("file");
+        std::fs::write(&file, b"")?;
+        assert!(matches!(BitCask::new(file.join("log")), Err(Error::Internal(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_tail() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("truncated_test");
+
+        let mut s = BitCask::new(path.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"bbbb", vec![0x02])?;
+        drop(s);
+
+        // Cut the log inside the second entry's key.
+        let file = fs::OpenOptions::new().write(true).open(&path)?;
+        file.set_len(8 + 14 + 12 + 2)?;
+        let mut s = BitCask::new(path.clone())?;
+        assert_eq!(vec![(b"a".to_vec(), vec![0x01])], s.scan(..).collect::<Result<Vec<_>>>()?);
+        assert_eq!(8 + 14, fs::metadata(&path)?.len());
+
+        // A corrupt header claiming a huge key must not be allocated.
+        let mut file = fs::OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&0u32.to_be_bytes())?;
+        file.write_all(&u32::MAX.to_be_bytes())?;
+        file.write_all(&0i32.to_be_bytes())?;
+        drop(file);
+        drop(s);
+        let mut s = BitCask::new(path.clone())?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert_eq!(8 + 14, fs::metadata(&path)?.len());
+
+        // Shrinking the log underneath an open store is reported, not a panic.
+        s.set(b"c", vec![0x03; 16])?;
+        fs::OpenOptions::new().write(true).open(&path)?.set_len(12)?;
+        assert!(matches!(s.status(), Err(Error::Internal(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_vfs() -> Result<()> {
+        use crate::storage::vfs::{Faults, FaultyFs, MemFs};
+
+        let mem = MemFs::new();
+        let faults = Faults::default();
+        let options = Options {
+            vfs: Arc::new(FaultyFs::new(Arc::new(mem.clone()), faults.clone())),
+            ..Default::default()
+        };
+        let path = PathBuf::from("/db/log");
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        faults.fail_writes(1, std::io::ErrorKind::Interrupted);
+        assert!(matches!(s.set(b"b", vec![0x02]), Err(Error::Transient(_))));
+        assert_eq!(None, s.get(b"b")?);
+
+        faults.fail_reads(1, std::io::ErrorKind::Other);
+        assert!(matches!(s.get(b"a"), Err(Error::Internal(_))));
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+
+        // A torn write is rolled back right away, so the writes after it
+        // survive reopening the log.
+        faults.tear_next_write(5);
+        assert!(s.set(b"c", vec![0x03]).is_err());
+        assert_eq!(Some(8 + 14), mem.read(&path).map(|data| data.len()));
+        s.set(b"d", vec![0x04])?;
+        assert_eq!(Some(vec![0x04]), s.get(b"d")?);
+
+        // Failed writes don't count as written.
+        faults.fail_writes(1, std::io::ErrorKind::Other);
+        assert!(s.delete(b"a").is_err());
+        faults.fail_writes(1, std::io::ErrorKind::Other);
+        assert!(s.write_batch(vec![(b"e".to_vec(), Some(vec![0x05]))]).is_err());
+        assert_eq!(4, s.status()?.logical_bytes_written);
+        drop(s);
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01]), (b"d".to_vec(), vec![0x04])],
+            s.scan(..).collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(Some(8 + 28), mem.read(&path).map(|data| data.len()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_sync() -> Result<()> {
+        use crate::storage::vfs::{Faults, FaultyFs, MemFs};
+
+        let mem = MemFs::new();
+        let faults = Faults::default();
+        let options = Options {
+            vfs: Arc::new(FaultyFs::new(Arc::new(mem.clone()), faults.clone())),
+            sync_policy: SyncPolicy::EveryWrite,
+            ..Default::default()
+        };
+        let path = PathBuf::from("/db/log");
+
+        // A write whose sync fails is cut off the log, so it doesn't take
+        // effect when the store is reopened either.
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        faults.fail_syncs(1, std::io::ErrorKind::Other);
+        assert!(s.set(b"a", vec![0x03]).is_err());
+        faults.fail_syncs(1, std::io::ErrorKind::Other);
+        assert!(s.delete(b"b").is_err());
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert_eq!(Some(vec![0x02]), s.get(b"b")?);
+        assert_eq!(Some(8 + 28), mem.read(&path).map(|data| data.len()));
+        drop(s);
+
+        let mut s = BitCask::new_with_options(path, options)?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert_eq!(Some(vec![0x02]), s.get(b"b")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_forecast() -> Result<()> {
+        let clock = crate::clock::MockClock::new(Duration::from_secs(1000));
+        let options = Options {
+            vfs: Arc::new(crate::storage::vfs::MemFs::new()),
+            clock: Arc::new(clock.clone()),
+            compaction_schedule: CompactionSchedule { garbage_ratio: 0.6, ..Default::default() },
+            ..Default::default()
+        };
+        let forecast = |s: &BitCask| -> Result<super::super::Forecast> {
+            s.status()?.forecast.ok_or_else(|| Error::Internal("no forecast".to_string()))
+        };
+
+        let mut s = BitCask::new_with_options(PathBuf::from("/db/log"), options.clone())?;
+        assert_eq!((0.0, 0.0, None, None), {
+            let f = forecast(&s)?;
+            (f.growth_rate, f.garbage_rate, f.until_disk_full, f.until_compaction)
+        });
+
+        // 42 bytes of entries in 10 seconds, 28 of them overwritten. Garbage
+        // reaches 60% of the log after (0.6 * 50 - 28) / (2.8 - 0.6 * 4.2)
+        // seconds.
+        for i in 0..3 {
+            s.set(b"a", vec![i])?;
+        }
+        clock.advance(Duration::from_secs(10));
+        let f = forecast(&s)?;
+        assert_eq!((4.2, 2.8, None), (f.growth_rate, f.garbage_rate, f.until_disk_full));
+        let until_compaction = f.until_compaction.map(|d| d.as_secs_f64()).unwrap_or_default();
+        assert!((until_compaction - 2.0 / 0.28).abs() < 1e-6);
+
+        // Compaction restarts the extrapolation.
+        s.compact()?;
+        let f = forecast(&s)?;
+        assert_eq!((0.0, 0.0, None), (f.growth_rate, f.garbage_rate, f.until_compaction));
+
+        // Stores on a real filesystem also forecast when it is full.
+        let dir = TempDir::new("bitcask_test").expect("Failed to create temporary directory");
+        let options = Options { vfs: Arc::new(StdFs), ..options };
+        let mut s = BitCask::new_with_options(dir.path().join("log"), options)?;
+        s.set(b"a", vec![0x01])?;
+        clock.advance(Duration::from_secs(1));
+        assert!(forecast(&s)?.until_disk_full.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_degraded() -> Result<()> {
+        use crate::storage::vfs::{Faults, FaultyFs, MemFs};
+
+        let mem = MemFs::new();
+        let faults = Faults::default();
+        let options = Options {
+            vfs: Arc::new(FaultyFs::new(Arc::new(mem.clone()), faults.clone())),
+            max_write_failures: 3,
+            ..Default::default()
+        };
+        let path = PathBuf::from("/db/log");
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        faults.fail_writes(1, std::io::ErrorKind::Other);
+        assert!(s.set(b"b", vec![0x02]).is_err());
+        s.set(b"b", vec![0x02])?;
+
+        faults.tear_next_write(5);
+        assert!(matches!(s.set(b"c", vec![0x03]), Err(Error::Internal(_))));
+        faults.fail_writes(2, std::io::ErrorKind::Other);
+        assert!(matches!(s.delete(b"a"), Err(Error::Internal(_))));
+        assert!(!s.is_degraded());
+        assert!(matches!(s.delete(b"a"), Err(Error::Internal(_))));
+        assert!(s.is_degraded());
+
+        // Writes are refused without touching the disk, reads still work.
+        faults.fail_writes(1, std::io::ErrorKind::Other);
+        assert!(matches!(s.set(b"c", vec![0x03]), Err(Error::Degraded(_))));
+        assert!(matches!(s.write_batch(vec![(b"c".to_vec(), None)]), Err(Error::Degraded(_))));
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert!(s.recover().is_err());
+        assert!(s.is_degraded());
+
+        // Recovery drops the torn entry, so later writes stay readable.
+        s.recover()?;
+        s.set(b"c", vec![0x03])?;
+        drop(s);
+        let mut s = BitCask::new_with_options(path, options)?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01]), (b"b".to_vec(), vec![0x02]), (b"c".to_vec(), vec![0x03])],
+            s.scan(..).collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_policy() -> Result<()> {
+        use crate::storage::vfs::{Faults, FaultyFs, MemFs};
+        use std::io::ErrorKind;
+
+        let faults = Faults::default();
+        let clock = crate::clock::MockClock::new(Duration::from_secs(1000));
+        let options = |sync_policy| Options {
+            vfs: Arc::new(FaultyFs::new(Arc::new(MemFs::new()), faults.clone())),
+            clock: Arc::new(clock.clone()),
+            sync_policy,
+            ..Default::default()
+        };
+        let path = PathBuf::from("/db/log");
+
+        // Failing syncs show which writes sync.
+        let mut s = BitCask::new_with_options(path.clone(), options(SyncPolicy::Never))?;
+        faults.fail_syncs(1, ErrorKind::Other);
+        s.set(b"a", vec![0x01])?;
+        assert!(s.sync().is_err());
+        faults.clear();
+
+        let mut s = BitCask::new_with_options(path.clone(), options(SyncPolicy::EveryWrite))?;
+        faults.fail_syncs(1, ErrorKind::Other);
+        assert!(s.set(b"a", vec![0x01]).is_err());
+        s.set(b"a", vec![0x01])?;
+
+        let interval = Duration::from_secs(10);
+        let mut s = BitCask::new_with_options(path.clone(), options(SyncPolicy::Interval(interval)))?;
+        faults.fail_syncs(1, ErrorKind::Other);
+        s.set(b"a", vec![0x01])?;
+        clock.advance(interval);
+        assert!(s.set(b"b", vec![0x02]).is_err());
+        s.set(b"b", vec![0x02])?;
+        faults.fail_syncs(1, ErrorKind::Other);
+        s.delete(b"a")?;
+        faults.clear();
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_batch() -> Result<()> {
+        use crate::storage::vfs::{Faults, FaultyFs, MemFs};
+
+        let mem = MemFs::new();
+        let faults = Faults::default();
+        let options = Options {
+            vfs: Arc::new(FaultyFs::new(Arc::new(mem.clone()), faults.clone())),
+            ..Default::default()
+        };
+        let path = PathBuf::from("/db/log");
+        let scan = |s: &mut BitCask| s.scan(..).collect::<Result<Vec<_>>>();
+        let expect = vec![(b"b".to_vec(), vec![0x04]), (b"c".to_vec(), vec![0x03])];
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.write_batch(vec![
+            (b"a".to_vec(), None),
+            (b"b".to_vec(), Some(vec![0x02])),
+            (b"c".to_vec(), Some(vec![0x03])),
+            (b"b".to_vec(), Some(vec![0x04])),
+        ])?;
+        assert_eq!(expect, scan(&mut s)?);
+        drop(s);
+        assert_eq!(Some(8 + 14 + 20 + 13 + 3 * 14), mem.read(&path).map(|data| data.len()));
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(expect, scan(&mut s)?);
+
+        // A torn batch is dropped as a whole: rolled back right away if it was
+        // cut short, and when the log is reopened if it has the right length
+        // but the wrong contents.
+        faults.tear_next_write(40);
+        assert!(s.write_batch(vec![(b"d".to_vec(), Some(vec![0x05])), (b"b".to_vec(), None)]).is_err());
+        assert_eq!(expect, scan(&mut s)?);
+        assert_eq!(Some(97), mem.read(&path).map(|data| data.len()));
+        drop(s);
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(expect, scan(&mut s)?);
+        assert_eq!(Some(97), mem.read(&path).map(|data| data.len()));
+
+        s.write_batch(vec![(b"d".to_vec(), Some(vec![0x05])), (b"b".to_vec(), None)])?;
+        drop(s);
+        let mut data = mem.read(&path).unwrap_or_default();
+        data[97 + 20 + 13] ^= 0x01;
+        mem.write(&path, data);
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        assert_eq!(expect, scan(&mut s)?);
+        assert_eq!(Some(97), mem.read(&path).map(|data| data.len()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_alignment() -> Result<()> {
+        let mem = crate::storage::vfs::MemFs::new();
+        let options = Options { vfs: Arc::new(mem.clone()), record_alignment: 64, ..Default::default() };
+        let path = PathBuf::from("/db/log");
+        let size = || mem.read(&path).map(|data| data.len());
+        let truncate = |len: usize| mem.write(&path, mem.read(&path).unwrap_or_default()[..len].to_vec());
+        let keys = |s: &mut BitCask| s.scan(..).map(|item| item.map(|(key, _)| key)).collect::<Result<Vec<_>>>();
+
+        let invalid = Options { record_alignment: 100, ..options.clone() };
+        assert!(BitCask::new_with_options(path.clone(), invalid).is_err());
+
+        // The header and every record take a multiple of 64 bytes.
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(Some(64), size());
+        s.set(b"a", vec![0x01])?;
+        assert_eq!(Some(128), size());
+        s.write_batch(vec![(b"b".to_vec(), Some(vec![0x02])), (b"c".to_vec(), None)])?;
+        assert_eq!(Some(192), size());
+        s.set(b"d", vec![0x04; 100])?;
+        assert_eq!(Some(320), size());
+        assert_eq!(Some(vec![0x04; 100]), s.get(b"d")?);
+        drop(s);
+
+        // A record whose padding was cut short is kept, and the padding
+        // restored.
+        truncate(310);
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"d".to_vec()], keys(&mut s)?);
+        assert_eq!(Some(320), size());
+        drop(s);
+
+        // A torn record is dropped, and the next one starts at its offset.
+        truncate(250);
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec()], keys(&mut s)?);
+        assert_eq!(Some(192), size());
+        s.set(b"e", vec![0x05])?;
+        assert_eq!(Some(256), size());
+
+        s.compact()?;
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec(), b"e".to_vec()], keys(&mut s)?);
+        drop(s);
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        assert_eq!(Some(vec![0x05]), s.get(b"e")?);
+        assert_eq!(Some(64), size());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_preallocate_segments() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("log");
+        let options = Options { preallocate_segments: true, max_segment_size: 1 << 20, ..Default::default() };
+        let allocated = |path: &Path| -> Result<u64> {
+            Ok(std::os::unix::fs::MetadataExt::blocks(&std::fs::metadata(path)?) * 512)
+        };
+
+        // The active segment's size only covers its records, though its
+        // blocks are reserved.
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01; 1000])?;
+        let size = std::fs::metadata(&path)?.len();
+        assert!(size < 2000);
+        #[cfg(target_os = "linux")]
+        assert!(allocated(&path)? >= 1 << 20);
+
+        // Sealing the segment releases the reservation.
+        s.set(b"b", vec![0x02; 1 << 20])?;
+        let sealed = segment_path(&path, 1, "");
+        assert_eq!(size, std::fs::metadata(&sealed)?.len());
+        assert!(allocated(&sealed)? < 1 << 20);
+        drop(s);
+
+        let mut s = BitCask::new_with_options(path, options.clone())?;
+        assert_eq!(Some(vec![0x01; 1000]), s.get(b"a")?);
+        assert_eq!(Some(vec![0x02; 1 << 20]), s.get(b"b")?);
+        drop(s);
+
+        // Read-only and unlocked opens don't preallocate: the injected write
+        // fault is still pending for the writer afterwards.
+        let faults = crate::storage::vfs::Faults::default();
+        let mem = Arc::new(crate::storage::vfs::MemFs::new());
+        let options = Options { vfs: Arc::new(crate::storage::vfs::FaultyFs::new(mem, faults.clone())), ..options };
+        let path = PathBuf::from("/db/log");
+        drop(BitCask::new_with_options(path.clone(), options.clone())?);
+        faults.fail_writes(1, std::io::ErrorKind::Other);
+        drop(BitCask::new_with_options(path.clone(), Options { read_only: true, ..options.clone() })?);
+        drop(BitCask::open_read_only_with_options(path.clone(), options.clone())?);
+        let mut s = BitCask::new_with_options(path, Options { preallocate_segments: false, ..options })?;
+        assert!(s.set(b"a", vec![0x01]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_punch_holes() -> Result<()> {
+        let mem = crate::storage::vfs::MemFs::new();
+        let options = Options { vfs: Arc::new(mem.clone()), ..Default::default() };
+        let path = PathBuf::from("/db/log");
+        let segment = |id| mem.read(&segment_path(&path, id, "")).unwrap_or_default();
+
+        // Segment 1 holds b's deleted value, segment 2 is mostly overwritten,
+        // and segment 3 is the active one.
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01; 10000])?;
+        s.set(b"b", vec![0x02])?;
+        s.rotate()?;
+        s.delete(b"b")?;
+        s.set(b"c", vec![0x03; 5000])?;
+        s.set(b"c", vec![0x04; 5000])?;
+        s.set(b"c", vec![0x05])?;
+        s.rotate()?;
+        s.set(b"d", vec![0x06; 10000])?;
+
+        // Everything but the file headers and live entries is zeroed.
+        let sizes = (segment(1).len(), segment(2).len());
+        s.punch_holes()?;
+        assert_eq!(sizes, (segment(1).len(), segment(2).len()));
+        assert!(mem.read(&segment_path(&path, 1, ".hint")).is_some());
+        assert!(segment(1)[segment(1).len() - 14..].iter().all(|b| *b == 0));
+        assert!(segment(2)[entry::FILE_HEADER_SIZE as usize..10000].iter().all(|b| *b == 0));
+        assert_eq!(None, mem.read(&segment_path(&path, 3, ".hint")));
+        s.punch_holes()?;
+        assert_eq!(Some(vec![0x05]), s.get(b"c")?);
+
+        // A segment whose hint file fails its checksum is left alone rather
+        // than given a new hint from its already punched entries.
+        let hint_path = segment_path(&path, 2, ".hint");
+        let hint = mem.read(&hint_path).unwrap_or_default();
+        let mut corrupt = hint.clone();
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 0xff;
+        mem.write(&hint_path, corrupt.clone());
+        s.punch_holes()?;
+        assert_eq!(Some(corrupt), mem.read(&hint_path));
+        mem.write(&hint_path, hint);
+        drop(s);
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(Some(vec![0x01; 10000]), s.get(b"a")?);
+        assert_eq!(None, s.get(b"b")?);
+        assert_eq!(Some(vec![0x05]), s.get(b"c")?);
+        assert_eq!(Some(vec![0x06; 10000]), s.get(b"d")?);
+        drop(s);
+
+        // Without the hint files the zeroed ranges are skipped, and b stays
+        // deleted even though its tombstone is gone.
+        for id in [1, 2] {
+            options.vfs.remove(&segment_path(&path, id, ".hint"))?;
+        }
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        assert_eq!(Some(vec![0x01; 10000]), s.get(b"a")?);
+        assert_eq!(None, s.get(b"b")?);
+        assert_eq!(Some(vec![0x05]), s.get(b"c")?);
+        assert_eq!(Some(vec![0x06; 10000]), s.get(b"d")?);
+
+        s.compact()?;
+        assert_eq!(3, s.status()?.keys);
+        Ok(())
+    }
+
+    #[test]
+    fn test_locking() -> Result<()> {
+        let mem = crate::storage::vfs::MemFs::new();
+        let options = Options { vfs: Arc::new(mem.clone()), ..Default::default() };
+        let read_only = Options { read_only: true, ..options.clone() };
+        let path = PathBuf::from("/db/log");
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.rotate()?;
+        s.set(b"b", vec![0x02])?;
+        assert!(matches!(BitCask::new_with_options(path.clone(), options.clone()), Err(Error::InUse(_))));
+        assert!(matches!(BitCask::new_with_options(path.clone(), read_only.clone()), Err(Error::InUse(_))));
+
+        // A second open fails before it cleans up leftovers or completes a
+        // merge, which would remove segments from under the writer.
+        let planted = [segment_path(&path, 2, ".merged"), segment_path(&path, 3, ".new")];
+        for file in &planted {
+            mem.write(file, vec![0xff; 3]);
+        }
+        assert!(matches!(BitCask::new_with_options(path.clone(), options.clone()), Err(Error::InUse(_))));
+        assert!(mem.read(&segment_path(&path, 1, "")).is_some());
+        assert!(planted.iter().all(|file| mem.read(file).is_some()));
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        for file in &planted {
+            options.vfs.remove(file)?;
+        }
+        drop(s);
+
+        // Read-only handles share the store, but refuse writes and keep
+        // writers out.
+        let mut r1 = BitCask::new_with_options(path.clone(), read_only.clone())?;
+        let mut r2 = BitCask::new_with_options(path.clone(), read_only)?;
+        assert_eq!(Some(vec![0x01]), r1.get(b"a")?);
+        assert_eq!(Some(vec![0x02]), r2.get(b"b")?);
+        assert!(matches!(r1.set(b"c", vec![0x03]), Err(Error::Value(_))));
+        assert!(matches!(r1.delete(b"a"), Err(Error::Value(_))));
+        assert!(r1.compact().is_err());
+        assert!(matches!(BitCask::new_with_options(path.clone(), options.clone()), Err(Error::InUse(_))));
+        drop(r1);
+        drop(r2);
+
+        let mut s = BitCask::new_with_options(path, options)?;
+        s.set(b"c", vec![0x03])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_read_only() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("read_only_test");
+        assert!(BitCask::open_read_only(path.clone()).is_err());
+
+        // A snapshot can be opened next to the writer, and refuses writes.
+        let mut s = BitCask::new(path.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        let mut r = BitCask::open_read_only(path.clone())?;
+        s.set(b"c", vec![0x03])?;
+        assert_eq!(Some(vec![0x01]), r.get(b"a")?);
+        assert_eq!(None, r.get(b"c")?);
+        assert!(matches!(r.set(b"d", vec![0x04]), Err(Error::Value(_))));
+        assert!(matches!(r.delete(b"a"), Err(Error::Value(_))));
+        assert!(r.compact().is_err());
+        drop(r);
+        drop(s);
+
+        // A torn tail is left in place.
+        let mut file = fs::OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&[0x00, 0x00, 0x00])?;
+        let len = fs::metadata(&path)?.len();
+        let mut r = BitCask::open_read_only(path.clone())?;
+        assert_eq!(3, r.status()?.keys);
+        assert_eq!(Some(vec![0x03]), r.get(b"c")?);
+        assert_eq!(len, fs::metadata(&path)?.len());
+        drop(r);
+        BitCask::new(path.clone())?;
+        assert_eq!(len - 3, fs::metadata(&path)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksums() -> Result<()> {
+        let mem = crate::storage::vfs::MemFs::new();
+        let options = Options { vfs: Arc::new(mem.clone()), ..Default::default() };
+        let path = PathBuf::from("/db/log");
+        let flip = |pos: usize| {
+            let mut data = mem.read(&path).unwrap_or_default();
+            data[pos] ^= 0x01;
+            mem.write(&path, data);
+        };
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.set(b"c", vec![0x03])?;
+
+        // Flip a bit in the value of b, after the file header, a and the
+        // checksum, header and key of b.
+        flip(8 + 14 + 13);
+        assert!(matches!(s.get(b"b"), Err(Error::Corruption(_))));
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        drop(s);
+        assert!(matches!(BitCask::new_with_options(path.clone(), options.clone()), Err(Error::Corruption(_))));
+
+        // A bad checksum on the last entry is taken for a torn write.
+        flip(8 + 14 + 13);
+        flip(8 + 28 + 13);
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01]), (b"b".to_vec(), vec![0x02])],
+            s.scan(..).collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(Some(8 + 28), mem.read(&path).map(|data| data.len()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_segments() -> Result<()> {
+        let mem = crate::storage::vfs::MemFs::new();
+        let options = Options { vfs: Arc::new(mem.clone()), max_segment_size: 36, ..Default::default() };
+        let path = PathBuf::from("/db/log");
+        let files = || -> Result<Vec<_>> {
+            let mut files = options.vfs.read_dir(Path::new("/db"))?;
+            files.retain(|file| !matches!(file.extension().and_then(|ext| ext.to_str()), Some("access" | "lock")));
+            files.sort();
+            Ok(files.into_iter().map(|file| (file.display().to_string(), mem.read(&file).map_or(0, |d| d.len()))).collect())
+        };
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.set(b"c", vec![0x03])?;
+        s.set(b"a", vec![0x04])?;
+        s.set(b"d", vec![0x05])?;
+        s.delete(b"b")?;
+        assert_eq!(
+            vec![("/db/log".to_string(), 35), ("/db/log.000001".to_string(), 36), ("/db/log.000002".to_string(), 36)],
+            files()?
+        );
+        assert_eq!(Some(vec![0x04]), s.get(b"a")?);
+        let status = s.status()?;
+        assert_eq!((3, 107, 41), (status.keys, status.total_disk_size, status.garbage_disk_size));
+        drop(s);
+
+        let expect = vec![
+            (b"a".to_vec(), vec![0x04]),
+            (b"c".to_vec(), vec![0x03]),
+            (b"d".to_vec(), vec![0x05]),
+        ];
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        s.compact()?;
+        assert_eq!(
+            vec![
+                ("/db/log".to_string(), 8),
+                ("/db/log.000003".to_string(), 50),
+                ("/db/log.000003.hint".to_string(), 8 + 3 * 17 + 4),
+            ],
+            files()?
+        );
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        drop(s);
+
+        // The keydir of the merged segment is loaded from its hint file.
+        let hint = mem.read(Path::new("/db/log.000003.hint")).unwrap_or_default();
+        let keydir = KeyDir::from([(b"a".to_vec(), (3, 21, 1))]);
+        mem.write(Path::new("/db/log.000003.hint"), encode_hint(50, &keydir, &Tombstones::new()));
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(expect[..1], s.scan(..).collect::<Result<Vec<_>>>()?);
+        drop(s);
+
+        // One that fails its checksum is ignored and the segment scanned.
+        let mut corrupt = encode_hint(50, &keydir, &Tombstones::new());
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 0xff;
+        mem.write(Path::new("/db/log.000003.hint"), corrupt);
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        drop(s);
+        mem.write(Path::new("/db/log.000003.hint"), hint);
+
+        // A merge interrupted after the merged segment was written is
+        // completed on open, and a partially written one is discarded.
+        mem.write(Path::new("/db/log.000003.merged"), mem.read(Path::new("/db/log.000003")).unwrap_or_default());
+        mem.write(Path::new("/db/log.000001"), entry::encode(b"x", Some(&[0x06]))?);
+        mem.write(Path::new("/db/log.000004.new"), vec![0xff; 3]);
+        let mut s = BitCask::new_with_options(path, options.clone())?;
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        assert_eq!(vec![("/db/log".to_string(), 8), ("/db/log.000003".to_string(), 50)], files()?);
+        Ok(())
+    }
+
+    // Each format_v* fixture is a store written by a version of the on-disk
+    // format, with the same operations. The current code must keep reading
+    // all of them, and writing the operations must still produce the bytes of
+    // the latest one.
+    #[test]
+    fn test_format_fixtures() -> Result<()> {
+        for version in ["format_v1", "format_v2"] {
+            let fixture = PathBuf::from(TEST_DIR).join(version);
+            let temp_dir = TempDir::new("bitcask_test")
+                .expect("Failed to create temporary directory");
+            for name in ["log.000001", "log"] {
+                fs::copy(fixture.join(name), temp_dir.path().join(name))?;
+            }
+
+            let expect = vec![
+                (b"".to_vec(), vec![]),
+                (vec![0x00, 0xff], vec![0xab; 300]),
+                (b"a".to_vec(), vec![0x02]),
+                (b"b".to_vec(), b"hello".to_vec()),
+            ];
+            let mut s = BitCask::new(temp_dir.path().join("log"))?;
+            assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+            assert_eq!(None, s.get(b"c")?);
+            assert_eq!(Some(b"hello".to_vec()), s.get(b"b")?);
+
+            // Version 1 segments have no checksums to resume scanning after
+            // a zeroed range, so they aren't punched.
+            let sealed = fs::read(temp_dir.path().join("log.000001"))?;
+            s.punch_holes()?;
+            drop(s);
+            let hint = temp_dir.path().join("log.000001.hint");
+            assert_eq!(version == "format_v1", sealed == fs::read(temp_dir.path().join("log.000001"))?);
+            assert_eq!(version == "format_v2", hint.exists());
+            if hint.exists() {
+                fs::remove_file(hint)?;
+            }
+            let mut s = BitCask::new(temp_dir.path().join("log"))?;
+            assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        }
+
+        let fixture = PathBuf::from(TEST_DIR).join("format_v2");
+        let mem = crate::storage::vfs::MemFs::new();
+        let sealed = fs::read(fixture.join("log.000001"))?;
+        let options = Options {
+            vfs: Arc::new(mem.clone()),
+            max_segment_size: sealed.len() as u64,
+            ..Default::default()
+        };
+        let mut s = BitCask::new_with_options(PathBuf::from("/db/log"), options)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(&[0x00, 0xff], vec![0xab; 300])?;
+        s.set(b"", vec![])?;
+        s.set(b"c", vec![0x03])?;
+        s.set(b"a", vec![0x02])?;
+        s.delete(b"c")?;
+        s.set(b"b", b"hello".to_vec())?;
+        s.delete(b"z")?;
+        assert_eq!(Some(sealed), mem.read(Path::new("/db/log.000001")));
+        assert_eq!(Some(fs::read(fixture.join("log"))?), mem.read(Path::new("/db/log")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert() -> Result<()> {
+        let fixture = PathBuf::from(TEST_DIR).join("format_v1");
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        for name in ["log.000001", "log"] {
+            fs::copy(fixture.join(name), temp_dir.path().join(name))?;
+        }
+        let mut s = BitCask::new(temp_dir.path().join("log"))?;
+        let expect = s.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(Some(1), s.convert()?);
+        drop(s);
+
+        let mut s = BitCask::new(temp_dir.path().join("log"))?;
+        assert!(s.segments.values().all(|log| log.version == 2));
+        assert_eq!(expect, s.scan(..).collect::<Result<Vec<_>>>()?);
+        assert_eq!(None, s.get(b"c")?);
+        assert_eq!(None, s.convert()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_warm_up() -> Result<()> {
+        let mem = crate::storage::vfs::MemFs::new();
+        let options = Options { vfs: Arc::new(mem.clone()), track_reads: true, ..Default::default() };
+        let path = PathBuf::from("/db/log");
+
+        // Reads are only tracked when asked for.
+        let untracked = Options { track_reads: false, ..options.clone() };
+        let mut s = BitCask::new_with_options(PathBuf::from("/db/untracked"), untracked)?;
+        s.set(b"a", vec![0x01])?;
+        s.get(b"a")?;
+        drop(s);
+        assert_eq!(None, mem.read(Path::new("/db/untracked.access")));
+
+        let mut s = BitCask::new_with_options(path.clone(), options.clone())?;
+        s.warm_up()?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0; ACCESS_CHUNK as usize])?;
+        s.set(b"c", vec![0x03])?;
+        s.get(b"a")?;
+        s.get(b"c")?;
+        s.get(b"c")?;
+        drop(s);
+
+        let access = mem.read(Path::new("/db/log.access"));
+        let entries = [1u64, 1, 1, 0];
+        assert_eq!(Some(entries.iter().flat_map(|n| n.to_be_bytes()).collect()), access);
+        let mut s = BitCask::new_with_options(path, options)?;
+        s.warm_up()?;
+        assert_eq!(Some(vec![0x03]), s.get(b"c")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_scan() -> Result<()> {
+        let temp_dir = TempDir::new("bitcask_test")
+            .expect("Failed to create temporary directory");
+        let mut s = BitCask::new(temp_dir.path().join("parallel_test"))?;
+        for i in 0..100u8 {
+            s.set(&[i], vec![i; i as usize])?;
+        }
 
+        let expect = s.scan(vec![10]..vec![90]).collect::<Result<Vec<_>>>()?;
+        assert_eq!(expect, s.parallel_scan_collect(vec![10]..vec![90], 4)?);
+        assert_eq!(expect, s.parallel_scan_collect(vec![10]..vec![90], 1000)?);
+        assert!(s.parallel_scan_collect(vec![200].., 4)?.is_empty());
+
+        let bytes = std::sync::atomic::AtomicUsize::new(0);
+        s.parallel_scan(.., 3, |_, value| {
+            bytes.fetch_add(value.len(), std::sync::atomic::Ordering::Relaxed);
+            Ok(())
+        })?;
+        assert_eq!((0..100).sum::<usize>(), bytes.into_inner());
+
+        let result = s.parallel_scan(.., 3, |key, _| match key {
+            [50] => Err(Error::Value("stop".to_string())),
+            _ => Ok(()),
+        });
+        assert_eq!(Err(Error::Value("stop".to_string())), result);
         Ok(())
     }
 
@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use super::{read_value, BitCask, Segments};
+use crate::error::{Error, Result};
+use crate::storage::scheduler::{IoClass, IoScheduler};
+use crate::storage::Engine;
+
+// Where a scrub fetches a fresh copy of a corrupt value from, e.g. a replica
+// or an archive. None means the source doesn't have the key either.
+pub trait RepairSource: Send + Sync {
+    fn fetch(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScrubReport {
+    // Live entries checked, and the bytes of their values read.
+    pub entries: u64,
+    pub bytes: u64,
+    // Keys whose entry failed its checksum and couldn't be repaired.
+    pub corrupt: Vec<Vec<u8>>,
+    // Keys whose entry failed its checksum and were rewritten from the
+    // repair source.
+    pub repaired: Vec<Vec<u8>>,
+}
+
+// The live entries to check, as of BitCask::start_scrub.
+pub(super) struct ScrubJob {
+    entries: Vec<(Vec<u8>, (u64, u64, u32))>,
+    segments: Segments,
+    io_scheduler: Option<Arc<IoScheduler>>,
+}
+
+// What a scrub job found: the counts for the report, and the corrupt entries.
+pub(super) struct Scrubbed {
+    entries: u64,
+    bytes: u64,
+    corrupt: Vec<(Vec<u8>, (u64, u64, u32))>,
+}
+
+impl BitCask {
+    // Reads every live entry and verifies its checksum, throttled as
+    // IoClass::Scrub. A corrupt entry that is still the key's latest is
+    // rewritten with the value from repair, if given and it has the key, and
+    // reported otherwise. Entries in version 1 segments have no checksums, so
+    // only their framing is checked. See Scrubber to run this periodically.
+    pub fn scrub(&mut self, repair: Option<&dyn RepairSource>) -> Result<ScrubReport> {
+        let job = self.start_scrub()?;
+        self.finish_scrub(job.run(), repair)
+    }
+
+    pub(super) fn start_scrub(&mut self) -> Result<ScrubJob> {
+        self.flush()?;
+        Ok(ScrubJob {
+            entries: self.keydir.iter().map(|(key, entry)| (key.clone(), *entry)).collect(),
+            segments: self.segments.clone(),
+            io_scheduler: self.options.io_scheduler.clone(),
+        })
+    }
+
+    pub(super) fn finish_scrub(
+        &mut self,
+        scrubbed: Result<Scrubbed>,
+        repair: Option<&dyn RepairSource>,
+    ) -> Result<ScrubReport> {
+        let scrubbed = scrubbed?;
+        let mut report = ScrubReport { entries: scrubbed.entries, bytes: scrubbed.bytes, ..Default::default() };
+        for (key, entry) in scrubbed.corrupt {
+            // The key was overwritten or deleted since, or merged into a
+            // segment that the corruption didn't make it into.
+            if self.keydir.get(&key) != Some(&entry) {
+                continue;
+            }
+            let value = match repair {
+                Some(repair) => repair.fetch(&key)?,
+                None => None,
+            };
+            match value {
+                Some(value) => {
+                    log::warn!("Repairing corrupt entry in segment {} of {}", entry.0, self.path.display());
+                    self.set(&key, value)?;
+                    report.repaired.push(key);
+                }
+                None => {
+                    log::error!("Corrupt entry in segment {} of {}", entry.0, self.path.display());
+                    report.corrupt.push(key);
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl ScrubJob {
+    pub(super) fn run(self) -> Result<Scrubbed> {
+        let mut scrubbed = Scrubbed { entries: 0, bytes: 0, corrupt: Vec::new() };
+        for (key, entry) in self.entries {
+            if let Some(scheduler) = &self.io_scheduler {
+                scheduler.acquire(IoClass::Scrub, key.len() as u64 + entry.2 as u64);
+            }
+            match read_value(&self.segments, &key, entry) {
+                Ok(_) => {}
+                Err(Error::Corruption(_)) => scrubbed.corrupt.push((key, entry)),
+                Err(err) => return Err(err),
+            }
+            scrubbed.entries += 1;
+            scrubbed.bytes += entry.2 as u64;
+        }
+        Ok(scrubbed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::{segment_path, Options};
+    use crate::storage::vfs::MemFs;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    struct Replica(HashMap<Vec<u8>, Vec<u8>>);
+
+    impl RepairSource for Replica {
+        fn fetch(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.get(key).cloned())
+        }
+    }
+
+    #[test]
+    fn test_scrub() -> Result<()> {
+        let mem = MemFs::new();
+        let options = Options { vfs: Arc::new(mem.clone()), ..Default::default() };
+        let path = PathBuf::from("/db/log");
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        s.set(b"a", vec![0x01; 4])?;
+        s.set(b"b", vec![0x02; 4])?;
+        s.set(b"c", vec![0x03; 4])?;
+        s.rotate()?;
+        assert_eq!(ScrubReport { entries: 3, bytes: 12, ..Default::default() }, s.scrub(None)?);
+
+        // Flip a byte of a's and of b's values.
+        let segment = segment_path(&path, 1, "");
+        let mut data = mem.read(&segment).unwrap_or_default();
+        for value in [[0x01; 4], [0x02; 4]] {
+            let pos = data.windows(4).position(|window| window == value).unwrap_or_default();
+            data[pos] ^= 0xff;
+        }
+        mem.write(&segment, data);
+
+        let report = s.scrub(None)?;
+        assert_eq!((3, vec![b"a".to_vec(), b"b".to_vec()]), (report.entries, report.corrupt));
+        assert!(matches!(s.get(b"a"), Err(Error::Corruption(_))));
+
+        // The replica only has a.
+        let replica = Replica(HashMap::from([(b"a".to_vec(), vec![0x01; 4])]));
+        let report = s.scrub(Some(&replica))?;
+        assert_eq!((vec![b"b".to_vec()], vec![b"a".to_vec()]), (report.corrupt, report.repaired));
+        assert_eq!(Some(vec![0x01; 4]), s.get(b"a")?);
+        assert_eq!(Some(vec![0x03; 4]), s.get(b"c")?);
+
+        // An entry overwritten meanwhile is no longer reported.
+        let job = s.start_scrub()?;
+        s.set(b"b", vec![0x04])?;
+        let report = s.finish_scrub(job.run(), None)?;
+        assert!(report.corrupt.is_empty());
+        Ok(())
+    }
+}
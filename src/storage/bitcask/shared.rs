@@ -0,0 +1,226 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::{read_value, segment_path, sibling_path, BitCask, KeyDir, LockMode, Log, Segments};
+use crate::error::{Error, Result};
+use crate::storage::vfs::{StdFs, Vfs};
+
+// Lets other processes serve gets from a store without rebuilding its keydir
+// or going through the server. The writer publishes snapshots of its keydir
+// to path.keydir (see Options::publish_keydir), replacing the file atomically
+// and numbering each snapshot with a generation. A SharedReader loads the
+// latest snapshot and reads values straight from the segments, without
+// locking them, so it can run alongside the writer.
+//
+// A reader only sees writes published before its last refresh. If the
+// writer has since sealed or merged the segment an entry points into, the
+// read fails the entry's checksum or doesn't find the segment, and the
+// reader refreshes and retries once. Version 1 segments have no checksums,
+// so stale reads from them go undetected.
+//
+// The snapshot file is laid out as the generation and the active segment's
+// ID, as big-endian u64s, followed by one record per key:
+//
+//   file_id: u64 (big-endian)
+//   value_pos: u64 (big-endian)
+//   value_len: u32 (big-endian)
+//   key_len: u32 (big-endian)
+//   key: [u8; key_len]
+impl BitCask {
+    // Flushes buffered writes and publishes the keydir for SharedReaders,
+    // returning the generation of the snapshot.
+    pub fn publish_keydir(&mut self) -> Result<u64> {
+        self.check_writable()?;
+        self.flush()?;
+        self.write_keydir()
+    }
+
+    pub(super) fn write_keydir(&self) -> Result<u64> {
+        let generation = match read_keydir(&self.options.vfs, &keydir_path(&self.path)) {
+            Ok(data) => decode_header(&data).map_or(0, |(generation, _)| generation),
+            Err(Error::Value(_)) => 0,
+            Err(err) => return Err(err),
+        } + 1;
+        let active_id = self.segments.keys().next_back().copied().unwrap_or(0);
+
+        let mut data = Vec::with_capacity(16 + self.keydir.len() * 32);
+        data.extend_from_slice(&generation.to_be_bytes());
+        data.extend_from_slice(&active_id.to_be_bytes());
+        for (key, (file_id, value_pos, value_len)) in &self.keydir {
+            data.extend_from_slice(&file_id.to_be_bytes());
+            data.extend_from_slice(&value_pos.to_be_bytes());
+            data.extend_from_slice(&value_len.to_be_bytes());
+            data.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            data.extend_from_slice(key);
+        }
+
+        let temp_path = sibling_path(&self.path, ".keydir.new");
+        let file = self.options.vfs.open(&temp_path)?;
+        file.set_len(0)?;
+        file.append(&data)?;
+        self.options.vfs.rename(&temp_path, &keydir_path(&self.path))?;
+        Ok(generation)
+    }
+}
+
+// Serves gets from the keydir snapshots published by a store's writer.
+pub struct SharedReader {
+    path: PathBuf,
+    vfs: Arc<dyn Vfs>,
+    generation: u64,
+    active_id: u64,
+    keydir: KeyDir,
+    // Segments opened so far, reset on refresh since merges reuse IDs.
+    segments: Segments,
+}
+
+impl SharedReader {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        Self::new_with_vfs(path, Arc::new(StdFs))
+    }
+
+    pub fn new_with_vfs(path: PathBuf, vfs: Arc<dyn Vfs>) -> Result<Self> {
+        let mut reader = Self {
+            path,
+            vfs,
+            generation: 0,
+            active_id: 0,
+            keydir: KeyDir::new(),
+            segments: Segments::new(),
+        };
+        reader.refresh()?;
+        Ok(reader)
+    }
+
+    // The generation of the snapshot being served.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Loads the latest published snapshot, returning whether it is newer
+    // than the one being served.
+    pub fn refresh(&mut self) -> Result<bool> {
+        let path = keydir_path(&self.path);
+        let data = read_keydir(&self.vfs, &path)?;
+        let corrupt = || Error::Corruption(format!("truncated keydir snapshot {}", path.display()));
+        let (generation, active_id) = decode_header(&data).ok_or_else(corrupt)?;
+        if generation == self.generation {
+            return Ok(false);
+        }
+        let keydir = decode_entries(&data[16..]).ok_or_else(corrupt)?;
+        self.generation = generation;
+        self.active_id = active_id;
+        self.keydir = keydir;
+        self.segments.clear();
+        Ok(true)
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.read(key) {
+            Err(_) if self.refresh()? => self.read(key),
+            result => result,
+        }
+    }
+
+    fn read(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(&(file_id, value_pos, value_len)) = self.keydir.get(key) else {
+            return Ok(None);
+        };
+        if !self.segments.contains_key(&file_id) {
+            let path = match file_id == self.active_id {
+                true => self.path.clone(),
+                false => segment_path(&self.path, file_id, ""),
+            };
+            self.segments.insert(file_id, Log::new(self.vfs.clone(), path, 0, LockMode::Unlocked)?);
+        }
+        read_value(&self.segments, key, (file_id, value_pos, value_len)).map(Some)
+    }
+}
+
+fn keydir_path(path: &std::path::Path) -> PathBuf {
+    sibling_path(path, ".keydir")
+}
+
+// Reads a snapshot file, failing with Error::Value if none was published.
+fn read_keydir(vfs: &Arc<dyn Vfs>, path: &std::path::Path) -> Result<Vec<u8>> {
+    let file = match vfs.open_read(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::Value(format!("no keydir published at {}", path.display())));
+        }
+        result => result?,
+    };
+    let mut data = vec![0; file.size()? as usize];
+    file.read_exact_at(&mut data, 0)?;
+    Ok(data)
+}
+
+// Returns a snapshot's generation and active segment ID.
+fn decode_header(data: &[u8]) -> Option<(u64, u64)> {
+    let (generation, rest) = data.split_first_chunk::<8>()?;
+    let (active_id, _) = rest.split_first_chunk::<8>()?;
+    Some((u64::from_be_bytes(*generation), u64::from_be_bytes(*active_id)))
+}
+
+// Decodes a snapshot's keydir entries, or returns None if it is truncated.
+fn decode_entries(mut data: &[u8]) -> Option<KeyDir> {
+    let mut keydir = KeyDir::new();
+    while !data.is_empty() {
+        let (header, rest) = data.split_first_chunk::<24>()?;
+        let file_id = u64::from_be_bytes(header[..8].try_into().ok()?);
+        let value_pos = u64::from_be_bytes(header[8..16].try_into().ok()?);
+        let value_len = u32::from_be_bytes(header[16..20].try_into().ok()?);
+        let key_len = u32::from_be_bytes(header[20..].try_into().ok()?) as usize;
+        if rest.len() < key_len {
+            return None;
+        }
+        let (key, rest) = rest.split_at(key_len);
+        keydir.insert(key.to_vec(), (file_id, value_pos, value_len));
+        data = rest;
+    }
+    Some(keydir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::Options;
+    use crate::storage::vfs::MemFs;
+    use crate::storage::Engine;
+
+    #[test]
+    fn test_shared_reader() -> Result<()> {
+        let mem = MemFs::new();
+        let vfs: Arc<dyn Vfs> = Arc::new(mem.clone());
+        let path = PathBuf::from("/db/log");
+        assert!(matches!(SharedReader::new_with_vfs(path.clone(), vfs.clone()), Err(Error::Value(_))));
+
+        let options = Options { vfs: vfs.clone(), publish_keydir: true, ..Default::default() };
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        assert_eq!(2, s.publish_keydir()?);
+
+        // Readers only see published writes, once they refresh.
+        let mut r = SharedReader::new_with_vfs(path.clone(), vfs.clone())?;
+        assert_eq!(Some(vec![0x01]), r.get(b"a")?);
+        s.set(b"c", vec![0x03])?;
+        assert_eq!(None, r.get(b"c")?);
+        s.publish_keydir()?;
+        assert_eq!(None, r.get(b"c")?);
+        assert!(r.refresh()?);
+        assert!(!r.refresh()?);
+        assert_eq!(Some(vec![0x03]), r.get(b"c")?);
+
+        // A merge removes the segments a stale snapshot points to, so the
+        // reader refreshes.
+        let mut stale = SharedReader::new_with_vfs(path.clone(), vfs.clone())?;
+        s.delete(b"b")?;
+        s.compact()?;
+        assert_eq!(Some(vec![0x01]), stale.get(b"a")?);
+        assert_eq!(None, stale.get(b"b")?);
+        assert_eq!(Some(vec![0x03]), stale.get(b"c")?);
+        assert!(stale.generation() > r.generation());
+        assert!(mem.read(&path.with_extension("keydir.new")).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::{BitCask, RepairSource, ScrubReport};
+use crate::error::Result;
+use crate::storage::DbManager;
+
+// Compacts a shared store from a background thread. Every interval, it checks
+// whether the store's compaction schedule calls for a compaction (see
+// BitCask::maybe_compact), and if so merges the sealed segments while only
+// holding the store's lock to start and finish the merge, so reads and writes
+// are served meanwhile. Give the store an IoScheduler with a rate for
+// IoClass::Compaction to keep the merge from starving foreground I/O.
+//
+// The store can be wrapped, e.g. in Instrumented, as long as the wrapper gives
+// access to the BitCask through AsMut.
+//
+// The worker stops when dropped, after any merge in progress.
+pub struct CompactionWorker {
+    _worker: Worker,
+}
+
+// Scrubs a shared store from a background thread every interval, checking
+// the live entries' checksums and repairing corrupt ones from the repair
+// source, if any (see BitCask::scrub). The store's lock is only held to start
+// and finish each run. Give the store an IoScheduler with a rate for
+// IoClass::Scrub to cap the scrubber's bandwidth.
+//
+// The scrubber stops when dropped, after any run in progress.
+pub struct Scrubber {
+    // The report of the last completed run.
+    report: Arc<Mutex<Option<ScrubReport>>>,
+    _worker: Worker,
+}
+
+// A thread calling a function every interval until dropped.
+struct Worker {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CompactionWorker {
+    pub fn start<E: AsMut<BitCask> + Send + 'static>(db: Arc<Mutex<E>>, interval: Duration) -> Self {
+        let worker = Worker::spawn(interval, move || {
+            if let Err(err) = compact(&db) {
+                log::error!("Background compaction failed: {}", err);
+            }
+        });
+        Self { _worker: worker }
+    }
+
+    // Like start, but for all databases open in the manager, one at a time,
+    // so a host with many tenants runs a single compaction thread. The
+    // manager's lock is only held to start and finish each merge. A database
+    // closed while being merged can't be reopened until the merge is done,
+    // and the next open completes it.
+    pub fn start_for_manager(manager: Arc<Mutex<DbManager>>, interval: Duration) -> Self {
+        let worker = Worker::spawn(interval, move || {
+            let names = lock(&manager).open_names();
+            for name in names {
+                if let Err(err) = compact_managed(&manager, &name) {
+                    log::error!("Background compaction of database {} failed: {}", name, err);
+                }
+            }
+        });
+        Self { _worker: worker }
+    }
+}
+
+impl Scrubber {
+    pub fn start<E: AsMut<BitCask> + Send + 'static>(
+        db: Arc<Mutex<E>>,
+        interval: Duration,
+        repair: Option<Arc<dyn RepairSource>>,
+    ) -> Self {
+        let report = Arc::new(Mutex::new(None));
+        let worker = Worker::spawn(interval, {
+            let report = report.clone();
+            move || match scrub(&db, repair.as_deref()) {
+                Ok(scrubbed) => *lock(&report) = Some(scrubbed),
+                Err(err) => log::error!("Background scrub failed: {}", err),
+            }
+        });
+        Self { report, _worker: worker }
+    }
+
+    pub fn last_report(&self) -> Option<ScrubReport> {
+        lock(&self.report).clone()
+    }
+}
+
+impl Worker {
+    fn spawn(interval: Duration, tick: impl FnMut() + Send + 'static) -> Self {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = std::thread::spawn({
+            let stopped = stopped.clone();
+            move || run(interval, &stopped, tick)
+        });
+        Self { stopped, thread: Some(thread) }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let (stopped, wakeup) = &*self.stopped;
+        *lock(stopped) = true;
+        wakeup.notify_all();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Background worker panicked");
+            }
+        }
+    }
+}
+
+fn run(interval: Duration, stopped: &(Mutex<bool>, Condvar), mut tick: impl FnMut()) {
+    let (stopped, wakeup) = stopped;
+    loop {
+        let guard = wakeup
+            .wait_timeout_while(lock(stopped), interval, |stopped| !*stopped)
+            .map(|(guard, _)| guard)
+            .unwrap_or_else(|poisoned| poisoned.into_inner().0);
+        if *guard {
+            return;
+        }
+        drop(guard);
+        tick();
+    }
+}
+
+fn compact<E: AsMut<BitCask>>(db: &Mutex<E>) -> Result<()> {
+    let job = {
+        let mut guard = lock(db);
+        let db = guard.as_mut();
+        if !db.compaction_due()? {
+            return Ok(());
+        }
+        db.start_merge()?
+    };
+    if let Some(job) = job {
+        let merged = job.run();
+        lock(db).as_mut().finish_merge(merged)?;
+    }
+    Ok(())
+}
+
+fn scrub<E: AsMut<BitCask>>(db: &Mutex<E>, repair: Option<&dyn RepairSource>) -> Result<ScrubReport> {
+    let job = lock(db).as_mut().start_scrub()?;
+    let scrubbed = job.run();
+    lock(db).as_mut().finish_scrub(scrubbed, repair)
+}
+
+fn compact_managed(manager: &Mutex<DbManager>, name: &str) -> Result<()> {
+    let started = lock(manager).start_compaction(name, |db| {
+        if !db.compaction_due()? {
+            return Ok(None);
+        }
+        db.start_merge()
+    })?;
+    if let Some((generation, job)) = started {
+        let merged = job.run();
+        lock(manager).end_compaction(name, generation, |db| db.finish_merge(merged))?;
+    }
+    Ok(())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::Options;
+    use crate::storage::{Engine, Instrumented};
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    #[test]
+    fn test_compaction_worker() -> Result<()> {
+        let options = Options { vfs: Arc::new(crate::storage::vfs::MemFs::new()), ..Default::default() };
+        let db = BitCask::new_with_options(PathBuf::from("/db/log"), options)?;
+        let db = Arc::new(Mutex::new(Instrumented::new(db)));
+        let worker = CompactionWorker::start(db.clone(), Duration::from_millis(1));
+
+        for i in 0..100u8 {
+            lock(&db).set(b"counter", vec![i])?;
+        }
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while lock(&db).status()?.garbage_disk_size > 0 {
+            assert!(Instant::now() < deadline, "compaction didn't run");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(worker);
+
+        let mut db = lock(&db);
+        assert_eq!(Some(vec![99]), db.get(b"counter")?);
+        assert_eq!(1, db.status()?.keys);
+        let metrics = db.metrics();
+        assert_eq!(100, metrics.set.latency.count() + metrics.set.latency_compacting.count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_scrubber() -> Result<()> {
+        let options = Options { vfs: Arc::new(crate::storage::vfs::MemFs::new()), ..Default::default() };
+        let mut db = BitCask::new_with_options(PathBuf::from("/db/log"), options)?;
+        db.set(b"a", vec![0x01])?;
+        let db = Arc::new(Mutex::new(db));
+        let scrubber = Scrubber::start(db.clone(), Duration::from_millis(1), None);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while scrubber.last_report().is_none() {
+            assert!(Instant::now() < deadline, "scrub didn't run");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(Some(ScrubReport { entries: 1, bytes: 1, ..Default::default() }), scrubber.last_report());
+        Ok(())
+    }
+
+    #[test]
+    fn test_manager_worker() -> Result<()> {
+        let options = Options { vfs: Arc::new(crate::storage::vfs::MemFs::new()), ..Default::default() };
+        let manager = Arc::new(Mutex::new(DbManager::new(PathBuf::from("/dbs"), options)?));
+        for name in ["a", "b"] {
+            let mut m = lock(&manager);
+            let db = m.create(name)?;
+            for i in 0..100u8 {
+                db.set(b"counter", vec![i])?;
+            }
+        }
+        let worker = CompactionWorker::start_for_manager(manager.clone(), Duration::from_millis(1));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        for name in ["a", "b"] {
+            while lock(&manager).open(name)?.status()?.garbage_disk_size > 0 {
+                assert!(Instant::now() < deadline, "compaction didn't run");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        drop(worker);
+
+        let mut m = lock(&manager);
+        for name in ["a", "b"] {
+            assert_eq!(Some(vec![99]), m.open(name)?.get(b"counter")?);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::ops::{Bound, RangeBounds};
+
+use super::{Engine, ScanIterator, Status};
+use crate::error::{Error, Result};
+
+const PREFIX_LEN: usize = 8;
+
+// Stores every key under hash(key) || key, spreading sequential keys (e.g.
+// timestamps or auto-increment IDs) uniformly across the keyspace so they
+// don't all land on the same partition or segment. Point lookups cost the
+// same as before, but the inner engine's key order no longer matches the user
+// key order: scans read the whole keyspace and sort the matching entries in
+// memory, so they should be limited to small stores or offline jobs.
+pub struct Hashed<E: Engine> {
+    inner: E,
+}
+
+impl<E: Engine> Hashed<E> {
+    pub fn new(inner: E) -> Self {
+        Self { inner }
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    fn sorted_scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> HashedScan {
+        let items = || -> Result<Vec<_>> {
+            let mut items = Vec::new();
+            for item in self.inner.scan_dyn((Bound::Unbounded, Bound::Unbounded)) {
+                let (key, value) = item?;
+                let key = decode(key)?;
+                if range.contains(&key) {
+                    items.push((key, value));
+                }
+            }
+            items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            Ok(items)
+        }();
+        let items = match items {
+            Ok(items) => items.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+        HashedScan { items: items.into_iter() }
+    }
+}
+
+// 64-bit FNV-1a. The hash is part of the on-disk key, so it must stay stable
+// across Rust versions, which rules out std's DefaultHasher.
+fn hash(key: &[u8]) -> [u8; PREFIX_LEN] {
+    let hash = key.iter().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+    hash.to_be_bytes()
+}
+
+fn encode(key: &[u8]) -> Vec<u8> {
+    [&hash(key)[..], key].concat()
+}
+
+fn decode(mut key: Vec<u8>) -> Result<Vec<u8>> {
+    if key.len() < PREFIX_LEN {
+        return Err(Error::Internal(format!("hashed key {:?} is missing its prefix", key)));
+    }
+    key.drain(..PREFIX_LEN);
+    Ok(key)
+}
+
+impl<E: Engine> std::fmt::Display for Hashed<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "hashed {}", self.inner)
+    }
+}
+
+impl<E: Engine> Engine for Hashed<E> {
+    type ScanIterator<'a> = HashedScan where E: 'a;
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.inner.set(&encode(key), value)
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(&encode(key))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.delete(&encode(key))
+    }
+
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        self.inner.write_batch(batch.into_iter().map(|(key, value)| (encode(&key), value)).collect())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_>
+    where
+        Self: Sized,
+    {
+        self.sorted_scan(range)
+    }
+
+    fn scan_dyn(&mut self, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Box<dyn ScanIterator + '_> {
+        Box::new(self.sorted_scan(range))
+    }
+
+    fn sample_keys(&mut self, n: usize) -> Result<Vec<Vec<u8>>> {
+        self.inner.sample_keys(n)?.into_iter().map(decode).collect()
+    }
+
+    fn warm_up(&mut self) -> Result<()> {
+        self.inner.warm_up()
+    }
+
+    fn is_compacting(&self) -> bool {
+        self.inner.is_compacting()
+    }
+
+    fn status(&self) -> Result<Status> {
+        self.inner.status()
+    }
+}
+
+pub struct HashedScan {
+    items: std::vec::IntoIter<Result<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl Iterator for HashedScan {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.items.next()
+    }
+}
+
+impl DoubleEndedIterator for HashedScan {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.items.next_back()
+    }
+}
+
+impl ScanIterator for HashedScan {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::BitCask;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_hashed() -> Result<()> {
+        let dir = TempDir::new("hashed").expect("Failed to create temporary directory");
+        let mut s = Hashed::new(BitCask::new(dir.path().join("log"))?);
+        for i in 0..10u8 {
+            s.set(&[i], vec![i])?;
+        }
+        s.delete(&[3])?;
+        assert_eq!(Some(vec![5]), s.get(&[5])?);
+        assert_eq!(None, s.get(&[3])?);
+
+        assert_eq!(
+            vec![(vec![2], vec![2]), (vec![4], vec![4]), (vec![5], vec![5])],
+            s.scan(vec![2]..vec![6]).collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(Some(vec![9]), s.scan(..).next_back().transpose()?.map(|(k, _)| k));
+        assert_eq!(9, s.sample_keys(100)?.len());
+
+        // The inner engine sees the keys out of order, behind their hash.
+        let mut inner = s.into_inner();
+        let keys: Vec<_> = inner.scan(..).map(|item| item.map(|(k, _)| k)).collect::<Result<_>>()?;
+        assert_eq!(9, keys.len());
+        assert!(keys.iter().all(|key| key.len() == PREFIX_LEN + 1));
+        let user_keys: Vec<_> = keys.iter().map(|key| key[PREFIX_LEN]).collect();
+        assert_ne!((0..10u8).filter(|i| *i != 3).collect::<Vec<_>>(), user_keys);
+        Ok(())
+    }
+}
@@ -0,0 +1,381 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Engine, ScanIterator, Status};
+use crate::error::Result;
+use crate::metrics::{Counter, Histogram};
+
+#[derive(Debug, Default)]
+pub struct OpMetrics {
+    // Latency of operations started while the engine was compacting, and of
+    // all others.
+    pub latency: Histogram,
+    pub latency_compacting: Histogram,
+    pub bytes: Counter,
+    pub errors: Counter,
+}
+
+impl OpMetrics {
+    fn latency(&self, compacting: bool) -> &Histogram {
+        if compacting {
+            &self.latency_compacting
+        } else {
+            &self.latency
+        }
+    }
+
+    fn record<T>(&self, start: Instant, compacting: bool, bytes: usize, result: &Result<T>) {
+        self.latency(compacting).observe(start.elapsed());
+        match result {
+            Ok(_) => self.bytes.add(bytes as u64),
+            Err(_) => self.errors.inc(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EngineMetrics {
+    pub set: OpMetrics,
+    pub get: OpMetrics,
+    pub delete: OpMetrics,
+    pub write_batch: OpMetrics,
+    // Scan latency is the time spent inside the iterator over its lifetime,
+    // recorded when the iterator is dropped.
+    pub scan: OpMetrics,
+}
+
+impl EngineMetrics {
+    // Renders the metrics in the Prometheus text format, e.g. for a /metrics
+    // endpoint. Latency is labelled with whether a compaction was running,
+    // so compaction's impact on foreground operations is the difference
+    // between the compacting="true" and "false" series.
+    pub fn to_prometheus(&self) -> String {
+        let ops = [
+            ("set", &self.set),
+            ("get", &self.get),
+            ("delete", &self.delete),
+            ("write_batch", &self.write_batch),
+            ("scan", &self.scan),
+        ];
+        let mut out = String::new();
+        out.push_str("# HELP lndb_op_duration_seconds Latency of engine operations.\n");
+        out.push_str("# TYPE lndb_op_duration_seconds histogram\n");
+        for (op, metrics) in ops {
+            for (compacting, latency) in [(false, &metrics.latency), (true, &metrics.latency_compacting)] {
+                let labels = format!("op=\"{}\",compacting=\"{}\"", op, compacting);
+                latency.write_prometheus(&mut out, "lndb_op_duration_seconds", &labels);
+            }
+        }
+        out.push_str("# HELP lndb_op_bytes_total Bytes of keys and values read or written.\n");
+        out.push_str("# TYPE lndb_op_bytes_total counter\n");
+        for (op, metrics) in ops {
+            metrics.bytes.write_prometheus(&mut out, "lndb_op_bytes_total", &format!("op=\"{}\"", op));
+        }
+        out.push_str("# HELP lndb_op_errors_total Failed engine operations.\n");
+        out.push_str("# TYPE lndb_op_errors_total counter\n");
+        for (op, metrics) in ops {
+            metrics.errors.write_prometheus(&mut out, "lndb_op_errors_total", &format!("op=\"{}\"", op));
+        }
+        out
+    }
+}
+
+// Wraps any engine and records per-operation latency, byte and error counts.
+pub struct Instrumented<E: Engine> {
+    inner: E,
+    metrics: Arc<EngineMetrics>,
+}
+
+impl<E: Engine> Instrumented<E> {
+    pub fn new(inner: E) -> Self {
+        Self::with_metrics(inner, Arc::default())
+    }
+
+    pub fn with_metrics(inner: E, metrics: Arc<EngineMetrics>) -> Self {
+        Self { inner, metrics }
+    }
+
+    pub fn metrics(&self) -> Arc<EngineMetrics> {
+        self.metrics.clone()
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+// Gives access to the wrapped store, e.g. so a CompactionWorker can compact
+// an Instrumented<BitCask>.
+impl<T, E: Engine + AsMut<T>> AsMut<T> for Instrumented<E> {
+    fn as_mut(&mut self) -> &mut T {
+        self.inner.as_mut()
+    }
+}
+
+impl<E: Engine> std::fmt::Display for Instrumented<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl<E: Engine> Engine for Instrumented<E> {
+    type ScanIterator<'a> = InstrumentedScan<'a, E::ScanIterator<'a>> where E: 'a;
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let compacting = self.inner.is_compacting();
+        let start = Instant::now();
+        let bytes = key.len() + value.len();
+        let result = self.inner.set(key, value);
+        self.metrics.set.record(start, compacting, bytes, &result);
+        result
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let compacting = self.inner.is_compacting();
+        let start = Instant::now();
+        let result = self.inner.get(key);
+        let bytes = key.len() + result.as_ref().map_or(0, |v| v.as_ref().map_or(0, |v| v.len()));
+        self.metrics.get.record(start, compacting, bytes, &result);
+        result
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        let compacting = self.inner.is_compacting();
+        let start = Instant::now();
+        let result = self.inner.delete(key);
+        self.metrics.delete.record(start, compacting, key.len(), &result);
+        result
+    }
+
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        let compacting = self.inner.is_compacting();
+        let start = Instant::now();
+        let bytes = batch.iter().map(|(key, value)| key.len() + value.as_ref().map_or(0, |v| v.len())).sum();
+        let result = self.inner.write_batch(batch);
+        self.metrics.write_batch.record(start, compacting, bytes, &result);
+        result
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_>
+    where
+        Self: Sized,
+    {
+        let compacting = self.inner.is_compacting();
+        let start = Instant::now();
+        let inner = self.inner.scan(range);
+        InstrumentedScan::new(inner, self.metrics.scan.latency(compacting), &self.metrics.scan, start.elapsed())
+    }
+
+    fn scan_dyn(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>)
+    ) -> Box<dyn ScanIterator + '_> {
+        let compacting = self.inner.is_compacting();
+        let start = Instant::now();
+        let inner = self.inner.scan_dyn(range);
+        let latency = self.metrics.scan.latency(compacting);
+        Box::new(InstrumentedScan::new(inner, latency, &self.metrics.scan, start.elapsed()))
+    }
+
+    fn hint_sequential(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.inner.hint_sequential(range)
+    }
+
+    fn sample_keys(&mut self, n: usize) -> Result<Vec<Vec<u8>>> {
+        self.inner.sample_keys(n)
+    }
+
+    fn warm_up(&mut self) -> Result<()> {
+        self.inner.warm_up()
+    }
+
+    fn is_compacting(&self) -> bool {
+        self.inner.is_compacting()
+    }
+
+    fn status(&self) -> Result<Status> {
+        self.inner.status()
+    }
+}
+
+pub struct InstrumentedScan<'a, I> {
+    inner: I,
+    // The latency histogram chosen when the scan started.
+    latency: &'a Histogram,
+    metrics: &'a OpMetrics,
+    elapsed: Duration,
+    bytes: usize,
+}
+
+impl<'a, I> InstrumentedScan<'a, I>
+where
+    I: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+{
+    fn new(inner: I, latency: &'a Histogram, metrics: &'a OpMetrics, elapsed: Duration) -> Self {
+        Self { inner, latency, metrics, elapsed, bytes: 0 }
+    }
+
+    fn record(&mut self, start: Instant, item: Option<Result<(Vec<u8>, Vec<u8>)>>) -> Option<I::Item> {
+        self.elapsed += start.elapsed();
+        match &item {
+            Some(Ok((key, value))) => self.bytes += key.len() + value.len(),
+            Some(Err(_)) => self.metrics.errors.inc(),
+            None => {}
+        }
+        item
+    }
+}
+
+impl<I> Iterator for InstrumentedScan<'_, I>
+where
+    I: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+{
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let item = self.inner.next();
+        self.record(start, item)
+    }
+}
+
+impl<I> DoubleEndedIterator for InstrumentedScan<'_, I>
+where
+    I: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let item = self.inner.next_back();
+        self.record(start, item)
+    }
+}
+
+impl<I> ScanIterator for InstrumentedScan<'_, I>
+where
+    I: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+{}
+
+impl<I> Drop for InstrumentedScan<'_, I> {
+    fn drop(&mut self) {
+        self.latency.observe(self.elapsed);
+        self.metrics.bytes.add(self.bytes as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::BitCask;
+    use crate::storage::memory::Memory;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_instrumented() -> Result<()> {
+        let dir = TempDir::new("instrumented").expect("Failed to create temporary directory");
+        let mut s = Instrumented::new(BitCask::new(dir.path().join("log"))?);
+        let metrics = s.metrics();
+
+        s.set(b"a", vec![1, 2, 3])?;
+        s.set(b"b", vec![4])?;
+        assert_eq!(Some(vec![1, 2, 3]), s.get(b"a")?);
+        assert_eq!(None, s.get(b"x")?);
+        s.delete(b"b")?;
+        assert_eq!(1, s.scan(..).collect::<Result<Vec<_>>>()?.len());
+        assert_eq!(0, s.scan_dyn((std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)).rev().skip(1).count());
+
+        assert_eq!(2, metrics.set.latency.count());
+        assert_eq!(6, metrics.set.bytes.get());
+        assert_eq!(2, metrics.get.latency.count());
+        assert_eq!(5, metrics.get.bytes.get());
+        assert_eq!(1, metrics.delete.latency.count());
+        assert_eq!(2, metrics.scan.latency.count());
+        assert_eq!(8, metrics.scan.bytes.get());
+        assert_eq!(0, metrics.set.errors.get() + metrics.get.errors.get());
+        Ok(())
+    }
+
+    // A memory engine that claims to be compacting while the flag is set.
+    struct Compacting {
+        inner: Memory,
+        compacting: bool,
+    }
+
+    impl std::fmt::Display for Compacting {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "compacting")
+        }
+    }
+
+    impl Engine for Compacting {
+        type ScanIterator<'a> = <Memory as Engine>::ScanIterator<'a>;
+
+        fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+            self.inner.set(key, value)
+        }
+
+        fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+
+        fn delete(&mut self, key: &[u8]) -> Result<()> {
+            self.inner.delete(key)
+        }
+
+        fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+            self.inner.scan(range)
+        }
+
+        fn scan_dyn(
+            &mut self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>)
+        ) -> Box<dyn ScanIterator + '_> {
+            self.inner.scan_dyn(range)
+        }
+
+        fn is_compacting(&self) -> bool {
+            self.compacting
+        }
+
+        fn status(&self) -> Result<Status> {
+            self.inner.status()
+        }
+    }
+
+    impl AsMut<Compacting> for Compacting {
+        fn as_mut(&mut self) -> &mut Compacting {
+            self
+        }
+    }
+
+    #[test]
+    fn test_compaction_latency() -> Result<()> {
+        let mut s = Instrumented::new(Compacting { inner: Memory::new(), compacting: false });
+        let metrics = s.metrics();
+        s.set(b"a", vec![1])?;
+        s.as_mut().compacting = true;
+        s.set(b"b", vec![2])?;
+        s.get(b"a")?;
+        assert_eq!(2, s.scan(..).count());
+        s.as_mut().compacting = false;
+        s.get(b"b")?;
+
+        assert_eq!((1, 1), (metrics.set.latency.count(), metrics.set.latency_compacting.count()));
+        assert_eq!((1, 1), (metrics.get.latency.count(), metrics.get.latency_compacting.count()));
+        assert_eq!((0, 1), (metrics.scan.latency.count(), metrics.scan.latency_compacting.count()));
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE lndb_op_duration_seconds histogram\n"));
+        assert!(text.contains("lndb_op_duration_seconds_count{op=\"set\",compacting=\"true\"} 1\n"));
+        assert!(text.contains("lndb_op_duration_seconds_bucket{op=\"get\",compacting=\"false\",le=\"+Inf\"} 1\n"));
+        assert!(text.contains("lndb_op_bytes_total{op=\"set\"} 4\n"));
+        assert!(text.contains("lndb_op_errors_total{op=\"scan\"} 0\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::bitcask::{BitCask, CompactionSchedule, Options, SyncPolicy};
+use super::IoScheduler;
+use crate::error::{Error, Result};
+
+// Hosts many named databases under one root directory, e.g. one per tenant.
+// Each database is a Bitcask log at <root>/<name>/log, and all of them share
+// the manager's options, and with them its clock, filesystem and IoScheduler.
+// One CompactionWorker::start_for_manager thread compacts all of them.
+//
+// Databases are opened on first access. With a max_open limit, opening one
+// more database closes the least recently used one, so deployments with many
+// mostly idle tenants don't hold a file and keydir for each of them.
+pub struct DbManager {
+    root: PathBuf,
+    options: Options,
+    // Maximum number of open databases, 0 for no limit.
+    max_open: usize,
+    open: HashMap<String, Handle>,
+    // Incremented on every access, to order handles by recency.
+    accesses: u64,
+    // Incremented on every open, to tell a reopened database from the
+    // handle a merge was started on.
+    opens: u64,
+    // Databases with a merge started by the compaction worker in progress.
+    compacting: HashSet<String>,
+}
+
+// The options a single database can override, each left as the manager's if
+// None. The rest, like the filesystem and clock, are always the manager's.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DbOptions {
+    pub tombstone_retention: Option<Duration>,
+    pub sync_policy: Option<SyncPolicy>,
+    pub compaction_schedule: Option<CompactionSchedule>,
+    pub max_segment_size: Option<u64>,
+}
+
+impl DbOptions {
+    fn apply(&self, mut options: Options) -> Options {
+        if let Some(retention) = self.tombstone_retention {
+            options.tombstone_retention = retention;
+        }
+        if let Some(sync_policy) = self.sync_policy {
+            options.sync_policy = sync_policy;
+        }
+        if let Some(schedule) = &self.compaction_schedule {
+            options.compaction_schedule = schedule.clone();
+        }
+        if let Some(max_segment_size) = self.max_segment_size {
+            options.max_segment_size = max_segment_size;
+        }
+        options
+    }
+}
+
+struct Handle {
+    db: BitCask,
+    generation: u64,
+    last_access: u64,
+    last_used: Duration,
+}
+
+impl DbManager {
+    pub fn new(root: PathBuf, options: Options) -> Result<Self> {
+        Self::new_with_max_open(root, options, 0)
+    }
+
+    // Without an IoScheduler in the options, the manager creates one without
+    // any rates, which can be set through io_scheduler.
+    pub fn new_with_max_open(root: PathBuf, mut options: Options, max_open: usize) -> Result<Self> {
+        options.vfs.create_dir_all(&root)?;
+        if options.io_scheduler.is_none() {
+            options.io_scheduler = Some(Arc::new(IoScheduler::new(options.clock.clone())));
+        }
+        Ok(Self {
+            root,
+            options,
+            max_open,
+            open: HashMap::new(),
+            accesses: 0,
+            opens: 0,
+            compacting: HashSet::new(),
+        })
+    }
+
+    // The scheduler throttling the I/O of all databases together.
+    pub fn io_scheduler(&self) -> Option<&Arc<IoScheduler>> {
+        self.options.io_scheduler.as_ref()
+    }
+
+    // Creates a new database, failing if it already exists.
+    pub fn create(&mut self, name: &str) -> Result<&mut BitCask> {
+        if self.exists(name)? {
+            return Err(Error::Value(format!("database {} already exists", name)));
+        }
+        self.open_path(name)
+    }
+
+    // Opens an existing database, or returns the already open handle.
+    pub fn open(&mut self, name: &str) -> Result<&mut BitCask> {
+        if !self.open.contains_key(name) && !self.exists(name)? {
+            return Err(Error::Value(format!("database {} does not exist", name)));
+        }
+        self.open_path(name)
+    }
+
+    // Closes the database if it is open. It is reopened by the next open.
+    pub fn close(&mut self, name: &str) {
+        self.open.remove(name);
+    }
+
+    // Closes the database and deletes its files.
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        if !self.exists(name)? {
+            return Err(Error::Value(format!("database {} does not exist", name)));
+        }
+        self.open.remove(name);
+        Ok(self.options.vfs.remove_dir_all(&self.root.join(name))?)
+    }
+
+    // Overrides the options of an existing database, e.g. a different
+    // tombstone retention or compaction schedule. They are kept in
+    // <root>/<name>/options, so they outlive the manager. If the database is
+    // open it is closed, so the options take effect when it is next opened.
+    pub fn set_options(&mut self, name: &str, options: DbOptions) -> Result<()> {
+        if !self.exists(name)? {
+            return Err(Error::Value(format!("database {} does not exist", name)));
+        }
+        self.open.remove(name);
+        let data = bincode::serialize(&options).map_err(|err| Error::Internal(err.to_string()))?;
+        let path = self.root.join(name).join("options");
+        let new_path = path.with_extension("new");
+        let file = self.options.vfs.open(&new_path)?;
+        file.set_len(0)?;
+        file.append(&data)?;
+        file.sync()?;
+        Ok(self.options.vfs.rename(&new_path, &path)?)
+    }
+
+    // Returns the options a database overrides.
+    pub fn get_options(&self, name: &str) -> Result<DbOptions> {
+        validate(name)?;
+        let file = match self.options.vfs.open_read(&self.root.join(name).join("options")) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(DbOptions::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut data = vec![0; file.size()? as usize];
+        file.read_exact_at(&mut data, 0)?;
+        bincode::deserialize(&data)
+            .map_err(|err| Error::Corruption(format!("invalid options of database {}: {}", name, err)))
+    }
+
+    // Reverts a database to the manager's options.
+    pub fn reset_options(&mut self, name: &str) -> Result<()> {
+        if !self.exists(name)? {
+            return Err(Error::Value(format!("database {} does not exist", name)));
+        }
+        self.open.remove(name);
+        match self.options.vfs.remove(&self.root.join(name).join("options")) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_open(&self, name: &str) -> bool {
+        self.open.contains_key(name)
+    }
+
+    // Closes databases that haven't been accessed for at least max_idle.
+    pub fn close_idle(&mut self, max_idle: Duration) {
+        let now = self.options.clock.now();
+        self.open.retain(|_, handle| now.saturating_sub(handle.last_used) < max_idle);
+    }
+
+    // Returns the names of all databases, sorted.
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut names: Vec<_> = self.options.vfs.read_dir(&self.root)?
+            .into_iter()
+            .filter_map(|path| Some(path.file_name()?.to_str()?.to_string()))
+            .filter(|name| validate(name).is_ok())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        validate(name)?;
+        let path = self.root.join(name);
+        Ok(self.options.vfs.read_dir(&self.root)?.contains(&path))
+    }
+
+    fn open_path(&mut self, name: &str) -> Result<&mut BitCask> {
+        validate(name)?;
+        if !self.open.contains_key(name) {
+            // The worker's merge still writes to the files of a database
+            // closed since it started.
+            if self.compacting.contains(name) {
+                return Err(Error::InUse(format!("database {} is being compacted", name)));
+            }
+            let path = self.root.join(name).join("log");
+            let options = self.get_options(name)?.apply(self.options.clone());
+            let db = BitCask::new_with_options(path, options)?;
+            if self.max_open > 0 && self.open.len() >= self.max_open {
+                self.evict();
+            }
+            self.opens += 1;
+            let handle = Handle { db, generation: self.opens, last_access: 0, last_used: Duration::ZERO };
+            self.open.insert(name.to_string(), handle);
+        }
+        self.accesses += 1;
+        let handle = self.open
+            .get_mut(name)
+            .ok_or_else(|| Error::Internal(format!("database {} not open", name)))?;
+        handle.last_access = self.accesses;
+        handle.last_used = self.options.clock.now();
+        Ok(&mut handle.db)
+    }
+
+    // The open databases, for the compaction worker.
+    pub(super) fn open_names(&self) -> Vec<String> {
+        self.open.keys().filter(|name| !self.compacting.contains(*name)).cloned().collect()
+    }
+
+    // Runs start on the database if it is open, for the compaction worker.
+    // If start begins a merge, the database is marked as being compacted
+    // until end_compaction, and the handle's generation is returned with it.
+    pub(super) fn start_compaction<T>(
+        &mut self,
+        name: &str,
+        start: impl FnOnce(&mut BitCask) -> Result<Option<T>>,
+    ) -> Result<Option<(u64, T)>> {
+        let Some(handle) = self.open.get_mut(name).filter(|_| !self.compacting.contains(name)) else {
+            return Ok(None);
+        };
+        let Some(job) = start(&mut handle.db)? else {
+            return Ok(None);
+        };
+        self.compacting.insert(name.to_string());
+        Ok(Some((handle.generation, job)))
+    }
+
+    // Runs finish on the database if the handle start_compaction returned is
+    // still open. Otherwise the merge is left for the next open to complete.
+    pub(super) fn end_compaction(
+        &mut self,
+        name: &str,
+        generation: u64,
+        finish: impl FnOnce(&mut BitCask) -> Result<()>,
+    ) -> Result<()> {
+        self.compacting.remove(name);
+        match self.open.get_mut(name).filter(|handle| handle.generation == generation) {
+            Some(handle) => finish(&mut handle.db),
+            None => Ok(()),
+        }
+    }
+
+    fn evict(&mut self) {
+        let lru = self.open.iter().min_by_key(|(_, handle)| handle.last_access).map(|(name, _)| name.clone());
+        if let Some(name) = lru {
+            log::info!("Closing database {} to stay within {} open databases", name, self.max_open);
+            self.open.remove(&name);
+        }
+    }
+}
+
+// Names become directory names, so they are restricted to a safe character set.
+fn validate(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+    if !valid {
+        return Err(Error::Value(format!("invalid database name {:?}", name)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Engine;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_manager() -> Result<()> {
+        let dir = TempDir::new("manager").expect("Failed to create temporary directory");
+        let mut m = DbManager::new(dir.path().join("dbs"), Options::default())?;
+        assert!(m.list()?.is_empty());
+
+        m.create("tenant-b")?.set(b"a", vec![0x01])?;
+        m.create("tenant-a")?.set(b"a", vec![0x02])?;
+        assert!(matches!(m.create("tenant-a"), Err(Error::Value(_))));
+        assert!(matches!(m.open("tenant-c"), Err(Error::Value(_))));
+        assert!(matches!(m.create("../escape"), Err(Error::Value(_))));
+        assert_eq!(vec!["tenant-a", "tenant-b"], m.list()?);
+
+        m.close("tenant-b");
+        assert_eq!(Some(vec![0x01]), m.open("tenant-b")?.get(b"a")?);
+        assert_eq!(Some(vec![0x02]), m.open("tenant-a")?.get(b"a")?);
+
+        m.remove("tenant-a")?;
+        assert_eq!(vec!["tenant-b"], m.list()?);
+        assert_eq!(None, m.create("tenant-a")?.get(b"a")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_open() -> Result<()> {
+        let dir = TempDir::new("manager").expect("Failed to create temporary directory");
+        let clock = crate::clock::MockClock::new(Duration::from_secs(1000));
+        let options = Options { clock: std::sync::Arc::new(clock.clone()), ..Default::default() };
+        let mut m = DbManager::new_with_max_open(dir.path().to_path_buf(), options, 2)?;
+
+        m.create("a")?.set(b"k", vec![0x01])?;
+        m.create("b")?;
+        m.open("a")?;
+        m.create("c")?;
+        assert_eq!((true, false, true), (m.is_open("a"), m.is_open("b"), m.is_open("c")));
+
+        clock.advance(Duration::from_secs(60));
+        m.open("c")?;
+        m.close_idle(Duration::from_secs(30));
+        assert_eq!((false, false, true), (m.is_open("a"), m.is_open("b"), m.is_open("c")));
+        assert_eq!(Some(vec![0x01]), m.open("a")?.get(b"k")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_closed_while_compacting() -> Result<()> {
+        let dir = TempDir::new("manager").expect("Failed to create temporary directory");
+        let mut m = DbManager::new(dir.path().to_path_buf(), Options::default())?;
+        m.create("a")?;
+        assert!(m.io_scheduler().is_some());
+
+        let Some((generation, ())) = m.start_compaction("a", |_| Ok(Some(())))? else {
+            panic!("compaction not started");
+        };
+        assert!(m.open_names().is_empty());
+        m.close("a");
+        assert!(matches!(m.open("a"), Err(Error::InUse(_))));
+        m.end_compaction("a", generation, |_| panic!("finished on a closed database"))?;
+        m.open("a")?;
+        assert_eq!(vec!["a".to_string()], m.open_names());
+        Ok(())
+    }
+
+    #[test]
+    fn test_options_override() -> Result<()> {
+        let dir = TempDir::new("manager").expect("Failed to create temporary directory");
+        let mut m = DbManager::new(dir.path().to_path_buf(), Options::default())?;
+        let retained = DbOptions { tombstone_retention: Some(Duration::from_secs(3600)), ..Default::default() };
+        assert!(matches!(m.set_options("retained", retained.clone()), Err(Error::Value(_))));
+        m.create("retained")?;
+        m.create("default")?;
+        m.set_options("retained", retained.clone())?;
+        assert!(!m.is_open("retained"));
+
+        for name in ["retained", "default"] {
+            let db = m.open(name)?;
+            db.set(b"a", vec![0x01])?;
+            db.delete(b"a")?;
+            db.compact()?;
+        }
+        // Both segments start with an 8-byte file header.
+        assert_eq!(16 + 13, m.open("retained")?.status()?.total_disk_size);
+        assert_eq!(16, m.open("default")?.status()?.total_disk_size);
+
+        // The overrides are kept with the database.
+        drop(m);
+        let mut m = DbManager::new(dir.path().to_path_buf(), Options::default())?;
+        assert_eq!(retained, m.get_options("retained")?);
+        assert_eq!(DbOptions::default(), m.get_options("default")?);
+        assert_eq!(vec!["default", "retained"], m.list()?);
+
+        m.open("retained")?;
+        m.reset_options("retained")?;
+        assert!(!m.is_open("retained"));
+        assert_eq!(DbOptions::default(), m.get_options("retained")?);
+        m.open("retained")?.compact()?;
+        assert_eq!(16, m.open("retained")?.status()?.total_disk_size);
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use super::{Engine, Status};
+use crate::error::Result;
+
+// An in-memory engine backed by a BTreeMap, for tests of higher layers and for
+// ephemeral databases. Nothing is persisted.
+#[derive(Debug, Default)]
+pub struct Memory {
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+    logical_bytes_written: u64,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl std::fmt::Display for Memory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "memory")
+    }
+}
+
+impl Engine for Memory {
+    type ScanIterator<'a> = ScanIterator<'a>;
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.logical_bytes_written += (key.len() + value.len()) as u64;
+        self.data.insert(key.to_vec(), value);
+        Ok(())
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.logical_bytes_written += key.len() as u64;
+        self.data.remove(key);
+        Ok(())
+    }
+
+    fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+        ScanIterator { inner: self.data.range(range) }
+    }
+
+    fn scan_dyn(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>)
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(self.scan(range))
+    }
+
+    fn sample_keys(&mut self, n: usize) -> Result<Vec<Vec<u8>>> {
+        let sample = super::reservoir_sample(self.data.keys().map(Ok), n)?;
+        Ok(sample.into_iter().cloned().collect())
+    }
+
+    fn status(&self) -> Result<Status> {
+        Ok(Status {
+            name: "memory".to_string(),
+            keys: self.data.len() as u64,
+            size: self.data.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum(),
+            total_disk_size: 0,
+            live_disk_size: 0,
+            garbage_disk_size: 0,
+            logical_bytes_written: self.logical_bytes_written,
+            physical_bytes_written: 0,
+            forecast: None,
+        })
+    }
+}
+
+pub struct ScanIterator<'a> {
+    inner: std::collections::btree_map::Range<'a, Vec<u8>, Vec<u8>>,
+}
+
+impl Iterator for ScanIterator<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, value)| Ok((key.clone(), value.clone())))
+    }
+}
+
+impl DoubleEndedIterator for ScanIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(key, value)| Ok((key.clone(), value.clone())))
+    }
+}
+
+impl super::ScanIterator for ScanIterator<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory() -> Result<()> {
+        let mut s = Memory::new();
+        s.set(b"b", vec![0x02])?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"c", vec![0x03, 0x03])?;
+        s.set(b"a", vec![0x04])?;
+        s.delete(b"b")?;
+        s.delete(b"x")?;
+
+        assert_eq!(Some(vec![0x04]), s.get(b"a")?);
+        assert_eq!(None, s.get(b"b")?);
+        assert_eq!(
+            vec![(b"c".to_vec(), vec![0x03, 0x03]), (b"a".to_vec(), vec![0x04])],
+            s.scan(..).rev().collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            vec![(b"c".to_vec(), vec![0x03, 0x03])],
+            s.scan_dyn((std::ops::Bound::Excluded(b"a".to_vec()), std::ops::Bound::Unbounded))
+                .collect::<Result<Vec<_>>>()?
+        );
+
+        let status = s.status()?;
+        assert_eq!(("memory", 2, 5, 0), (status.name.as_str(), status.keys, status.size, status.total_disk_size));
+        assert_eq!(2, s.sample_keys(5)?.len());
+        Ok(())
+    }
+}
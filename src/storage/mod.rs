@@ -1,13 +1,63 @@
-#![feature(seek_seek_relative)]
+pub mod bitcask;
+mod hashed;
+mod instrumented;
+mod manager;
+pub mod memory;
+mod retrying;
+mod scan;
+mod scheduler;
+pub mod usage;
+pub mod vfs;
+mod watchdog;
 
+pub use hashed::{Hashed, HashedScan};
+pub use instrumented::{EngineMetrics, Instrumented, InstrumentedScan, OpMetrics};
+pub use manager::{DbManager, DbOptions};
+pub use retrying::{RetryPolicy, Retrying};
+pub use scan::{KeyOnly, Peeking, TakeWhilePrefix};
+pub use watchdog::{PendingOp, Watchdog, WatchdogThread};
+pub use scheduler::{IoClass, IoScheduler};
+
+use serde::{Deserialize, Serialize};
 
-mod bitcask;
 use crate::error::Result;
 
 
-pub trait ScanIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {}
+pub trait ScanIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {
+    // Yields only the keys.
+    fn key_only(self) -> KeyOnly<Self>
+    where
+        Self: Sized,
+    {
+        KeyOnly::new(self)
+    }
+
+    // Stops at the first key that doesn't start with prefix. Iterating from
+    // the back skips the keys after the prefix's range instead, so the scan
+    // should start or end near the prefix.
+    fn take_while_prefix(self, prefix: Vec<u8>) -> TakeWhilePrefix<Self>
+    where
+        Self: Sized,
+    {
+        TakeWhilePrefix::new(self, prefix)
+    }
+
+    // Allows looking at the next item or key without consuming it, e.g. to
+    // merge scans. Named so as not to clash with Iterator::peekable.
+    fn peeking(self) -> Peeking<Self>
+    where
+        Self: Sized,
+    {
+        Peeking::new(self)
+    }
+}
 // impl<I: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>>> ScanIterator for I {}
 
+impl<I: ScanIterator + ?Sized> ScanIterator for Box<I> {}
+
+// A scan wrapped by Iterator::peekable is still a ScanIterator too.
+impl<I: ScanIterator> ScanIterator for std::iter::Peekable<I> {}
+
 
 pub trait Engine: std::fmt::Display + Send + Sync {
     type ScanIterator<'a>: ScanIterator + 'a
@@ -20,6 +70,25 @@ pub trait Engine: std::fmt::Display + Send + Sync {
 
     fn delete(&mut self, key: &[u8]) -> Result<()>;
 
+    // Applies the sets (Some) and deletes (None) in order, atomically. The
+    // default applies them one at a time, which is only atomic for engines
+    // whose writes can't fail; others must override it.
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        for (key, value) in batch {
+            match value {
+                Some(value) => self.set(&key, value)?,
+                None => self.delete(&key)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Makes the writes so far durable, whatever the engine's sync policy.
+    // Engines that don't persist anything have nothing to do.
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_>
     where 
         Self: Sized;
@@ -29,10 +98,86 @@ pub trait Engine: std::fmt::Display + Send + Sync {
         range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>)
     ) -> Box<dyn ScanIterator + '_>;
 
+    // Scans the keys starting with prefix.
+    fn scan_prefix(&mut self, prefix: &[u8]) -> Self::ScanIterator<'_>
+    where
+        Self: Sized,
+    {
+        self.scan(prefix_range(prefix))
+    }
+
+    // Hints that the range is about to be read sequentially (e.g. a bulk
+    // export), so the engine can start readahead. Purely advisory.
+    fn hint_sequential(&mut self, _range: impl std::ops::RangeBounds<Vec<u8>>) -> Result<()>
+    where
+        Self: Sized,
+    {
+        Ok(())
+    }
+
+    // Returns a uniform random sample of up to n keys, for inspecting the
+    // keyspace without a full scan on the caller's side.
+    fn sample_keys(&mut self, n: usize) -> Result<Vec<Vec<u8>>> {
+        let keys = self
+            .scan_dyn((std::ops::Bound::Unbounded, std::ops::Bound::Unbounded))
+            .map(|item| item.map(|(key, _)| key));
+        reservoir_sample(keys, n)
+    }
+
+    // Prepares the engine to serve reads after opening, e.g. by preloading
+    // data that was hot before the last shutdown into the page cache, to avoid
+    // a cold-start latency spike. Purely advisory.
+    fn warm_up(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    // Whether a compaction is in progress, e.g. a merge running in the
+    // background while the engine serves reads and writes.
+    fn is_compacting(&self) -> bool {
+        false
+    }
+
     fn status(&self) -> Result<Status>;
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Returns the range of keys starting with prefix. The end bound is the prefix
+// with trailing 0xff bytes stripped and the last byte incremented, or
+// unbounded if the prefix is empty or all 0xff.
+pub fn prefix_range(prefix: &[u8]) -> (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>) {
+    let start = std::ops::Bound::Included(prefix.to_vec());
+    let end = match prefix.iter().rposition(|b| *b != 0xff) {
+        Some(i) => {
+            let mut end = prefix[..=i].to_vec();
+            end[i] += 1;
+            std::ops::Bound::Excluded(end)
+        }
+        None => std::ops::Bound::Unbounded,
+    };
+    (start, end)
+}
+
+// Algorithm R: keeps the first n items, then replaces a random slot with the
+// i-th item with probability n/i.
+pub(crate) fn reservoir_sample<T>(iter: impl Iterator<Item = Result<T>>, n: usize) -> Result<Vec<T>> {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    let mut sample = Vec::with_capacity(n);
+    for (i, item) in iter.enumerate() {
+        let item = item?;
+        if i < n {
+            sample.push(item);
+        } else {
+            let j = rng.gen_range(0..=i);
+            if j < n {
+                sample[j] = item;
+            }
+        }
+    }
+    Ok(sample)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
     pub name: String,
     pub keys: u64,
@@ -40,4 +185,60 @@ pub struct Status {
     pub total_disk_size: u64,
     pub live_disk_size: u64,
     pub garbage_disk_size: u64,
-}
\ No newline at end of file
+    // Bytes of keys and values submitted by callers, and bytes actually
+    // appended to disk including entry headers and compaction rewrites, since
+    // the engine was opened.
+    pub logical_bytes_written: u64,
+    pub physical_bytes_written: u64,
+    // Extrapolated from the disk growth since the engine was opened or last
+    // compacted, if the engine keeps data on disk.
+    pub forecast: Option<Forecast>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Forecast {
+    // Growth of total_disk_size and garbage_disk_size in bytes per second.
+    pub growth_rate: f64,
+    pub garbage_rate: f64,
+    // Time until the filesystem is full, if its free space is known, and
+    // until garbage reaches the compaction schedule's garbage ratio. None if
+    // that never happens at the current rates.
+    pub until_disk_full: Option<std::time::Duration>,
+    pub until_compaction: Option<std::time::Duration>,
+}
+
+impl Status {
+    pub fn write_amplification(&self) -> f64 {
+        if self.logical_bytes_written == 0 {
+            return 0.0;
+        }
+        self.physical_bytes_written as f64 / self.logical_bytes_written as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Bound;
+
+    #[test]
+    fn test_scan_prefix() -> Result<()> {
+        assert_eq!((Bound::Included(vec![]), Bound::Unbounded), prefix_range(&[]));
+        assert_eq!((Bound::Included(vec![1, 2]), Bound::Excluded(vec![1, 3])), prefix_range(&[1, 2]));
+        assert_eq!((Bound::Included(vec![1, 0xff]), Bound::Excluded(vec![2])), prefix_range(&[1, 0xff]));
+        assert_eq!((Bound::Included(vec![0xff, 0xff]), Bound::Unbounded), prefix_range(&[0xff, 0xff]));
+
+        let mut s = memory::Memory::new();
+        for key in [&[0x01][..], &[0x01, 0x00], &[0x01, 0xff], &[0x01, 0xff, 0xff], &[0x02], &[0xff], &[0xff, 0x00]] {
+            s.set(key, vec![])?;
+        }
+        let keys = |s: &mut memory::Memory, prefix: &[u8]| -> Result<Vec<Vec<u8>>> {
+            s.scan_prefix(prefix).key_only().collect()
+        };
+        assert_eq!(vec![vec![0x01], vec![0x01, 0x00], vec![0x01, 0xff], vec![0x01, 0xff, 0xff]], keys(&mut s, &[0x01])?);
+        assert_eq!(vec![vec![0x01, 0xff], vec![0x01, 0xff, 0xff]], keys(&mut s, &[0x01, 0xff])?);
+        assert_eq!(vec![vec![0xff], vec![0xff, 0x00]], keys(&mut s, &[0xff])?);
+        assert_eq!(7, keys(&mut s, &[])?.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use super::{Engine, ScanIterator, Status};
+use crate::clock::{self, Clock};
+use crate::error::{Error, Result};
+use crate::retry::Backoff;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RetryPolicy {
+    // Used for get and status.
+    pub reads: Backoff,
+    // Used for set and delete.
+    pub writes: Backoff,
+}
+
+// Wraps an engine and retries operations failing with Error::Transient, e.g.
+// EINTR, EAGAIN or a network filesystem timing out, before surfacing the error.
+// Scans are passed through: a failed item cannot be retried without restarting
+// the iteration.
+//
+// Retrying a write is only safe if the inner engine leaves no trace of a failed
+// write. Bitcask updates its keydir only on success, and truncates whatever a
+// failed append left at the end of its log, so the retry doesn't follow a torn
+// record. If it can't truncate it, it turns read-only and the retry fails with
+// Error::Degraded, which isn't retried. A write whose sync failed is cut off
+// the same way, so it doesn't take effect behind the caller's back.
+pub struct Retrying<E: Engine> {
+    inner: E,
+    policy: RetryPolicy,
+    // Waited on between attempts.
+    clock: Arc<dyn Clock>,
+}
+
+impl<E: Engine> Retrying<E> {
+    pub fn new(inner: E, policy: RetryPolicy) -> Self {
+        Self::new_with_clock(inner, policy, clock::system())
+    }
+
+    pub fn new_with_clock(inner: E, policy: RetryPolicy, clock: Arc<dyn Clock>) -> Self {
+        Self { inner, policy, clock }
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+fn is_transient(err: &Error) -> bool {
+    matches!(err, Error::Transient(_))
+}
+
+impl<E: Engine> std::fmt::Display for Retrying<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl<E: Engine> Engine for Retrying<E> {
+    type ScanIterator<'a> = E::ScanIterator<'a> where E: 'a;
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let inner = &mut self.inner;
+        self.policy.writes.retry(&*self.clock, || inner.set(key, value.clone()), is_transient)
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let inner = &mut self.inner;
+        self.policy.reads.retry(&*self.clock, || inner.get(key), is_transient)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        let inner = &mut self.inner;
+        self.policy.writes.retry(&*self.clock, || inner.delete(key), is_transient)
+    }
+
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        let inner = &mut self.inner;
+        self.policy.writes.retry(&*self.clock, || inner.write_batch(batch.clone()), is_transient)
+    }
+
+    // Not retried: after a failed fsync the kernel may have dropped the dirty
+    // pages, so a retry can succeed without the writes being on disk.
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_>
+    where
+        Self: Sized,
+    {
+        self.inner.scan(range)
+    }
+
+    fn scan_dyn(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>)
+    ) -> Box<dyn ScanIterator + '_> {
+        self.inner.scan_dyn(range)
+    }
+
+    fn hint_sequential(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.inner.hint_sequential(range)
+    }
+
+    fn sample_keys(&mut self, n: usize) -> Result<Vec<Vec<u8>>> {
+        let inner = &mut self.inner;
+        self.policy.reads.retry(&*self.clock, || inner.sample_keys(n), is_transient)
+    }
+
+    fn warm_up(&mut self) -> Result<()> {
+        let inner = &mut self.inner;
+        self.policy.reads.retry(&*self.clock, || inner.warm_up(), is_transient)
+    }
+
+    fn is_compacting(&self) -> bool {
+        self.inner.is_compacting()
+    }
+
+    fn status(&self) -> Result<Status> {
+        self.policy.reads.retry(&*self.clock, || self.inner.status(), is_transient)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::BitCask;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    // Fails the first `failures` writes with the given error.
+    struct Flaky {
+        inner: BitCask,
+        failures: u32,
+        error: Error,
+    }
+
+    impl std::fmt::Display for Flaky {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "flaky")
+        }
+    }
+
+    impl Engine for Flaky {
+        type ScanIterator<'a> = <BitCask as Engine>::ScanIterator<'a>;
+
+        fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(self.error.clone());
+            }
+            self.inner.set(key, value)
+        }
+
+        fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+
+        fn delete(&mut self, key: &[u8]) -> Result<()> {
+            self.inner.delete(key)
+        }
+
+        fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+            self.inner.scan(range)
+        }
+
+        fn scan_dyn(
+            &mut self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>)
+        ) -> Box<dyn ScanIterator + '_> {
+            self.inner.scan_dyn(range)
+        }
+
+        fn status(&self) -> Result<Status> {
+            self.inner.status()
+        }
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        let backoff = Backoff { initial: Duration::ZERO, max: Duration::ZERO, max_attempts };
+        RetryPolicy { reads: backoff.clone(), writes: backoff }
+    }
+
+    #[test]
+    fn test_retrying() -> Result<()> {
+        let dir = TempDir::new("retrying").expect("Failed to create temporary directory");
+        let inner = BitCask::new(dir.path().join("log"))?;
+        let transient = Error::Transient("interrupted".to_string());
+
+        let mut s = Retrying::new(Flaky { inner, failures: 2, error: transient.clone() }, policy(3));
+        s.set(b"a", vec![0x01])?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+
+        let mut s = Retrying::new(Flaky { failures: 3, ..s.into_inner() }, policy(3));
+        assert_eq!(Err(transient), s.set(b"b", vec![0x02]));
+        assert_eq!(None, s.get(b"b")?);
+
+        let internal = Error::Internal("disk on fire".to_string());
+        let mut s = Retrying::new(Flaky { failures: 1, error: internal.clone(), ..s.into_inner() }, policy(3));
+        assert_eq!(Err(internal), s.set(b"c", vec![0x03]));
+        s.set(b"c", vec![0x03])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use super::ScanIterator;
+use crate::error::Result;
+
+// Yields only the keys of a scan. See ScanIterator::key_only.
+pub struct KeyOnly<I> {
+    inner: I,
+}
+
+impl<I> KeyOnly<I> {
+    pub(super) fn new(inner: I) -> Self {
+        Self { inner }
+    }
+}
+
+impl<I: ScanIterator> Iterator for KeyOnly<I> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|item| item.map(|(key, _)| key))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<I: ScanIterator> DoubleEndedIterator for KeyOnly<I> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|item| item.map(|(key, _)| key))
+    }
+}
+
+// Yields the items of a scan while their keys start with a prefix. See
+// ScanIterator::take_while_prefix.
+pub struct TakeWhilePrefix<I> {
+    inner: I,
+    prefix: Vec<u8>,
+    // Whether a matching item was yielded from the back, after which the next
+    // item that doesn't match comes before the prefix's range.
+    matched_back: bool,
+    done: bool,
+}
+
+impl<I> TakeWhilePrefix<I> {
+    pub(super) fn new(inner: I, prefix: Vec<u8>) -> Self {
+        Self { inner, prefix, matched_back: false, done: false }
+    }
+}
+
+impl<I: ScanIterator> Iterator for TakeWhilePrefix<I> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.inner.next()? {
+            Ok((key, _)) if !key.starts_with(&self.prefix) => {
+                self.done = true;
+                None
+            }
+            item => Some(item),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.done {
+            return (0, Some(0));
+        }
+        (0, self.inner.size_hint().1)
+    }
+}
+
+// Skips the items after the prefix's range, then yields the matching ones.
+impl<I: ScanIterator> DoubleEndedIterator for TakeWhilePrefix<I> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.inner.next_back()? {
+                Ok((key, _)) if !key.starts_with(&self.prefix) => {
+                    if self.matched_back {
+                        self.done = true;
+                    }
+                }
+                Ok(item) => {
+                    self.matched_back = true;
+                    return Some(Ok(item));
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        None
+    }
+}
+
+impl<I: ScanIterator> ScanIterator for TakeWhilePrefix<I> {}
+
+// A scan that can look at its next item without consuming it. See
+// ScanIterator::peeking.
+pub struct Peeking<I: ScanIterator> {
+    inner: I,
+    // The item peeked from the front, None if the scan was found exhausted.
+    peeked: Option<Option<I::Item>>,
+}
+
+impl<I: ScanIterator> Peeking<I> {
+    pub(super) fn new(inner: I) -> Self {
+        Self { inner, peeked: None }
+    }
+
+    // Returns the next item without consuming it.
+    pub fn peek(&mut self) -> Option<&Result<(Vec<u8>, Vec<u8>)>> {
+        let inner = &mut self.inner;
+        self.peeked.get_or_insert_with(|| inner.next()).as_ref()
+    }
+
+    // Returns the next key without consuming it, or the error in its place.
+    pub fn peek_key(&mut self) -> Option<Result<&[u8]>> {
+        self.peek().map(|item| item.as_ref().map(|(key, _)| key.as_slice()).map_err(|err| err.clone()))
+    }
+}
+
+impl<I: ScanIterator> Iterator for Peeking<I> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.peeked.take() {
+            Some(item) => item,
+            None => self.inner.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let peeked = match self.peeked {
+            Some(None) => return (0, Some(0)),
+            Some(Some(_)) => 1,
+            None => 0,
+        };
+        let (lower, upper) = self.inner.size_hint();
+        (lower.saturating_add(peeked), upper.and_then(|upper| upper.checked_add(peeked)))
+    }
+}
+
+// Once the back reaches the peeked item, it is the last one left.
+impl<I: ScanIterator> DoubleEndedIterator for Peeking<I> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match &self.peeked {
+            Some(None) => None,
+            Some(Some(_)) => self.inner.next_back().or_else(|| self.peeked.take().flatten()),
+            None => self.inner.next_back(),
+        }
+    }
+}
+
+impl<I: ScanIterator> ScanIterator for Peeking<I> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::Memory;
+    use crate::storage::Engine;
+
+    #[test]
+    fn test_adapters() -> Result<()> {
+        let mut s = Memory::new();
+        for key in [&b"a"[..], b"b/1", b"b/2", b"b/3", b"c"] {
+            s.set(key, key.to_vec())?;
+        }
+        let keys = |keys: &[&[u8]]| keys.iter().map(|key| key.to_vec()).collect::<Vec<_>>();
+
+        let mut scan = s.scan(b"b/".to_vec()..).peeking();
+        assert_eq!(Some(&b"b/1"[..]), scan.peek_key().transpose()?);
+        assert_eq!(Some(&b"b/1"[..]), scan.peek_key().transpose()?);
+        assert_eq!(
+            keys(&[b"b/1", b"b/2", b"b/3"]),
+            scan.take_while_prefix(b"b/".to_vec()).key_only().collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            keys(&[b"b/3", b"b/2", b"b/1"]),
+            s.scan(..).take_while_prefix(b"b/".to_vec()).key_only().rev().collect::<Result<Vec<_>>>()?
+        );
+        assert_eq!(
+            keys(&[b"a", b"b/1", b"b/2", b"b/3", b"c"]),
+            s.scan_dyn((std::ops::Bound::Unbounded, std::ops::Bound::Unbounded))
+                .key_only()
+                .collect::<Result<Vec<_>>>()?
+        );
+
+        // Iteration stops at the first key past the prefix, from either end.
+        let mut scan = s.scan(b"b/".to_vec()..).take_while_prefix(b"b/".to_vec());
+        assert_eq!(Some(b"b/1".to_vec()), scan.next().transpose()?.map(|(key, _)| key));
+        assert_eq!(Some(b"b/3".to_vec()), scan.next_back().transpose()?.map(|(key, _)| key));
+        assert_eq!(Some(b"b/2".to_vec()), scan.next().transpose()?.map(|(key, _)| key));
+        assert!(scan.next().is_none());
+        assert!(s.scan(..).take_while_prefix(b"x".to_vec()).next().is_none());
+
+        // A peeked item is still returned from the back once it's the last.
+        let mut scan = s.scan(b"c".to_vec()..).peeking();
+        assert_eq!(Some(&b"c"[..]), scan.peek_key().transpose()?);
+        assert_eq!(Some(b"c".to_vec()), scan.next_back().transpose()?.map(|(key, _)| key));
+        assert!(scan.peek().is_none());
+        assert!(scan.next().is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IoClass {
+    // Gets, sets and deletes issued by clients.
+    Foreground,
+    // Reads and writes done while compacting the log.
+    Compaction,
+    // Reads done by the scrubber.
+    Scrub,
+}
+
+// Throttles I/O per class with a token bucket each, so maintenance work like
+// compaction can't saturate the disk and cause latency spikes for foreground
+// requests. Classes without a configured rate are not throttled. A scheduler
+// can be shared between stores (e.g. all databases of a DbManager) to bound
+// their combined background bandwidth.
+#[derive(Debug)]
+pub struct IoScheduler {
+    clock: Arc<dyn Clock>,
+    buckets: Mutex<HashMap<IoClass, TokenBucket>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    // Refill rate in bytes per second, which is also the burst size.
+    rate: u64,
+    // May go negative when a request is larger than the available tokens; the
+    // requester then sleeps until the deficit would have been refilled.
+    tokens: f64,
+    last_refill: Duration,
+}
+
+impl IoScheduler {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { clock, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    // Splits a total bandwidth in bytes per second between classes, e.g.
+    // &[(IoClass::Compaction, 0.2)] caps compaction at 20% of it and leaves
+    // foreground I/O unthrottled.
+    pub fn with_shares(clock: Arc<dyn Clock>, bandwidth: u64, shares: &[(IoClass, f64)]) -> Self {
+        let scheduler = Self::new(clock);
+        for (class, share) in shares {
+            scheduler.set_rate(*class, Some((bandwidth as f64 * share) as u64));
+        }
+        scheduler
+    }
+
+    // Sets the rate of a class in bytes per second, or None to stop throttling it.
+    pub fn set_rate(&self, class: IoClass, rate: Option<u64>) {
+        let mut buckets = self.lock();
+        match rate {
+            Some(rate) => {
+                let rate = rate.max(1);
+                let bucket = TokenBucket { rate, tokens: rate as f64, last_refill: self.clock.now() };
+                buckets.insert(class, bucket);
+            }
+            None => {
+                buckets.remove(&class);
+            }
+        }
+    }
+
+    // Accounts for bytes of I/O of the given class, sleeping if the class is
+    // over its rate.
+    pub fn acquire(&self, class: IoClass, bytes: u64) {
+        let wait = {
+            let mut buckets = self.lock();
+            let Some(bucket) = buckets.get_mut(&class) else {
+                return;
+            };
+            let now = self.clock.now();
+            let elapsed = now.saturating_sub(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * bucket.rate as f64).min(bucket.rate as f64);
+            bucket.last_refill = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / bucket.rate as f64)
+        };
+        self.clock.sleep(wait);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<IoClass, TokenBucket>> {
+        self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::error::Result;
+    use crate::storage::bitcask::{BitCask, Options};
+    use crate::storage::Engine;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_io_scheduler() {
+        let clock = MockClock::new(Duration::from_secs(100));
+        let scheduler = IoScheduler::with_shares(Arc::new(clock.clone()), 1000, &[(IoClass::Compaction, 0.5)]);
+
+        scheduler.acquire(IoClass::Foreground, 1_000_000);
+        scheduler.acquire(IoClass::Compaction, 500);
+        assert_eq!(Duration::from_secs(100), clock.now());
+
+        scheduler.acquire(IoClass::Compaction, 250);
+        assert_eq!(Duration::from_millis(100_500), clock.now());
+
+        // The deficit was paid off by sleeping, so a second later the bucket is full.
+        clock.advance(Duration::from_secs(1));
+        scheduler.acquire(IoClass::Compaction, 1000);
+        assert_eq!(Duration::from_millis(102_500), clock.now());
+
+        scheduler.set_rate(IoClass::Compaction, None);
+        scheduler.acquire(IoClass::Compaction, 1000);
+        assert_eq!(Duration::from_millis(102_500), clock.now());
+    }
+
+    #[test]
+    fn test_throttled_compaction() -> Result<()> {
+        let dir = TempDir::new("scheduler").expect("Failed to create temporary directory");
+        let clock = MockClock::new(Duration::from_secs(100));
+        let scheduler = IoScheduler::with_shares(Arc::new(clock.clone()), 128, &[(IoClass::Compaction, 1.0)]);
+        let options = Options {
+            clock: Arc::new(clock.clone()),
+            io_scheduler: Some(Arc::new(scheduler)),
+            ..Default::default()
+        };
+        let mut s = BitCask::new_with_options(dir.path().join("log"), options)?;
+        s.set(b"a", vec![0; 100])?;
+        s.set(b"b", vec![0; 100])?;
+        assert_eq!(Duration::from_secs(100), clock.now());
+
+        // Compaction reads 200 value bytes and writes 226 bytes of entries, of
+        // which the first 128 are covered by the initial burst.
+        s.compact()?;
+        assert_eq!(Duration::from_secs(100) + Duration::from_secs(298) / 128, clock.now());
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use super::Engine;
+use crate::error::Result;
+
+// How keys are grouped into top-level prefixes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grouping {
+    // Up to and including the first occurrence of the delimiter, or the whole
+    // key if it doesn't contain one.
+    Delimiter(u8),
+    // The first n bytes of the key.
+    Depth(usize),
+}
+
+impl Grouping {
+    pub fn prefix<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        match self {
+            Grouping::Delimiter(delimiter) => match key.iter().position(|b| b == delimiter) {
+                Some(i) => &key[..=i],
+                None => key,
+            },
+            Grouping::Depth(depth) => &key[..key.len().min(*depth)],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrefixUsage {
+    pub prefix: Vec<u8>,
+    pub keys: u64,
+    pub key_bytes: u64,
+    pub value_bytes: u64,
+}
+
+impl PrefixUsage {
+    pub fn bytes(&self) -> u64 {
+        self.key_bytes + self.value_bytes
+    }
+}
+
+// Aggregates key count and byte usage per prefix over the whole keyspace,
+// sorted by descending total bytes.
+pub fn prefix_usage<E: Engine + ?Sized>(engine: &mut E, grouping: Grouping) -> Result<Vec<PrefixUsage>> {
+    let mut usage: BTreeMap<Vec<u8>, PrefixUsage> = BTreeMap::new();
+    for item in engine.scan_dyn((std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)) {
+        let (key, value) = item?;
+        let prefix = grouping.prefix(&key);
+        let entry = match usage.get_mut(prefix) {
+            Some(entry) => entry,
+            None => usage.entry(prefix.to_vec()).or_insert_with(|| PrefixUsage {
+                prefix: prefix.to_vec(),
+                ..Default::default()
+            }),
+        };
+        entry.keys += 1;
+        entry.key_bytes += key.len() as u64;
+        entry.value_bytes += value.len() as u64;
+    }
+    let mut usage: Vec<_> = usage.into_values().collect();
+    usage.sort_by(|a, b| b.bytes().cmp(&a.bytes()).then_with(|| a.prefix.cmp(&b.prefix)));
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::bitcask::BitCask;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_prefix_usage() -> Result<()> {
+        let dir = TempDir::new("usage").expect("Failed to create temporary directory");
+        let mut s = BitCask::new(dir.path().join("log"))?;
+        s.set(b"users/1", vec![0; 10])?;
+        s.set(b"users/2", vec![0; 20])?;
+        s.set(b"orders/1", vec![0; 100])?;
+        s.set(b"config", vec![0; 1])?;
+
+        assert_eq!(
+            vec![
+                PrefixUsage { prefix: b"orders/".to_vec(), keys: 1, key_bytes: 8, value_bytes: 100 },
+                PrefixUsage { prefix: b"users/".to_vec(), keys: 2, key_bytes: 14, value_bytes: 30 },
+                PrefixUsage { prefix: b"config".to_vec(), keys: 1, key_bytes: 6, value_bytes: 1 },
+            ],
+            prefix_usage(&mut s, Grouping::Delimiter(b'/'))?
+        );
+
+        let usage = prefix_usage(&mut s, Grouping::Depth(1))?;
+        assert_eq!(
+            vec![(b"o".to_vec(), 1), (b"u".to_vec(), 2), (b"c".to_vec(), 1)],
+            usage.into_iter().map(|u| (u.prefix, u.keys)).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
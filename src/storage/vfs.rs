@@ -0,0 +1,662 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// The file operations Bitcask needs, so it can run on something other than
+// std::fs: an in-memory filesystem for tests and ephemeral stores, a
+// fault-injecting wrapper, or a platform without a POSIX filesystem. Methods
+// return io errors so callers can still tell e.g. UnexpectedEof apart.
+pub trait Vfs: std::fmt::Debug + Send + Sync {
+    // Opens a file for reading and appending, creating it if missing.
+    fn open(&self, path: &Path) -> Result<Box<dyn File>>;
+
+    // Opens an existing file for reading only, failing with NotFound if it's
+    // missing.
+    fn open_read(&self, path: &Path) -> Result<Box<dyn File>>;
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()>;
+
+    // Returns the paths of the directory's immediate children, in no
+    // particular order.
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+
+    // Returns the bytes available to this process on the filesystem holding
+    // path, or None if unknown.
+    fn available_space(&self, _path: &Path) -> Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+pub trait File: std::fmt::Debug + Send + Sync {
+    // Reads up to buf.len() bytes at offset, returning the number read; 0 at
+    // or past the end of the file.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
+
+    // Appends data at the end of the file and returns the offset it was
+    // written at.
+    fn append(&self, data: &[u8]) -> Result<u64>;
+
+    fn size(&self) -> Result<u64>;
+
+    fn set_len(&self, len: u64) -> Result<()>;
+
+    fn sync(&self) -> Result<()>;
+
+    // Takes an exclusive advisory lock, failing with WouldBlock if another
+    // handle holds it. The lock is released when the handle is dropped.
+    fn lock(&self) -> Result<()>;
+
+    // Takes a shared advisory lock, failing with WouldBlock if another handle
+    // holds an exclusive one. Replaces an exclusive lock held by this handle.
+    fn lock_shared(&self) -> Result<()>;
+
+    // Hints that the range will be read sequentially soon. Advisory only.
+    fn readahead(&self, _offset: u64, _len: u64) -> Result<()> {
+        Ok(())
+    }
+
+    // Reserves disk blocks for the first len bytes without changing the
+    // file's size, so later appends needn't allocate. A no-op where the
+    // filesystem doesn't support it.
+    fn preallocate(&self, _len: u64) -> Result<()> {
+        Ok(())
+    }
+
+    // Frees the disk blocks of the range without changing the file's size;
+    // it then reads as zeros. A no-op where the filesystem doesn't support
+    // it, in which case the old contents remain.
+    fn punch_hole(&self, _offset: u64, _len: u64) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Adapts a File to Read + Seek, e.g. for use with a BufReader.
+pub struct Reader<'a> {
+    file: &'a dyn File,
+    pos: u64,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(file: &'a dyn File) -> Self {
+        Self { file, pos: 0 }
+    }
+}
+
+impl Read for Reader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.file.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Reader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.file.size()?.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "invalid seek position"))?;
+        Ok(self.pos)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdFs;
+
+#[derive(Debug)]
+struct StdFile(std::fs::File);
+
+impl Vfs for StdFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn File>> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Box::new(StdFile(file)))
+    }
+
+    fn open_read(&self, path: &Path) -> Result<Box<dyn File>> {
+        Ok(Box::new(StdFile(std::fs::File::open(path)?)))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        std::fs::read_dir(path)?.map(|entry| Ok(entry?.path())).collect()
+    }
+
+    fn available_space(&self, path: &Path) -> Result<Option<u64>> {
+        fs4::available_space(path).map(Some)
+    }
+}
+
+impl File for StdFile {
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        std::os::unix::fs::FileExt::read_at(&self.0, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(&self.0, buf, offset)
+    }
+
+    fn append(&self, data: &[u8]) -> Result<u64> {
+        use std::io::Write;
+
+        let mut file = &self.0;
+        let pos = file.seek(SeekFrom::End(0))?;
+        file.write_all(data)?;
+        Ok(pos)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.0.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> Result<()> {
+        self.0.set_len(len)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.0.sync_all()
+    }
+
+    fn lock(&self) -> Result<()> {
+        fs4::FileExt::try_lock_exclusive(&self.0)
+    }
+
+    fn lock_shared(&self) -> Result<()> {
+        fs4::FileExt::try_lock_shared(&self.0)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn readahead(&self, offset: u64, len: u64) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        for advice in [libc::POSIX_FADV_SEQUENTIAL, libc::POSIX_FADV_WILLNEED] {
+            let ret = unsafe {
+                libc::posix_fadvise(self.0.as_raw_fd(), offset as libc::off_t, len as libc::off_t, advice)
+            };
+            if ret != 0 {
+                return Err(Error::from_raw_os_error(ret));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn preallocate(&self, len: u64) -> Result<()> {
+        fallocate(&self.0, libc::FALLOC_FL_KEEP_SIZE, 0, len)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn punch_hole(&self, offset: u64, len: u64) -> Result<()> {
+        fallocate(&self.0, libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE, offset, len)
+    }
+}
+
+// Calls fallocate, treating filesystems that don't support the mode as
+// success.
+#[cfg(target_os = "linux")]
+fn fallocate(file: &std::fs::File, mode: libc::c_int, offset: u64, len: u64) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe { libc::fallocate(file.as_raw_fd(), mode, offset as libc::off_t, len as libc::off_t) };
+    if ret != 0 {
+        let err = Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::EOPNOTSUPP | libc::ENOSYS) => Ok(()),
+            _ => Err(err),
+        };
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct MemNode {
+    data: Vec<u8>,
+    exclusive: bool,
+    shared: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+// An in-memory filesystem. Clones share the same files, so a store can be
+// closed and reopened on the same MemFs. Directories are implicit.
+#[derive(Clone, Debug, Default)]
+pub struct MemFs {
+    files: Arc<Mutex<HashMap<PathBuf, Arc<Mutex<MemNode>>>>>,
+}
+
+#[derive(Debug)]
+struct MemFile {
+    node: Arc<Mutex<MemNode>>,
+    holds_lock: Mutex<Option<LockKind>>,
+}
+
+impl MemFile {
+    fn take_lock(&self, kind: LockKind) -> Result<()> {
+        let mut holds_lock = lock(&self.holds_lock);
+        if *holds_lock == Some(kind) {
+            return Ok(());
+        }
+        let mut node = lock(&self.node);
+        let exclusive = node.exclusive && *holds_lock != Some(LockKind::Exclusive);
+        let shared = node.shared - usize::from(*holds_lock == Some(LockKind::Shared));
+        if exclusive || (kind == LockKind::Exclusive && shared > 0) {
+            return Err(Error::new(ErrorKind::WouldBlock, "file is locked"));
+        }
+        release(&mut node, holds_lock.take());
+        match kind {
+            LockKind::Shared => node.shared += 1,
+            LockKind::Exclusive => node.exclusive = true,
+        }
+        *holds_lock = Some(kind);
+        Ok(())
+    }
+}
+
+fn release(node: &mut MemNode, held: Option<LockKind>) {
+    match held {
+        Some(LockKind::Shared) => node.shared -= 1,
+        Some(LockKind::Exclusive) => node.exclusive = false,
+        None => {}
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns a copy of a file's contents, for inspection in tests.
+    pub fn read(&self, path: &Path) -> Option<Vec<u8>> {
+        lock(&self.files).get(path).map(|node| lock(node).data.clone())
+    }
+
+    // Replaces a file's contents, e.g. to simulate corruption.
+    pub fn write(&self, path: &Path, data: Vec<u8>) {
+        let node = lock(&self.files).entry(path.to_path_buf()).or_default().clone();
+        lock(&node).data = data;
+    }
+}
+
+impl Vfs for MemFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn File>> {
+        let node = lock(&self.files).entry(path.to_path_buf()).or_default().clone();
+        Ok(Box::new(MemFile { node, holds_lock: Mutex::new(None) }))
+    }
+
+    fn open_read(&self, path: &Path) -> Result<Box<dyn File>> {
+        let node = lock(&self.files).get(path).cloned().ok_or_else(|| Error::from(ErrorKind::NotFound))?;
+        Ok(Box::new(MemFile { node, holds_lock: Mutex::new(None) }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut files = lock(&self.files);
+        let node = files.remove(from).ok_or_else(|| Error::from(ErrorKind::NotFound))?;
+        files.insert(to.to_path_buf(), node);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        lock(&self.files).remove(path).map(|_| ()).ok_or_else(|| Error::from(ErrorKind::NotFound))
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        lock(&self.files).retain(|file, _| !file.starts_with(path));
+        Ok(())
+    }
+
+    // Empty directories don't exist, so this lists the children of the
+    // directories implied by the file paths below it.
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let children: std::collections::BTreeSet<_> = lock(&self.files)
+            .keys()
+            .filter_map(|file| file.strip_prefix(path).ok()?.components().next())
+            .map(|child| path.join(child))
+            .collect();
+        Ok(children.into_iter().collect())
+    }
+}
+
+impl File for MemFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let node = lock(&self.node);
+        let start = (offset as usize).min(node.data.len());
+        let n = buf.len().min(node.data.len() - start);
+        buf[..n].copy_from_slice(&node.data[start..start + n]);
+        Ok(n)
+    }
+
+    fn append(&self, data: &[u8]) -> Result<u64> {
+        let mut node = lock(&self.node);
+        let pos = node.data.len() as u64;
+        node.data.extend_from_slice(data);
+        Ok(pos)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(lock(&self.node).data.len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> Result<()> {
+        lock(&self.node).data.resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn punch_hole(&self, offset: u64, len: u64) -> Result<()> {
+        let mut node = lock(&self.node);
+        let end = (offset.saturating_add(len) as usize).min(node.data.len());
+        let start = (offset as usize).min(end);
+        node.data[start..end].fill(0);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<()> {
+        self.take_lock(LockKind::Exclusive)
+    }
+
+    fn lock_shared(&self) -> Result<()> {
+        self.take_lock(LockKind::Shared)
+    }
+}
+
+impl Drop for MemFile {
+    fn drop(&mut self) {
+        let held = lock(&self.holds_lock).take();
+        release(&mut lock(&self.node), held);
+    }
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    reads: u32,
+    writes: u32,
+    syncs: u32,
+    kind: Option<ErrorKind>,
+    // Bytes to write before failing the next write, simulating a torn write.
+    torn: Option<usize>,
+}
+
+// Shared fault configuration for a FaultyFs. Each fail_* call makes the next n
+// operations of that type fail with the given error kind.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl Faults {
+    pub fn fail_reads(&self, n: u32, kind: ErrorKind) {
+        let mut state = lock(&self.state);
+        state.reads = n;
+        state.kind = Some(kind);
+    }
+
+    pub fn fail_writes(&self, n: u32, kind: ErrorKind) {
+        let mut state = lock(&self.state);
+        state.writes = n;
+        state.kind = Some(kind);
+    }
+
+    pub fn fail_syncs(&self, n: u32, kind: ErrorKind) {
+        let mut state = lock(&self.state);
+        state.syncs = n;
+        state.kind = Some(kind);
+    }
+
+    // Makes the next append write only the first `bytes` bytes and then fail.
+    pub fn tear_next_write(&self, bytes: usize) {
+        lock(&self.state).torn = Some(bytes);
+    }
+
+    pub fn clear(&self) {
+        *lock(&self.state) = FaultState::default();
+    }
+
+    fn check(&self, counter: impl Fn(&mut FaultState) -> &mut u32) -> Result<()> {
+        let mut state = lock(&self.state);
+        let kind = state.kind.unwrap_or(ErrorKind::Other);
+        let count = counter(&mut state);
+        if *count > 0 {
+            *count -= 1;
+            return Err(Error::new(kind, "injected fault"));
+        }
+        Ok(())
+    }
+}
+
+// Wraps another Vfs and injects errors according to the shared Faults.
+#[derive(Debug)]
+pub struct FaultyFs {
+    inner: Arc<dyn Vfs>,
+    faults: Faults,
+}
+
+impl FaultyFs {
+    pub fn new(inner: Arc<dyn Vfs>, faults: Faults) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[derive(Debug)]
+struct FaultyFile {
+    inner: Box<dyn File>,
+    faults: Faults,
+}
+
+impl Vfs for FaultyFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn File>> {
+        self.faults.check(|s| &mut s.reads)?;
+        Ok(Box::new(FaultyFile { inner: self.inner.open(path)?, faults: self.faults.clone() }))
+    }
+
+    fn open_read(&self, path: &Path) -> Result<Box<dyn File>> {
+        self.faults.check(|s| &mut s.reads)?;
+        Ok(Box::new(FaultyFile { inner: self.inner.open_read(path)?, faults: self.faults.clone() }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.faults.check(|s| &mut s.writes)?;
+        self.inner.rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.faults.check(|s| &mut s.writes)?;
+        self.inner.remove(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        self.faults.check(|s| &mut s.writes)?;
+        self.inner.remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.faults.check(|s| &mut s.reads)?;
+        self.inner.read_dir(path)
+    }
+
+    fn available_space(&self, path: &Path) -> Result<Option<u64>> {
+        self.inner.available_space(path)
+    }
+}
+
+impl File for FaultyFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.faults.check(|s| &mut s.reads)?;
+        self.inner.read_at(buf, offset)
+    }
+
+    fn append(&self, data: &[u8]) -> Result<u64> {
+        self.faults.check(|s| &mut s.writes)?;
+        if let Some(bytes) = lock(&self.faults.state).torn.take() {
+            self.inner.append(&data[..bytes.min(data.len())])?;
+            return Err(Error::other("injected torn write"));
+        }
+        self.inner.append(data)
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.inner.size()
+    }
+
+    fn set_len(&self, len: u64) -> Result<()> {
+        self.faults.check(|s| &mut s.writes)?;
+        self.inner.set_len(len)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.faults.check(|s| &mut s.syncs)?;
+        self.inner.sync()
+    }
+
+    fn lock(&self) -> Result<()> {
+        self.inner.lock()
+    }
+
+    fn lock_shared(&self) -> Result<()> {
+        self.inner.lock_shared()
+    }
+
+    fn readahead(&self, offset: u64, len: u64) -> Result<()> {
+        self.inner.readahead(offset, len)
+    }
+
+    fn preallocate(&self, len: u64) -> Result<()> {
+        self.faults.check(|s| &mut s.writes)?;
+        self.inner.preallocate(len)
+    }
+
+    fn punch_hole(&self, offset: u64, len: u64) -> Result<()> {
+        self.faults.check(|s| &mut s.writes)?;
+        self.inner.punch_hole(offset, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memfs() -> Result<()> {
+        let fs = MemFs::new();
+        let path = Path::new("/db/log");
+        let file = fs.open(path)?;
+        assert_eq!(0, file.append(b"hello")?);
+        assert_eq!(5, file.append(b" world")?);
+        assert_eq!(11, file.size()?);
+
+        let mut buf = [0; 5];
+        file.read_exact_at(&mut buf, 6)?;
+        assert_eq!(b"world", &buf);
+        assert_eq!(ErrorKind::UnexpectedEof, file.read_exact_at(&mut buf, 8).unwrap_err().kind());
+
+        file.lock()?;
+        let other = fs.open(path)?;
+        assert_eq!(ErrorKind::WouldBlock, other.lock().unwrap_err().kind());
+        assert_eq!(ErrorKind::WouldBlock, other.lock_shared().unwrap_err().kind());
+        file.lock_shared()?;
+        other.lock_shared()?;
+        assert_eq!(ErrorKind::WouldBlock, file.lock().unwrap_err().kind());
+        drop(file);
+        other.lock()?;
+
+        fs.rename(path, Path::new("/db/renamed"))?;
+        assert_eq!(None, fs.read(path));
+        assert_eq!(Some(b"hello world".to_vec()), fs.read(Path::new("/db/renamed")));
+        other.set_len(5)?;
+        assert_eq!(Some(b"hello".to_vec()), fs.read(Path::new("/db/renamed")));
+
+        assert_eq!(ErrorKind::NotFound, fs.open_read(Path::new("/db/missing")).unwrap_err().kind());
+        fs.open(Path::new("/db/sub/log"))?;
+        let mut children = fs.read_dir(Path::new("/db"))?;
+        children.sort();
+        assert_eq!(vec![PathBuf::from("/db/renamed"), PathBuf::from("/db/sub")], children);
+        fs.remove_dir_all(Path::new("/db/sub"))?;
+        assert_eq!(vec![PathBuf::from("/db/renamed")], fs.read_dir(Path::new("/db"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_faultyfs() -> Result<()> {
+        let mem = MemFs::new();
+        let faults = Faults::default();
+        let fs = FaultyFs::new(Arc::new(mem.clone()), faults.clone());
+        let file = fs.open(Path::new("log"))?;
+
+        faults.fail_writes(1, ErrorKind::Interrupted);
+        assert_eq!(ErrorKind::Interrupted, file.append(b"abc").unwrap_err().kind());
+        assert_eq!(0, file.append(b"abc")?);
+
+        faults.tear_next_write(2);
+        assert!(file.append(b"defg").is_err());
+        assert_eq!(Some(b"abcde".to_vec()), mem.read(Path::new("log")));
+
+        faults.fail_syncs(2, ErrorKind::Other);
+        assert!(file.sync().is_err());
+        faults.clear();
+        file.sync()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::vfs::{File, Vfs};
+use crate::clock::Clock;
+
+// Detects I/O operations that hang, e.g. an fsync on a dying disk, which would
+// otherwise block the store without leaving a trace. Filesystems wrapped by
+// Watchdog::wrap register every file operation while it runs, and check logs
+// the ones that have been running for longer than the limit, with their file
+// and offsets. Call it periodically, e.g. from Watchdog::start.
+//
+// With degrade_on_stall, a stuck operation also switches the wrapped
+// filesystems into a read-only degraded mode until clear_degraded is called:
+// writes, renames, removals and syncs fail with Error::Degraded, so the store
+// keeps serving reads instead of piling up writers behind the stuck one.
+#[derive(Debug)]
+pub struct Watchdog {
+    clock: Arc<dyn Clock>,
+    limit: Duration,
+    degrade_on_stall: bool,
+    degraded: AtomicBool,
+    next_id: AtomicU64,
+    // Operations in flight by ID, and whether they were reported as stuck.
+    pending: Mutex<HashMap<u64, (PendingOp, bool)>>,
+}
+
+// A file operation in flight.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingOp {
+    pub op: &'static str,
+    pub path: PathBuf,
+    // The offset and length of the bytes read or written, where known.
+    pub offset: Option<u64>,
+    pub len: Option<u64>,
+    pub started: Duration,
+}
+
+impl Watchdog {
+    pub fn new(clock: Arc<dyn Clock>, limit: Duration, degrade_on_stall: bool) -> Arc<Self> {
+        Arc::new(Self {
+            clock,
+            limit,
+            degrade_on_stall,
+            degraded: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Wraps a filesystem so its file operations are watched.
+    pub fn wrap(self: &Arc<Self>, vfs: Arc<dyn Vfs>) -> Arc<dyn Vfs> {
+        Arc::new(WatchedFs { inner: vfs, watchdog: self.clone() })
+    }
+
+    // Returns the operations running for longer than the limit, oldest first.
+    // Newly stuck operations are logged, and degrade the filesystems if
+    // configured to.
+    pub fn check(&self) -> Vec<PendingOp> {
+        let now = self.clock.now();
+        let mut stuck = Vec::new();
+        for (op, reported) in lock(&self.pending).values_mut() {
+            let elapsed = now.saturating_sub(op.started);
+            if elapsed < self.limit {
+                continue;
+            }
+            if !*reported {
+                *reported = true;
+                log::error!(
+                    "I/O operation {} on {} (offset {:?}, length {:?}) has been running for {:?}",
+                    op.op,
+                    op.path.display(),
+                    op.offset,
+                    op.len,
+                    elapsed
+                );
+            }
+            stuck.push(op.clone());
+        }
+        if !stuck.is_empty() && self.degrade_on_stall && !self.degraded.swap(true, Ordering::SeqCst) {
+            log::error!("Switching to read-only mode after a stuck I/O operation");
+        }
+        stuck.sort_by_key(|op| op.started);
+        stuck
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::SeqCst)
+    }
+
+    // Leaves the read-only degraded mode, e.g. once the disk was replaced.
+    pub fn clear_degraded(&self) {
+        self.degraded.store(false, Ordering::SeqCst);
+    }
+
+    // Calls check every interval from a background thread, until the
+    // returned handle is dropped.
+    pub fn start(self: &Arc<Self>, interval: Duration) -> WatchdogThread {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = std::thread::spawn({
+            let watchdog = self.clone();
+            let stopped = stopped.clone();
+            move || {
+                let (stopped, wakeup) = &*stopped;
+                loop {
+                    let guard = wakeup
+                        .wait_timeout_while(lock(stopped), interval, |stopped| !*stopped)
+                        .map(|(guard, _)| guard)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner().0);
+                    if *guard {
+                        return;
+                    }
+                    drop(guard);
+                    watchdog.check();
+                }
+            }
+        });
+        WatchdogThread { stopped, thread: Some(thread) }
+    }
+
+    fn track<T>(
+        &self,
+        op: &'static str,
+        path: &Path,
+        offset: Option<u64>,
+        len: Option<u64>,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let pending = PendingOp { op, path: path.to_path_buf(), offset, len, started: self.clock.now() };
+        lock(&self.pending).insert(id, (pending, false));
+        let result = f();
+        lock(&self.pending).remove(&id);
+        result
+    }
+
+    fn check_writable(&self, path: &Path) -> Result<()> {
+        if self.is_degraded() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                crate::error::Error::Degraded(format!(
+                    "can't write {}: read-only after a stuck I/O operation",
+                    path.display()
+                )),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Stops the watchdog's thread when dropped.
+pub struct WatchdogThread {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatchdogThread {
+    fn drop(&mut self) {
+        let (stopped, wakeup) = &*self.stopped;
+        *lock(stopped) = true;
+        wakeup.notify_all();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Watchdog thread panicked");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct WatchedFs {
+    inner: Arc<dyn Vfs>,
+    watchdog: Arc<Watchdog>,
+}
+
+#[derive(Debug)]
+struct WatchedFile {
+    inner: Box<dyn File>,
+    path: PathBuf,
+    watchdog: Arc<Watchdog>,
+}
+
+impl Vfs for WatchedFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn File>> {
+        let inner = self.watchdog.track("open", path, None, None, || self.inner.open(path))?;
+        Ok(Box::new(WatchedFile { inner, path: path.to_path_buf(), watchdog: self.watchdog.clone() }))
+    }
+
+    fn open_read(&self, path: &Path) -> Result<Box<dyn File>> {
+        let inner = self.watchdog.track("open_read", path, None, None, || self.inner.open_read(path))?;
+        Ok(Box::new(WatchedFile { inner, path: path.to_path_buf(), watchdog: self.watchdog.clone() }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.watchdog.check_writable(from)?;
+        self.watchdog.track("rename", from, None, None, || self.inner.rename(from, to))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.watchdog.check_writable(path)?;
+        self.watchdog.track("remove", path, None, None, || self.inner.remove(path))
+    }
+
+    // Allowed while degraded, since opening a store creates its directory.
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.watchdog.track("create_dir_all", path, None, None, || self.inner.create_dir_all(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        self.watchdog.check_writable(path)?;
+        self.watchdog.track("remove_dir_all", path, None, None, || self.inner.remove_dir_all(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.watchdog.track("read_dir", path, None, None, || self.inner.read_dir(path))
+    }
+
+    fn available_space(&self, path: &Path) -> Result<Option<u64>> {
+        self.watchdog.track("available_space", path, None, None, || self.inner.available_space(path))
+    }
+}
+
+impl File for WatchedFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let len = Some(buf.len() as u64);
+        self.watchdog.track("read", &self.path, Some(offset), len, || self.inner.read_at(buf, offset))
+    }
+
+    fn append(&self, data: &[u8]) -> Result<u64> {
+        self.watchdog.check_writable(&self.path)?;
+        let len = Some(data.len() as u64);
+        self.watchdog.track("append", &self.path, None, len, || self.inner.append(data))
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.watchdog.track("size", &self.path, None, None, || self.inner.size())
+    }
+
+    fn set_len(&self, len: u64) -> Result<()> {
+        self.watchdog.check_writable(&self.path)?;
+        self.watchdog.track("set_len", &self.path, Some(len), None, || self.inner.set_len(len))
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.watchdog.check_writable(&self.path)?;
+        self.watchdog.track("sync", &self.path, None, None, || self.inner.sync())
+    }
+
+    fn lock(&self) -> Result<()> {
+        self.inner.lock()
+    }
+
+    fn lock_shared(&self) -> Result<()> {
+        self.inner.lock_shared()
+    }
+
+    fn readahead(&self, offset: u64, len: u64) -> Result<()> {
+        self.inner.readahead(offset, len)
+    }
+
+    fn preallocate(&self, len: u64) -> Result<()> {
+        self.watchdog.check_writable(&self.path)?;
+        self.watchdog.track("preallocate", &self.path, None, Some(len), || self.inner.preallocate(len))
+    }
+
+    fn punch_hole(&self, offset: u64, len: u64) -> Result<()> {
+        self.watchdog.check_writable(&self.path)?;
+        let track = || self.inner.punch_hole(offset, len);
+        self.watchdog.track("punch_hole", &self.path, Some(offset), Some(len), track)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::error::Error as DbError;
+    use crate::storage::bitcask::{BitCask, Options};
+    use crate::storage::vfs::MemFs;
+    use crate::storage::Engine;
+
+    #[test]
+    fn test_watchdog() -> crate::error::Result<()> {
+        let clock = MockClock::new(Duration::from_secs(1000));
+        let limit = Duration::from_secs(30);
+        let watchdog = Watchdog::new(Arc::new(clock.clone()), limit, true);
+        let options = Options { vfs: watchdog.wrap(Arc::new(MemFs::new())), ..Default::default() };
+        let path = PathBuf::from("/db/log");
+        let mut s = BitCask::new_with_options(path.clone(), options)?;
+        s.set(b"a", vec![0x01])?;
+        assert!(watchdog.check().is_empty());
+
+        // Simulate a sync that hangs until the watchdog has checked twice.
+        let stuck = watchdog.track("sync", &path, None, None, || {
+            clock.advance(limit - Duration::from_secs(1));
+            assert!(watchdog.check().is_empty());
+            clock.advance(Duration::from_secs(1));
+            Ok(watchdog.check())
+        })?;
+        let expect = PendingOp { op: "sync", path, offset: None, len: None, started: Duration::from_secs(1000) };
+        assert_eq!(vec![expect], stuck);
+        assert!(watchdog.check().is_empty());
+
+        // Reads are still served in degraded mode, writes are refused.
+        assert!(watchdog.is_degraded());
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert!(matches!(s.set(b"b", vec![0x02]), Err(DbError::Degraded(_))));
+        watchdog.clear_degraded();
+        s.set(b"b", vec![0x02])?;
+
+        let thread = watchdog.start(Duration::from_millis(1));
+        drop(thread);
+        Ok(())
+    }
+}
@@ -0,0 +1,448 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::encoding::keycode::{self, KeyDecode, KeyEncode};
+use crate::error::{Error, Result};
+use crate::storage::Engine;
+
+// An experimental approximate nearest-neighbor index over float vectors,
+// stored in an engine as an HNSW graph (Malkov and Yashunin, 2016). Each
+// vector is assigned a random level, and is linked to its nearest neighbors
+// on every layer up to it, with higher layers thinning out exponentially.
+// Searches descend greedily from the entry point through the upper layers,
+// then run a best-first search of the bottom layer.
+//
+// Vectors are stored under ("vector", id) as their level and components,
+// neighbor lists under ("edges", id, layer), and the entry point and
+// dimensions under "entry". An insert and all the neighbor lists it changes
+// are written in one batch. Nodes are read from the engine as searches reach
+// them, so searching a large index on disk is bounded by the engine's reads.
+//
+// Vectors can't be replaced or deleted yet, since unlinking a node means
+// repairing its neighbors' neighborhoods.
+pub struct Index<E: Engine> {
+    engine: E,
+    options: Options,
+    rng: StdRng,
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub metric: Metric,
+    // Neighbors linked per node and layer, twice this on the bottom layer.
+    pub m: usize,
+    // Candidates considered when linking an inserted vector.
+    pub ef_construction: usize,
+    // Candidates considered when searching, at least the number of results.
+    pub ef_search: usize,
+    // Seeds the level assignment, for reproducible graphs.
+    pub seed: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { metric: Metric::Cosine, m: 16, ef_construction: 100, ef_search: 50, seed: None }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    // One minus the cosine similarity.
+    Cosine,
+    // The squared Euclidean distance.
+    Euclidean,
+}
+
+impl Metric {
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Self::Cosine => {
+                let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+                for (a, b) in a.iter().zip(b) {
+                    dot += a * b;
+                    norm_a += a * a;
+                    norm_b += b * b;
+                }
+                match norm_a * norm_b {
+                    norm if norm > 0.0 => 1.0 - dot / norm.sqrt(),
+                    _ => 1.0,
+                }
+            }
+            Self::Euclidean => a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum(),
+        }
+    }
+}
+
+// Levels are capped, since higher ones are vanishingly rare anyway.
+const MAX_LEVEL: u32 = 16;
+
+fn vector_key(id: &[u8]) -> Vec<u8> {
+    keycode::encode(&("vector", id))
+}
+
+fn edges_key(id: &[u8], layer: u32) -> Vec<u8> {
+    keycode::encode(&("edges", id, layer))
+}
+
+const ENTRY_KEY: &[u8] = b"entry";
+
+// A vector is its level as a big-endian u32 followed by its components as
+// big-endian f32s.
+fn encode_vector(level: u32, vector: &[f32]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + vector.len() * 4);
+    out.extend_from_slice(&level.to_be_bytes());
+    for component in vector {
+        out.extend_from_slice(&component.to_be_bytes());
+    }
+    out
+}
+
+fn decode_vector(bytes: &[u8]) -> Result<(u32, Vec<f32>)> {
+    let Some((level, components)) = bytes.split_first_chunk::<4>() else {
+        return Err(Error::Corruption("vector is missing its level".to_string()));
+    };
+    if components.len() % 4 != 0 {
+        return Err(Error::Corruption(format!("vector has {} trailing bytes", components.len() % 4)));
+    }
+    let vector = components.chunks_exact(4).map(|c| f32::from_be_bytes([c[0], c[1], c[2], c[3]])).collect();
+    Ok((u32::from_be_bytes(*level), vector))
+}
+
+// A neighbor list is its length followed by the neighbor IDs.
+fn encode_edges(neighbors: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    (neighbors.len() as u32).encode_into(&mut out);
+    for id in neighbors {
+        id.encode_into(&mut out);
+    }
+    out
+}
+
+fn decode_edges(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut input = bytes;
+    let count = u32::decode_from(&mut input)?;
+    let neighbors = (0..count).map(|_| Vec::<u8>::decode_from(&mut input)).collect::<std::result::Result<_, keycode::Error>>()?;
+    if !input.is_empty() {
+        return Err(Error::Corruption(format!("neighbor list has {} trailing bytes", input.len())));
+    }
+    Ok(neighbors)
+}
+
+// The entry point is the node with the highest level.
+struct Entry {
+    id: Vec<u8>,
+    level: u32,
+    dimensions: u32,
+}
+
+impl Entry {
+    fn encode(&self) -> Vec<u8> {
+        keycode::encode(&(self.id.as_slice(), self.level, self.dimensions))
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let (id, level, dimensions) = keycode::decode::<(Vec<u8>, u32, u32)>(bytes)?;
+        Ok(Self { id, level, dimensions })
+    }
+}
+
+// A node reached by a search, ordered by distance and then ID.
+#[derive(Clone, Debug)]
+struct Candidate {
+    distance: f32,
+    id: Vec<u8>,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance).then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+// A view of the graph for one insert or search, caching the nodes read from
+// the engine and buffering writes until commit.
+struct Graph<'a, E: Engine> {
+    engine: &'a mut E,
+    metric: Metric,
+    vectors: HashMap<Vec<u8>, Vec<f32>>,
+    edges: HashMap<(Vec<u8>, u32), Vec<Vec<u8>>>,
+    writes: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl<'a, E: Engine> Graph<'a, E> {
+    fn new(engine: &'a mut E, metric: Metric) -> Self {
+        Self { engine, metric, vectors: HashMap::new(), edges: HashMap::new(), writes: BTreeMap::new() }
+    }
+
+    fn entry(&mut self) -> Result<Option<Entry>> {
+        self.engine.get(ENTRY_KEY)?.map(|bytes| Entry::decode(&bytes)).transpose()
+    }
+
+    fn set_entry(&mut self, entry: &Entry) {
+        self.writes.insert(ENTRY_KEY.to_vec(), entry.encode());
+    }
+
+    fn add_vector(&mut self, id: &[u8], level: u32, vector: Vec<f32>) {
+        self.writes.insert(vector_key(id), encode_vector(level, &vector));
+        self.vectors.insert(id.to_vec(), vector);
+    }
+
+    fn vector(&mut self, id: &[u8]) -> Result<&[f32]> {
+        if !self.vectors.contains_key(id) {
+            let Some(bytes) = self.engine.get(&vector_key(id))? else {
+                return Err(Error::Corruption(format!("node {:?} has no vector", id)));
+            };
+            self.vectors.insert(id.to_vec(), decode_vector(&bytes)?.1);
+        }
+        Ok(&self.vectors[id])
+    }
+
+    // Returns the distance from the query to a stored vector.
+    fn distance(&mut self, query: &[f32], id: &[u8]) -> Result<f32> {
+        let metric = self.metric;
+        Ok(metric.distance(query, self.vector(id)?))
+    }
+
+    fn neighbors(&mut self, id: &[u8], layer: u32) -> Result<Vec<Vec<u8>>> {
+        let key = (id.to_vec(), layer);
+        if let Some(neighbors) = self.edges.get(&key) {
+            return Ok(neighbors.clone());
+        }
+        let neighbors = match self.engine.get(&edges_key(id, layer))? {
+            Some(bytes) => decode_edges(&bytes)?,
+            None => Vec::new(),
+        };
+        self.edges.insert(key, neighbors.clone());
+        Ok(neighbors)
+    }
+
+    fn set_neighbors(&mut self, id: &[u8], layer: u32, neighbors: Vec<Vec<u8>>) {
+        self.writes.insert(edges_key(id, layer), encode_edges(&neighbors));
+        self.edges.insert((id.to_vec(), layer), neighbors);
+    }
+
+    // Searches a layer best-first from the entry points, returning the ef
+    // nearest nodes found, nearest first.
+    fn search_layer(&mut self, query: &[f32], entry: Vec<Candidate>, ef: usize, layer: u32) -> Result<Vec<Candidate>> {
+        let mut visited: HashSet<Vec<u8>> = entry.iter().map(|c| c.id.clone()).collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = entry.iter().cloned().map(Reverse).collect();
+        let mut nearest: BinaryHeap<Candidate> = entry.into_iter().collect();
+        while nearest.len() > ef {
+            nearest.pop();
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            if nearest.len() >= ef && nearest.peek().is_some_and(|far| candidate.distance > far.distance) {
+                break;
+            }
+            for id in self.neighbors(&candidate.id, layer)? {
+                if !visited.insert(id.clone()) {
+                    continue;
+                }
+                let distance = self.distance(query, &id)?;
+                if nearest.len() < ef || nearest.peek().is_some_and(|far| distance < far.distance) {
+                    let neighbor = Candidate { distance, id };
+                    candidates.push(Reverse(neighbor.clone()));
+                    nearest.push(neighbor);
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+        Ok(nearest.into_sorted_vec())
+    }
+
+    // Links a node to a new neighbor, dropping its farthest neighbors beyond
+    // the limit.
+    fn link(&mut self, id: &[u8], neighbor: &[u8], layer: u32, limit: usize) -> Result<()> {
+        let mut neighbors = self.neighbors(id, layer)?;
+        neighbors.push(neighbor.to_vec());
+        if neighbors.len() > limit {
+            let vector = self.vector(id)?.to_vec();
+            let mut candidates = neighbors
+                .into_iter()
+                .map(|id| Ok(Candidate { distance: self.distance(&vector, &id)?, id }))
+                .collect::<Result<Vec<_>>>()?;
+            candidates.sort();
+            neighbors = candidates.into_iter().take(limit).map(|c| c.id).collect();
+        }
+        self.set_neighbors(id, layer, neighbors);
+        Ok(())
+    }
+
+    fn commit(self) -> Result<()> {
+        self.engine.write_batch(self.writes.into_iter().map(|(key, value)| (key, Some(value))).collect())
+    }
+}
+
+impl<E: Engine> Index<E> {
+    pub fn new(engine: E, options: Options) -> Self {
+        let rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self { engine, options, rng }
+    }
+
+    pub fn get(&mut self, id: &[u8]) -> Result<Option<Vec<f32>>> {
+        self.engine.get(&vector_key(id))?.map(|bytes| Ok(decode_vector(&bytes)?.1)).transpose()
+    }
+
+    // Stores the vector and links it into the graph. All vectors must have
+    // the dimensions of the first one, and IDs can't be reused.
+    pub fn insert(&mut self, id: &[u8], vector: Vec<f32>) -> Result<()> {
+        if vector.is_empty() || vector.iter().any(|c| !c.is_finite()) {
+            return Err(Error::Value("vector must be non-empty and finite".to_string()));
+        }
+        if self.engine.get(&vector_key(id))?.is_some() {
+            return Err(Error::Value(format!("vector {:?} already exists", id)));
+        }
+        let level = self.random_level();
+        let (m, ef_construction) = (self.options.m.max(1), self.options.ef_construction.max(1));
+        let mut graph = Graph::new(&mut self.engine, self.options.metric);
+
+        let Some(entry) = graph.entry()? else {
+            graph.set_entry(&Entry { id: id.to_vec(), level, dimensions: vector.len() as u32 });
+            graph.add_vector(id, level, vector);
+            return graph.commit();
+        };
+        if vector.len() != entry.dimensions as usize {
+            return Err(Error::Value(format!(
+                "vector has {} dimensions, index has {}",
+                vector.len(),
+                entry.dimensions
+            )));
+        }
+
+        let mut nearest = vec![Candidate { distance: graph.distance(&vector, &entry.id)?, id: entry.id.clone() }];
+        graph.add_vector(id, level, vector.clone());
+        for layer in (level + 1..=entry.level).rev() {
+            nearest = graph.search_layer(&vector, nearest, 1, layer)?;
+        }
+        for layer in (0..=level.min(entry.level)).rev() {
+            nearest = graph.search_layer(&vector, nearest, ef_construction, layer)?;
+            let neighbors: Vec<_> = nearest.iter().take(m).map(|c| c.id.clone()).collect();
+            let limit = if layer == 0 { 2 * m } else { m };
+            for neighbor in &neighbors {
+                graph.link(neighbor, id, layer, limit)?;
+            }
+            graph.set_neighbors(id, layer, neighbors);
+        }
+        if level > entry.level {
+            graph.set_entry(&Entry { id: id.to_vec(), level, dimensions: entry.dimensions });
+        }
+        graph.commit()
+    }
+
+    // Returns the IDs of up to k vectors nearest the query and their
+    // distances, nearest first. Results are approximate: raising ef_search
+    // trades speed for recall.
+    pub fn search_similar(&mut self, query: &[f32], k: usize) -> Result<Vec<(Vec<u8>, f32)>> {
+        let mut graph = Graph::new(&mut self.engine, self.options.metric);
+        let Some(entry) = graph.entry()? else {
+            return Ok(Vec::new());
+        };
+        if query.len() != entry.dimensions as usize {
+            return Err(Error::Value(format!(
+                "query has {} dimensions, index has {}",
+                query.len(),
+                entry.dimensions
+            )));
+        }
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut nearest = vec![Candidate { distance: graph.distance(query, &entry.id)?, id: entry.id }];
+        for layer in (1..=entry.level).rev() {
+            nearest = graph.search_layer(query, nearest, 1, layer)?;
+        }
+        nearest = graph.search_layer(query, nearest, self.options.ef_search.max(k), 0)?;
+        Ok(nearest.into_iter().take(k).map(|c| (c.id, c.distance)).collect())
+    }
+
+    // Draws a level with the geometric distribution of the paper, so each
+    // layer has about 1/m of the nodes of the one below.
+    fn random_level(&mut self) -> u32 {
+        let scale = 1.0 / (self.options.m.max(2) as f64).ln();
+        let uniform: f64 = self.rng.gen();
+        ((-(1.0 - uniform).ln() * scale).floor() as u32).min(MAX_LEVEL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::bitcask::{self, BitCask};
+    use crate::storage::memory::Memory;
+    use crate::storage::vfs::MemFs;
+
+    #[test]
+    fn test_search_similar() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let vectors: Vec<Vec<f32>> = (0..300).map(|_| (0..8).map(|_| rng.gen_range(-1.0..1.0)).collect()).collect();
+        let id = |i: usize| format!("v{:03}", i).into_bytes();
+
+        let options = Options { metric: Metric::Euclidean, m: 8, seed: Some(1), ..Default::default() };
+        let mut index = Index::new(Memory::new(), options.clone());
+        assert_eq!(Vec::<(Vec<u8>, f32)>::new(), index.search_similar(&vectors[0], 3)?);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.insert(&id(i), vector.clone())?;
+        }
+        assert_eq!(Some(vectors[5].clone()), index.get(&id(5))?);
+        assert!(matches!(index.insert(&id(5), vectors[5].clone()), Err(Error::Value(_))));
+        assert!(matches!(index.insert(b"short", vec![0.0; 4]), Err(Error::Value(_))));
+        assert!(matches!(index.search_similar(&[0.0; 4], 3), Err(Error::Value(_))));
+
+        // Nearly all of the true 10 nearest neighbors are found.
+        let (mut found, mut total) = (0, 0);
+        for _ in 0..20 {
+            let query: Vec<f32> = (0..8).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            let mut exact: Vec<_> =
+                (0..vectors.len()).map(|i| (Metric::Euclidean.distance(&query, &vectors[i]), id(i))).collect();
+            exact.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let results = index.search_similar(&query, 10)?;
+            assert_eq!(10, results.len());
+            assert!(results.windows(2).all(|w| w[0].1 <= w[1].1));
+            found += exact.iter().take(10).filter(|(_, id)| results.iter().any(|(r, _)| r == id)).count();
+            total += 10;
+        }
+        assert!(found * 100 >= total * 95, "recall {}/{}", found, total);
+
+        // The graph persists in the engine: a vector's nearest neighbor is
+        // itself.
+        let vfs = Arc::new(MemFs::new());
+        let path = PathBuf::from("/db/vectors");
+        let open = || BitCask::new_with_options(path.clone(), bitcask::Options { vfs: vfs.clone(), ..Default::default() });
+        let mut index = Index::new(open()?, options.clone());
+        for (i, vector) in vectors.iter().take(50).enumerate() {
+            index.insert(&id(i), vector.clone())?;
+        }
+        drop(index);
+        let mut index = Index::new(open()?, options);
+        assert_eq!(id(42), index.search_similar(&vectors[42], 1)?[0].0);
+        assert_eq!(0.0, Metric::Cosine.distance(&[1.0, 2.0], &[2.0, 4.0]));
+        Ok(())
+    }
+}